@@ -1,9 +1,9 @@
 import { ACCOUNT_SIZE, AccountLayout, ASSOCIATED_TOKEN_PROGRAM_ID, createAssociatedTokenAccountInstruction, createInitializeAccountInstruction, createInitializeMintInstruction, createMintToInstruction, getAccount, getAccountLen, getAssociatedTokenAddressSync, getMinimumBalanceForRentExemptMint, getMintLen, initializeMintInstructionData, MINT_SIZE, mintToInstructionData, TOKEN_PROGRAM_ID } from "@solana/spl-token";
 import { AccountMeta, Keypair, LAMPORTS_PER_SOL, PublicKey, SystemProgram, SYSVAR_RENT_PUBKEY, Transaction, TransactionInstruction } from "@solana/web3.js";
 import { FailedTransactionMetadata, LiteSVM, TransactionMetadata } from "litesvm";
-import { CancelOrderSchema, ConsumeEventsSchema, CreateOrderSchema, EventType, MarketEventsAccount, MarketEventsAccountSchema, MarketState, MarketStateSchema, OpenOrderAccount, OpenOrderAccountSchema, OrderBook, OrderBookSchema, Side, UserMarketAccount, UserMarketAccountSchema } from "./schema";
+import { AmendOrderSchema, CancelAllOrdersSchema, CancelAndReplaceSchema, CancelOrderByClientIdSchema, CancelOrderSchema, CancelTriggerOrderSchema, ConsumeEventsSchema, CreateOrderSchema, CreateOrdersSchema, CreateTriggerOrderSchema, EventType, ExecuteTriggerOrdersSchema, InitializeMarketSchema, MarketAccountKind, MarketEventsAccount, MarketEventsAccountSchema, MarketState, MarketStateSchema, MatchLimitBehavior, OpenOrderAccount, OrderBookError, OpenOrderAccountSchema, OrderBook, OrderBookSchema, OrderType, PruneExpiredOrdersSchema, ReallocMarketAccountSchema, SelfTradeBehavior, Side, TriggerOrderBook, TriggerOrderBookSchema, UserMarketAccount, UserMarketAccountSchema } from "./schema";
 import * as borsh from "borsh";
-import { createSideEncodedOrderId, EVENT_ACCOUNT_LEN, MAX_DRAIN_COUNT, ORDERBOOK_LEN } from "./utils";
+import { createSideEncodedOrderId, customErrorCode, EVENT_ACCOUNT_LEN, eventsAccountLen, MAX_DRAIN_COUNT, MAX_EVENT, MAX_ORDERS, ORDERBOOK_LEN, orderBookAccountLen, TRIGGER_ORDERS_LEN } from "./utils";
 
 
 describe("Orderbook tests", () => {
@@ -18,6 +18,9 @@ describe("Orderbook tests", () => {
     let pcMint: Keypair;
     let bids: Keypair;
     let asks: Keypair;
+    let triggerOrders: Keypair;
+    let oracle: PublicKey;
+    let feeDestination: PublicKey;
     let user: Keypair;
     let user2: Keypair;
     let user3: Keypair;
@@ -162,6 +165,49 @@ describe("Orderbook tests", () => {
         bidsAsksCreationTx.sign(accountsAuthority, bids, asks);
         svm.sendTransaction(bidsAsksCreationTx);
 
+        //creating trigger orders account off chain as well
+        triggerOrders = new Keypair();
+        let triggerOrdersCreationTx = new Transaction().add(
+            SystemProgram.createAccount({
+                fromPubkey: accountsAuthority.publicKey,
+                newAccountPubkey: triggerOrders.publicKey,
+                lamports: Number(svm.minimumBalanceForRentExemption(BigInt(TRIGGER_ORDERS_LEN))),
+                space: TRIGGER_ORDERS_LEN,
+                programId: programId
+            })
+        );
+        triggerOrdersCreationTx.recentBlockhash = svm.latestBlockhash();
+        triggerOrdersCreationTx.feePayer = accountsAuthority.publicKey;
+        triggerOrdersCreationTx.sign(accountsAuthority, triggerOrders);
+        svm.sendTransaction(triggerOrdersCreationTx);
+
+        //authority's pc token account receiving swept fees
+        feeDestination = getAssociatedTokenAddressSync(
+            pcMint.publicKey,
+            accountsAuthority.publicKey,
+            false,
+            TOKEN_PROGRAM_ID,
+            ASSOCIATED_TOKEN_PROGRAM_ID
+        );
+        let feeDestinationTx = new Transaction().add(
+            createAssociatedTokenAccountInstruction(
+                accountsAuthority.publicKey,
+                feeDestination,
+                accountsAuthority.publicKey,
+                pcMint.publicKey,
+                TOKEN_PROGRAM_ID,
+                ASSOCIATED_TOKEN_PROGRAM_ID
+            )
+        );
+        feeDestinationTx.recentBlockhash = svm.latestBlockhash();
+        feeDestinationTx.feePayer = accountsAuthority.publicKey;
+        feeDestinationTx.sign(accountsAuthority);
+        svm.sendTransaction(feeDestinationTx);
+
+        //mock oracle price account, tests write its price directly
+        oracle = PublicKey.unique();
+        setOraclePrice(BigInt(100));
+
         //creating user account and its ata
         user = new Keypair();
         svm.airdrop(user.publicKey, BigInt(100 * LAMPORTS_PER_SOL));
@@ -227,12 +273,23 @@ describe("Orderbook tests", () => {
                 {pubkey: pcVault, isSigner: false, isWritable: true},
                 {pubkey: bids.publicKey, isSigner: false, isWritable: true},
                 {pubkey: asks.publicKey, isSigner: false, isWritable: true},
+                {pubkey: triggerOrders.publicKey, isSigner: false, isWritable: true},
+                {pubkey: oracle, isSigner: false, isWritable: false},
+                {pubkey: feeDestination, isSigner: false, isWritable: false},
                 {pubkey: SystemProgram.programId, isSigner: false, isWritable: false},
                 {pubkey: TOKEN_PROGRAM_ID, isSigner: false, isWritable: false},
                 {pubkey: SYSVAR_RENT_PUBKEY, isSigner: false, isWritable: false},
             ],
             programId: programId,
-            data: Buffer.from([0])
+            data: Buffer.concat([Buffer.from([0]), Buffer.from(borsh.serialize(InitializeMarketSchema, {
+                tick_size: BigInt(5),
+                base_lot_size: BigInt(1),
+                quote_lot_size: BigInt(1),
+                min_order_size: BigInt(1),
+                maker_fee_bps: -5,
+                taker_fee_bps: 10,
+                referrer_fee_share_bps: 5000
+            }))])
         });
 
         let tx = new Transaction().add(ix);
@@ -252,6 +309,15 @@ describe("Orderbook tests", () => {
         const marketData = new MarketState(borsh.deserialize(MarketStateSchema, marketInfo!.data));
         // console.log(marketData);
         expect(marketData.next_order_id).toBe(BigInt(1));
+        expect(new PublicKey(marketData.trigger_orders)).toStrictEqual(triggerOrders.publicKey);
+        expect(new PublicKey(marketData.oracle)).toStrictEqual(oracle);
+        expect(marketData.last_traded_price).toBe(BigInt(0));
+        expect(marketData.tick_size).toBe(BigInt(5));
+        expect(marketData.min_order_size).toBe(BigInt(1));
+        expect(marketData.maker_fee_bps).toBe(-5);
+        expect(marketData.taker_fee_bps).toBe(10);
+        expect(marketData.referrer_fee_share_bps).toBe(5000);
+        expect(new PublicKey(marketData.fee_destination)).toStrictEqual(feeDestination);
         
         try {
             // bids checks
@@ -259,6 +325,7 @@ describe("Orderbook tests", () => {
             //@ts-ignore
             const bidsData = new OrderBook(borsh.deserialize(OrderBookSchema, bidsInfo!.data));
             expect(bidsData.side).toBe(Side.Bid);
+            expect(bidsData.capacity).toBe(MAX_ORDERS);
             expect(bidsData.slots_filled).toBe(0);
             expect(bidsData.next_order_id).toBe(BigInt(0));
             expect(new PublicKey(bidsData.market)).toStrictEqual(market);
@@ -269,6 +336,7 @@ describe("Orderbook tests", () => {
             //@ts-ignore
             const asksData = new OrderBook(borsh.deserialize(OrderBookSchema, asksInfo!.data));
             expect(asksData.side).toBe(Side.Ask);
+            expect(asksData.capacity).toBe(MAX_ORDERS);
             expect(asksData.slots_filled).toBe(0);
             expect(asksData.next_order_id).toBe(BigInt(0));
             expect(new PublicKey(asksData.market)).toStrictEqual(market);
@@ -280,12 +348,153 @@ describe("Orderbook tests", () => {
             expect(new PublicKey(eventAccData.market)).toStrictEqual(market);
             expect(eventAccData.head).toBe(0);
             expect(eventAccData.tail).toBe(0);
+            expect(eventAccData.capacity).toBe(MAX_EVENT);
+
+            //trigger orders account checks
+            let triggerOrdersInfo = svm.getAccount(triggerOrders.publicKey);
+            //@ts-ignore
+            const triggerOrdersData = new TriggerOrderBook(borsh.deserialize(TriggerOrderBookSchema, triggerOrdersInfo!.data));
+            expect(new PublicKey(triggerOrdersData.market)).toStrictEqual(market);
+            expect(triggerOrdersData.slots_filled).toBe(0);
 
         } catch (e) {
             console.log(e);
         }
     });
 
+    test("A market sizes its book and event queue by its accounts", async () => {
+        const createMint = () => {
+            const mint = new Keypair();
+            const tx = new Transaction().add(
+                SystemProgram.createAccount({
+                    fromPubkey: accountsAuthority.publicKey,
+                    newAccountPubkey: mint.publicKey,
+                    lamports: Number(svm.minimumBalanceForRentExemption(BigInt(MINT_SIZE))),
+                    space: MINT_SIZE,
+                    programId: TOKEN_PROGRAM_ID
+                }),
+                createInitializeMintInstruction(mint.publicKey, 9, accountsAuthority.publicKey, null, TOKEN_PROGRAM_ID)
+            );
+            tx.feePayer = accountsAuthority.publicKey;
+            tx.recentBlockhash = svm.latestBlockhash();
+            tx.sign(accountsAuthority, mint);
+            expect(svm.sendTransaction(tx)).toBeInstanceOf(TransactionMetadata);
+            return mint;
+        };
+        const createProgramAccount = (space: number) => {
+            const account = new Keypair();
+            const tx = new Transaction().add(
+                SystemProgram.createAccount({
+                    fromPubkey: accountsAuthority.publicKey,
+                    newAccountPubkey: account.publicKey,
+                    lamports: Number(svm.minimumBalanceForRentExemption(BigInt(space))),
+                    space,
+                    programId
+                })
+            );
+            tx.feePayer = accountsAuthority.publicKey;
+            tx.recentBlockhash = svm.latestBlockhash();
+            tx.sign(accountsAuthority, account);
+            expect(svm.sendTransaction(tx)).toBeInstanceOf(TransactionMetadata);
+            return account;
+        };
+
+        //a long tail market with room for 2 resting orders per side and 8 events
+        const smallCoinMint = createMint();
+        const smallPcMint = createMint();
+        const smallMarket = PublicKey.findProgramAddressSync([Buffer.from("market"), smallPcMint.publicKey.toBuffer(), smallCoinMint.publicKey.toBuffer()], programId)[0];
+        const smallCoinVault = PublicKey.findProgramAddressSync([Buffer.from("coin_vault"), smallMarket.toBuffer()], programId)[0];
+        const smallPcVault = PublicKey.findProgramAddressSync([Buffer.from("pc_vault"), smallMarket.toBuffer()], programId)[0];
+        const smallBids = createProgramAccount(orderBookAccountLen(2));
+        const smallAsks = createProgramAccount(orderBookAccountLen(2));
+        const smallTriggerOrders = createProgramAccount(TRIGGER_ORDERS_LEN);
+        const smallFeeDestination = getAssociatedTokenAddressSync(smallPcMint.publicKey, accountsAuthority.publicKey, false, TOKEN_PROGRAM_ID, ASSOCIATED_TOKEN_PROGRAM_ID);
+        {
+            const tx = new Transaction().add(
+                createAssociatedTokenAccountInstruction(accountsAuthority.publicKey, smallFeeDestination, accountsAuthority.publicKey, smallPcMint.publicKey, TOKEN_PROGRAM_ID, ASSOCIATED_TOKEN_PROGRAM_ID)
+            );
+            tx.feePayer = accountsAuthority.publicKey;
+            tx.recentBlockhash = svm.latestBlockhash();
+            tx.sign(accountsAuthority);
+            expect(svm.sendTransaction(tx)).toBeInstanceOf(TransactionMetadata);
+        }
+
+        const initializeSmallMarket = (events: PublicKey, triggerOrders: PublicKey = smallTriggerOrders.publicKey) => {
+            const ix = new TransactionInstruction({
+                keys: [
+                    {pubkey: accountsAuthority.publicKey, isSigner: true, isWritable: true},
+                    {pubkey: smallMarket, isSigner: false, isWritable: true},
+                    {pubkey: events, isSigner: false, isWritable: true},
+                    {pubkey: smallCoinMint.publicKey, isSigner: false, isWritable: true},
+                    {pubkey: smallPcMint.publicKey, isSigner: false, isWritable: true},
+                    {pubkey: smallCoinVault, isSigner: false, isWritable: true},
+                    {pubkey: smallPcVault, isSigner: false, isWritable: true},
+                    {pubkey: smallBids.publicKey, isSigner: false, isWritable: true},
+                    {pubkey: smallAsks.publicKey, isSigner: false, isWritable: true},
+                    {pubkey: triggerOrders, isSigner: false, isWritable: true},
+                    {pubkey: oracle, isSigner: false, isWritable: false},
+                    {pubkey: smallFeeDestination, isSigner: false, isWritable: false},
+                    {pubkey: SystemProgram.programId, isSigner: false, isWritable: false},
+                    {pubkey: TOKEN_PROGRAM_ID, isSigner: false, isWritable: false},
+                    {pubkey: SYSVAR_RENT_PUBKEY, isSigner: false, isWritable: false},
+                ],
+                programId: programId,
+                data: Buffer.concat([Buffer.from([0]), Buffer.from(borsh.serialize(InitializeMarketSchema, {
+                    tick_size: BigInt(5),
+                    base_lot_size: BigInt(1),
+                    quote_lot_size: BigInt(1),
+                    min_order_size: BigInt(1),
+                    maker_fee_bps: -5,
+                    taker_fee_bps: 10,
+                    referrer_fee_share_bps: 5000
+                }))])
+            });
+            const tx = new Transaction().add(ix);
+            tx.recentBlockhash = svm.latestBlockhash();
+            tx.feePayer = accountsAuthority.publicKey;
+            tx.sign(accountsAuthority);
+            return svm.sendTransaction(tx);
+        };
+
+        //an event queue needs at least 2 slots since one always stays empty
+        {
+            const tinyEvents = createProgramAccount(eventsAccountLen(1));
+            const sig = initializeSmallMarket(tinyEvents.publicKey);
+            expect(sig).toBeInstanceOf(FailedTransactionMetadata);
+        }
+
+        const smallEvents = createProgramAccount(eventsAccountLen(8));
+
+        //a trigger orders account of the wrong size is rejected instead of crashing the program
+        {
+            const shortTriggerOrders = createProgramAccount(TRIGGER_ORDERS_LEN - 1);
+            const sig = initializeSmallMarket(smallEvents.publicKey, shortTriggerOrders.publicKey);
+            expect(sig).toBeInstanceOf(FailedTransactionMetadata);
+            expect((sig as FailedTransactionMetadata).meta().logs().some((log) => log.includes("Trigger orders account has"))).toBe(true);
+        }
+
+        const sig = initializeSmallMarket(smallEvents.publicKey);
+        expect(sig).toBeInstanceOf(TransactionMetadata);
+
+        //@ts-ignore
+        const bidsData = new OrderBook(borsh.deserialize(OrderBookSchema, svm.getAccount(smallBids.publicKey)!.data));
+        expect(bidsData.side).toBe(Side.Bid);
+        expect(bidsData.capacity).toBe(2);
+        expect(new PublicKey(bidsData.market)).toStrictEqual(smallMarket);
+
+        //@ts-ignore
+        const asksData = new OrderBook(borsh.deserialize(OrderBookSchema, svm.getAccount(smallAsks.publicKey)!.data));
+        expect(asksData.side).toBe(Side.Ask);
+        expect(asksData.capacity).toBe(2);
+
+        //@ts-ignore
+        const eventsData = new MarketEventsAccount(borsh.deserialize(MarketEventsAccountSchema, svm.getAccount(smallEvents.publicKey)!.data));
+        expect(eventsData.capacity).toBe(8);
+        expect(new PublicKey(eventsData.market)).toStrictEqual(smallMarket);
+
+        expect(svm.getAccount(smallBids.publicKey)!.data.length).toBeLessThan(ORDERBOOK_LEN / 100);
+    });
+
     test("Place Bid, Sell Order", async () => {
         //Buy Order 
         //Qty: 5
@@ -293,12 +502,23 @@ describe("Orderbook tests", () => {
         {   
             test2BidLimitPrice = 100;
             test2BidCoinQty = 5;
-            test2BidPcQty = 520;
+            test2BidPcQty = test2BidLimitPrice * test2BidCoinQty;    //locked by the program, no maker fee to cover
             let args = {
                 side: 0,
                 limit_price: BigInt(test2BidLimitPrice),
                 coin_qty: BigInt(test2BidCoinQty),
-                pc_qty: BigInt(test2BidPcQty)
+                order_type: OrderType.Limit,
+                pc_qty_limit: BigInt(0),
+                self_trade_behavior: SelfTradeBehavior.CancelMaker,
+                client_order_id: BigInt(0),
+                expiry_timestamp: null,
+
+                display_quantity: BigInt(0),
+
+                peg_offset: null,
+                referrer: null,
+                match_limit: null,
+                match_limit_behavior: MatchLimitBehavior.FailOrder
             }
             
             createAndSendPlaceOrderIx(user, userMarketAccount, openOrderAccount, userPcAta, pcVault, args);
@@ -389,7 +609,18 @@ describe("Orderbook tests", () => {
                 side: 1,
                 limit_price: BigInt(test2AskLimitPrice),
                 coin_qty: BigInt(test2AskCoinQty),
-                pc_qty: BigInt(test2AskPcQty)
+                order_type: OrderType.Limit,
+                pc_qty_limit: BigInt(0),
+                self_trade_behavior: SelfTradeBehavior.CancelMaker,
+                client_order_id: BigInt(0),
+                expiry_timestamp: null,
+
+                display_quantity: BigInt(0),
+
+                peg_offset: null,
+                referrer: null,
+                match_limit: null,
+                match_limit_behavior: MatchLimitBehavior.FailOrder
             }
             
             createAndSendPlaceOrderIx(user, userMarketAccount, openOrderAccount, userCoinAta, coinVault, args);
@@ -578,7 +809,18 @@ describe("Orderbook tests", () => {
                 side: 0,
                 limit_price: BigInt(220),
                 coin_qty: BigInt(3),
-                pc_qty: BigInt(660)
+                order_type: OrderType.Limit,
+                pc_qty_limit: BigInt(0),
+                self_trade_behavior: SelfTradeBehavior.CancelMaker,
+                client_order_id: BigInt(0),
+                expiry_timestamp: null,
+
+                display_quantity: BigInt(0),
+
+                peg_offset: null,
+                referrer: null,
+                match_limit: null,
+                match_limit_behavior: MatchLimitBehavior.FailOrder
             }
 
             createAndSendPlaceOrderIx(user3, userMarketAccount3, openOrderAccount3, userPcAta3, pcVault, args);
@@ -600,7 +842,8 @@ describe("Orderbook tests", () => {
             let userMarketInfo = svm.getAccount(userMarketAccount3);
             //@ts-ignore
             const userMarketData = new UserMarketAccount(borsh.deserialize(UserMarketAccountSchema, userMarketInfo?.data));
-            expect(userMarketData.locked_pc).toBe(BigInt(660));
+            expect(userMarketData.locked_pc).toBe(BigInt(600 + 1));    //3 at 200 plus the taker fee, price improvement is never locked
+            expect(userMarketData.free_pc).toBe(BigInt(0));
             
             //open order account checks
             let openorderInfo = svm.getAccount(openOrderAccount3);
@@ -611,7 +854,7 @@ describe("Orderbook tests", () => {
             //Market's PC Vault checks
             let mktPcVaultInfo = svm.getAccount(pcVault);
             const mktPcVaultData = AccountLayout.decode(mktPcVaultInfo!.data);
-            expect(mktPcVaultData.amount).toBe(BigInt(test2BidPcQty + 660));
+            expect(mktPcVaultData.amount).toBe(BigInt(test2BidPcQty + 601));
 
             //Market's Coin Vault checks
             let mktCoinVaultInfo = svm.getAccount(coinVault);
@@ -650,7 +893,18 @@ describe("Orderbook tests", () => {
                 side: 1,
                 limit_price: BigInt(100),
                 coin_qty: BigInt(7),
-                pc_qty: BigInt(20)
+                order_type: OrderType.Limit,
+                pc_qty_limit: BigInt(0),
+                self_trade_behavior: SelfTradeBehavior.CancelMaker,
+                client_order_id: BigInt(0),
+                expiry_timestamp: null,
+
+                display_quantity: BigInt(0),
+
+                peg_offset: null,
+                referrer: null,
+                match_limit: null,
+                match_limit_behavior: MatchLimitBehavior.FailOrder
             }
 
             createAndSendPlaceOrderIx(user2, userMarketAccount2, openOrderAccount2, userCoinAta2, coinVault, args);
@@ -689,7 +943,7 @@ describe("Orderbook tests", () => {
             //Market's PC Vault checks
             let mktPcVaultInfo = svm.getAccount(pcVault);
             const mktPcVaultData = AccountLayout.decode(mktPcVaultInfo!.data);
-            expect(mktPcVaultData.amount).toBe(BigInt(test2BidPcQty + 660));
+            expect(mktPcVaultData.amount).toBe(BigInt(test2BidPcQty + 601));
 
             //Market's Coin Vault checks
             let mktCoinVaultInfo = svm.getAccount(coinVault);
@@ -748,7 +1002,7 @@ describe("Orderbook tests", () => {
             expect(userMarketData.free_coin).toBe(BigInt(0));
             expect(userMarketData.locked_coin).toBe(BigInt(5));
             expect(userMarketData.free_pc).toBe(BigInt(0));
-            expect(userMarketData.locked_pc).toBe(BigInt(520));
+            expect(userMarketData.locked_pc).toBe(BigInt(500));
 
             let userMarketInfo3 = svm.getAccount(userMarketAccount3);
             //@ts-ignore
@@ -756,7 +1010,7 @@ describe("Orderbook tests", () => {
             expect(userMarketData3.free_coin).toBe(BigInt(0));
             expect(userMarketData3.locked_coin).toBe(BigInt(0));
             expect(userMarketData3.free_pc).toBe(BigInt(0));
-            expect(userMarketData3.locked_pc).toBe(BigInt(660));
+            expect(userMarketData3.locked_pc).toBe(BigInt(601));
 
             let userMarketInfo2 = svm.getAccount(userMarketAccount2);
             //@ts-ignore
@@ -810,9 +1064,9 @@ describe("Orderbook tests", () => {
         //@ts-ignore
         const userMarketData = new UserMarketAccount(borsh.deserialize(UserMarketAccountSchema, userMarketInfo1?.data));
         expect(userMarketData.free_coin).toBe(BigInt(5));
-        expect(userMarketData.locked_coin).toBe(BigInt(5));
+        expect(userMarketData.locked_coin).toBe(BigInt(2));
         expect(userMarketData.free_pc).toBe(BigInt(600));
-        expect(userMarketData.locked_pc).toBe(BigInt(520));
+        expect(userMarketData.locked_pc).toBe(BigInt(0));
 
         let userMarketInfo3 = svm.getAccount(userMarketAccount3);
         //@ts-ignore
@@ -820,14 +1074,14 @@ describe("Orderbook tests", () => {
         expect(userMarketData3.free_coin).toBe(BigInt(3));
         expect(userMarketData3.locked_coin).toBe(BigInt(0));
         expect(userMarketData3.free_pc).toBe(BigInt(0));
-        expect(userMarketData3.locked_pc).toBe(BigInt(660));
+        expect(userMarketData3.locked_pc).toBe(BigInt(0));    //paid 600 and a taker fee of 10 bps on it, rounded up
 
         let userMarketInfo2 = svm.getAccount(userMarketAccount2);
         //@ts-ignore
         const userMarketData2 = new UserMarketAccount(borsh.deserialize(UserMarketAccountSchema, userMarketInfo2?.data));
         expect(userMarketData2.free_coin).toBe(BigInt(0));
-        expect(userMarketData2.locked_coin).toBe(BigInt(7));
-        expect(userMarketData2.free_pc).toBe(BigInt(500));
+        expect(userMarketData2.locked_coin).toBe(BigInt(2));
+        expect(userMarketData2.free_pc).toBe(BigInt(500 - 1));    //taker fee of 10 bps on 500, rounded up
         expect(userMarketData2.locked_pc).toBe(BigInt(0));
     });
 
@@ -842,7 +1096,7 @@ describe("Orderbook tests", () => {
 
             let userPcAtaInfo = svm.getAccount(userPcAta);
             const userPcAtaData = AccountLayout.decode(userPcAtaInfo!.data);
-            expect(userPcAtaData.amount).toBe(BigInt(initialPcQty - 520));
+            expect(userPcAtaData.amount).toBe(BigInt(initialPcQty - 500));
 
             let userMarketInfo1 = svm.getAccount(userMarketAccount);
             //@ts-ignore
@@ -858,7 +1112,7 @@ describe("Orderbook tests", () => {
 
             let userPcAtaInfo3 = svm.getAccount(userPcAta3);
             const userPcAtaData3 = AccountLayout.decode(userPcAtaInfo3!.data);
-            expect(userPcAtaData3.amount).toBe(BigInt(initialPcQty - 660));
+            expect(userPcAtaData3.amount).toBe(BigInt(initialPcQty - 601));
 
             let userMarketInfo3 = svm.getAccount(userMarketAccount3);
             //@ts-ignore
@@ -880,7 +1134,7 @@ describe("Orderbook tests", () => {
             //@ts-ignore
             const userMarketData2 = new UserMarketAccount(borsh.deserialize(UserMarketAccountSchema, userMarketInfo2?.data));
             expect(userMarketData2.free_coin).toBe(BigInt(0));
-            expect(userMarketData2.free_pc).toBe(BigInt(500));
+            expect(userMarketData2.free_pc).toBe(BigInt(499));
         }
 
         createAndSendSettleFundsIx(user, userMarketAccount, userCoinAta, userPcAta);
@@ -895,7 +1149,7 @@ describe("Orderbook tests", () => {
 
         let userPcAtaInfo = svm.getAccount(userPcAta);
         const userPcAtaData = AccountLayout.decode(userPcAtaInfo!.data);
-        expect(userPcAtaData.amount).toBe(BigInt(initialPcQty - 520 + 600));
+        expect(userPcAtaData.amount).toBe(BigInt(initialPcQty - 500 + 600));
 
         let userMarketInfo1 = svm.getAccount(userMarketAccount);
         //@ts-ignore
@@ -911,7 +1165,7 @@ describe("Orderbook tests", () => {
 
         let userPcAtaInfo3 = svm.getAccount(userPcAta3);
         const userPcAtaData3 = AccountLayout.decode(userPcAtaInfo3!.data);
-        expect(userPcAtaData3.amount).toBe(BigInt(initialPcQty - 660));
+        expect(userPcAtaData3.amount).toBe(BigInt(initialPcQty - 601));
 
         let userMarketInfo3 = svm.getAccount(userMarketAccount3);
         //@ts-ignore
@@ -927,7 +1181,7 @@ describe("Orderbook tests", () => {
 
         let userPcAtaInfo2 = svm.getAccount(userPcAta2);
         const userPcAtaData2 = AccountLayout.decode(userPcAtaInfo2!.data);
-        expect(userPcAtaData2.amount).toBe(BigInt(initialPcQty + 500));
+        expect(userPcAtaData2.amount).toBe(BigInt(initialPcQty + 499));
 
         let userMarketInfo2 = svm.getAccount(userMarketAccount2);
         //@ts-ignore
@@ -1047,7 +1301,7 @@ describe("Orderbook tests", () => {
                 //@ts-ignore
                 const userMarketData2 = new UserMarketAccount(borsh.deserialize(UserMarketAccountSchema, userMarketInfo2?.data));
                 expect(userMarketData2.free_coin).toBe(BigInt(0));
-                expect(userMarketData2.locked_coin).toBe(BigInt(7));
+                expect(userMarketData2.locked_coin).toBe(BigInt(2));
                 expect(userMarketData2.free_pc).toBe(BigInt(0));
                 expect(userMarketData2.locked_pc).toBe(BigInt(0));
             }
@@ -1095,7 +1349,7 @@ describe("Orderbook tests", () => {
             //@ts-ignore
             const userMarketData2 = new UserMarketAccount(borsh.deserialize(UserMarketAccountSchema, userMarketInfo2?.data));
             expect(userMarketData2.free_coin).toBe(BigInt(2));   
-            expect(userMarketData2.locked_coin).toBe(BigInt(7 - 2));   //converted 2 locked coins into 2 free coins
+            expect(userMarketData2.locked_coin).toBe(BigInt(2 - 2));   //converted 2 locked coins into 2 free coins
             expect(userMarketData2.free_pc).toBe(BigInt(0));
             expect(userMarketData2.locked_pc).toBe(BigInt(0));
         }
@@ -1111,7 +1365,7 @@ describe("Orderbook tests", () => {
 
                 let userPcAtaInfo2 = svm.getAccount(userPcAta2);
                 const userPcAtaData2 = AccountLayout.decode(userPcAtaInfo2!.data);
-                expect(userPcAtaData2.amount).toBe(BigInt(initialPcQty + 500));
+                expect(userPcAtaData2.amount).toBe(BigInt(initialPcQty + 499));
 
                 let userMarketInfo2 = svm.getAccount(userMarketAccount2);
                 //@ts-ignore
@@ -1129,7 +1383,7 @@ describe("Orderbook tests", () => {
 
             let userPcAtaInfo2 = svm.getAccount(userPcAta2);
             const userPcAtaData2 = AccountLayout.decode(userPcAtaInfo2!.data);
-            expect(userPcAtaData2.amount).toBe(BigInt(initialPcQty + 500));
+            expect(userPcAtaData2.amount).toBe(BigInt(initialPcQty + 499));
 
             let userMarketInfo2 = svm.getAccount(userMarketAccount2);
             //@ts-ignore
@@ -1139,141 +1393,3355 @@ describe("Orderbook tests", () => {
         }
     });
 
+    test("Order types: Post Only, Fill or Kill and Immediate or Cancel by user3", async () => {
+        //Current OrderBook
+        // ASK
+        // 200 | 2
+        // ----------
+        //
+        // BID
 
+        //Post Only bid at 200 would cross the ask at 200, so it is rejected
+        {
+            let args = {
+                side: 0,
+                limit_price: BigInt(200),
+                coin_qty: BigInt(1),
+                order_type: OrderType.PostOnly,
+                pc_qty_limit: BigInt(0),
+                self_trade_behavior: SelfTradeBehavior.CancelMaker,
+                client_order_id: BigInt(0),
+                expiry_timestamp: null,
+
+                display_quantity: BigInt(0),
+
+                peg_offset: null,
+                referrer: null,
+                match_limit: null,
+                match_limit_behavior: MatchLimitBehavior.FailOrder
+            }
 
-    function createAndSendCreateAtaIx(user: Keypair, userCoinAta: PublicKey, userPcAta: PublicKey, coinMint: PublicKey, pcMint: PublicKey) {
-        let createUserAtaIx = new Transaction().add(
-            createAssociatedTokenAccountInstruction(
-                user.publicKey,
-                userCoinAta,
-                user.publicKey,
-                coinMint,
-                TOKEN_PROGRAM_ID,
-                ASSOCIATED_TOKEN_PROGRAM_ID
-            ),
-            createAssociatedTokenAccountInstruction(
-                user.publicKey,
-                userPcAta,
-                user.publicKey,
-                pcMint,
-                TOKEN_PROGRAM_ID,
-                ASSOCIATED_TOKEN_PROGRAM_ID
-            )
-        );
+            const sig = createAndSendPlaceOrderIx(user3, userMarketAccount3, openOrderAccount3, userPcAta3, pcVault, args);
+            expect(sig).toBeInstanceOf(FailedTransactionMetadata);
+            expect(customErrorCode(sig as FailedTransactionMetadata)).toBe(OrderBookError.OrderWouldCross);
 
-        createUserAtaIx.recentBlockhash = svm.latestBlockhash();
-        createUserAtaIx.feePayer = user.publicKey;
-        createUserAtaIx.sign(user);
-        svm.sendTransaction(createUserAtaIx);
-    }
+            let bidsInfo = svm.getAccount(bids.publicKey);
+            //@ts-ignore
+            const bidsData = new OrderBook(borsh.deserialize(OrderBookSchema, bidsInfo!.data));
+            expect(bidsData.slots_filled).toBe(0);
+        }
 
-    function createAndSendMintToIx(accountsAuthority: Keypair, user: Keypair, userCoinAta: PublicKey, userPcAta: PublicKey, coinMint: PublicKey, pcMint: PublicKey) {
-        const mintCoinToUserIx = createMintToInstruction(
-            coinMint,
-            userCoinAta,
-            accountsAuthority.publicKey,
-            initialCoinQty,
-            [],
-            TOKEN_PROGRAM_ID
-        );
+        //Fill or Kill bid for 5 only finds 2 on the ask side, so it is rejected
+        {
+            let args = {
+                side: 0,
+                limit_price: BigInt(200),
+                coin_qty: BigInt(5),
+                order_type: OrderType.FillOrKill,
+                pc_qty_limit: BigInt(0),
+                self_trade_behavior: SelfTradeBehavior.CancelMaker,
+                client_order_id: BigInt(0),
+                expiry_timestamp: null,
+
+                display_quantity: BigInt(0),
+
+                peg_offset: null,
+                referrer: null,
+                match_limit: null,
+                match_limit_behavior: MatchLimitBehavior.FailOrder
+            }
 
-        const mintPcToUserIx = createMintToInstruction(
-            pcMint,
-            userPcAta,
-            accountsAuthority.publicKey,
-            initialPcQty,
-            [],
-            TOKEN_PROGRAM_ID
-        );
-        
-        let mintCoinAndPcToUserTx = new Transaction().add(mintCoinToUserIx, mintPcToUserIx);
-        mintCoinAndPcToUserTx.recentBlockhash = svm.latestBlockhash();
-        mintCoinAndPcToUserTx.feePayer = user.publicKey;
-        mintCoinAndPcToUserTx.sign(accountsAuthority, user);
-        svm.sendTransaction(mintCoinAndPcToUserTx);
-    }
+            const sig = createAndSendPlaceOrderIx(user3, userMarketAccount3, openOrderAccount3, userPcAta3, pcVault, args);
+            expect(sig).toBeInstanceOf(FailedTransactionMetadata);
+            expect(customErrorCode(sig as FailedTransactionMetadata)).toBe(OrderBookError.FillOrKillNotFilled);
 
-    function createAndSendPlaceOrderIx(user: Keypair, userMarketAccount: PublicKey, userOpenOrderAccount: PublicKey, userAta: PublicKey, marketVault: PublicKey, args: Object) {
-        let ix = new TransactionInstruction({
-                keys: [
-                    {pubkey: accountsAuthority.publicKey, isSigner: true, isWritable: true},
-                    {pubkey: market, isSigner: false, isWritable: true},
-                    {pubkey: marketEventsAccount.publicKey, isSigner: false, isWritable: true},
-                    {pubkey: user.publicKey, isSigner: true, isWritable: true},
-                    {pubkey: userMarketAccount, isSigner: false, isWritable: true},
-                    {pubkey: userOpenOrderAccount, isSigner: false, isWritable: true},
-                    {pubkey: userAta, isSigner: false, isWritable: true},
-                    {pubkey: marketVault, isSigner: false, isWritable: true},
-                    {pubkey: coinMint.publicKey, isSigner: false, isWritable: true},
-                    {pubkey: pcMint.publicKey, isSigner: false, isWritable: true},
-                    {pubkey: bids.publicKey, isSigner: false, isWritable: true},
-                    {pubkey: asks.publicKey, isSigner: false, isWritable: true},
-                    {pubkey: SystemProgram.programId, isSigner: false, isWritable: false},
-                    {pubkey: TOKEN_PROGRAM_ID, isSigner: false, isWritable: false},
-                ],
-                programId: programId,
-                data: Buffer.concat([Buffer.from([1]), Buffer.from(borsh.serialize(CreateOrderSchema, args))]) 
-            });
+            let asksInfo = svm.getAccount(asks.publicKey);
+            //@ts-ignore
+            const asksData = new OrderBook(borsh.deserialize(OrderBookSchema, asksInfo!.data));
+            expect(asksData.slots_filled).toBe(1);
+            expect(asksData.orders[0].filled_quantity).toBe(BigInt(3));
+        }
 
-        let tx = new Transaction().add(ix);
-        tx.recentBlockhash = svm.latestBlockhash();
-        tx.feePayer = user.publicKey;
-        tx.sign(accountsAuthority, user);
-        const sig = svm.sendTransaction(tx);
-        if (sig instanceof TransactionMetadata) {
-            console.log(sig.toString());
-        } else if (sig instanceof FailedTransactionMetadata) {
-            console.log(sig.toString());
+        //Immediate or Cancel bid for 5 fills 2 and drops the remaining 3
+        {
+            let args = {
+                side: 0,
+                limit_price: BigInt(200),
+                coin_qty: BigInt(5),
+                order_type: OrderType.ImmediateOrCancel,
+                pc_qty_limit: BigInt(0),
+                self_trade_behavior: SelfTradeBehavior.CancelMaker,
+                client_order_id: BigInt(0),
+                expiry_timestamp: null,
+
+                display_quantity: BigInt(0),
+
+                peg_offset: null,
+                referrer: null,
+                match_limit: null,
+                match_limit_behavior: MatchLimitBehavior.FailOrder
+            }
+
+            const sig = createAndSendPlaceOrderIx(user3, userMarketAccount3, openOrderAccount3, userPcAta3, pcVault, args);
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+
+            //asks checks
+            let asksInfo = svm.getAccount(asks.publicKey);
+            //@ts-ignore
+            const asksData = new OrderBook(borsh.deserialize(OrderBookSchema, asksInfo!.data));
+            expect(asksData.slots_filled).toBe(0);
+
+            //bids checks, remainder must not rest
+            let bidsInfo = svm.getAccount(bids.publicKey);
+            //@ts-ignore
+            const bidsData = new OrderBook(borsh.deserialize(OrderBookSchema, bidsInfo!.data));
+            expect(bidsData.slots_filled).toBe(0);
+
+            //user market checks, unspent pc is released back to free balance
+            let userMarketInfo = svm.getAccount(userMarketAccount3);
+            //@ts-ignore
+            const userMarketData = new UserMarketAccount(borsh.deserialize(UserMarketAccountSchema, userMarketInfo?.data));
+            expect(userMarketData.free_pc).toBe(BigInt(0));
+            expect(userMarketData.locked_pc).toBe(BigInt(2 * 200 + 1));    //only what the fills cost is locked, taker fee included
+
+            //Market Event Account checks
+            let mktEventInfo = svm.getAccount(marketEventsAccount.publicKey);
+            //@ts-ignore
+            const mktEventData = new MarketEventsAccount(borsh.deserialize(MarketEventsAccountSchema, mktEventInfo!.data));
+            expect(mktEventData.head).toBe(4);
+            expect(mktEventData.tail).toBe(3);
+            let event = mktEventData.events[mktEventData.head.valueOf() - 1];
+            expect(event.event_type).toBe(EventType.Fill);
+            expect(event.side).toBe(Side.Ask);
+            expect(new PublicKey(event.maker)).toStrictEqual(user.publicKey);
+            expect(new PublicKey(event.taker)).toStrictEqual(user3.publicKey);
+            expect(event.coin_qty).toBe(BigInt(2));
+            expect(event.pc_qty).toBe(BigInt(400));
         }
-    }
 
-    function createAndSendSettleFundsIx(user: Keypair, userMarketAccount: PublicKey, userCoinAta: PublicKey, userPcAta: PublicKey) {
-        let ix = new TransactionInstruction({
-            keys: [
-                {pubkey: market, isSigner: false, isWritable: true},
-                {pubkey: marketEventsAccount.publicKey, isSigner: false, isWritable: true},
-                {pubkey: user.publicKey, isSigner: true, isWritable: true},
-                {pubkey: userMarketAccount, isSigner: false, isWritable: true},
-                {pubkey: coinMint.publicKey, isSigner: false, isWritable: true},
-                {pubkey: pcMint.publicKey, isSigner: false, isWritable: true},
-                {pubkey: coinVault, isSigner: false, isWritable: true},
-                {pubkey: pcVault, isSigner: false, isWritable: true},
-                {pubkey: userCoinAta, isSigner: false, isWritable: true},
-                {pubkey: userPcAta, isSigner: false, isWritable: true},
-                {pubkey: TOKEN_PROGRAM_ID, isSigner: false, isWritable: false},
-            ],
-            programId: programId,
-            data: Buffer.from([3])
+        //Current OrderBook
+        // ASK
+        //
+        // ----------
+        //
+        // BID
+    });
+
+    test("Market orders with pc limits by user3", async () => {
+        //Asks by user2
+        //2 at 100 and 2 at 150
+        [100, 150].forEach(price => {
+            let args = {
+                side: 1,
+                limit_price: BigInt(price),
+                coin_qty: BigInt(2),
+                order_type: OrderType.Limit,
+                pc_qty_limit: BigInt(0),
+                self_trade_behavior: SelfTradeBehavior.CancelMaker,
+                client_order_id: BigInt(0),
+                expiry_timestamp: null,
+
+                display_quantity: BigInt(0),
+
+                peg_offset: null,
+                referrer: null,
+                match_limit: null,
+                match_limit_behavior: MatchLimitBehavior.FailOrder
+            }
+            const sig = createAndSendPlaceOrderIx(user2, userMarketAccount2, openOrderAccount2, userCoinAta2, coinVault, args);
+            expect(sig).toBeInstanceOf(TransactionMetadata);
         });
 
-        let tx = new Transaction().add(ix);
-        tx.recentBlockhash = svm.latestBlockhash();
-        tx.feePayer = user.publicKey;
-        tx.sign(user);
-        const sig = svm.sendTransaction(tx);
-        if (sig instanceof TransactionMetadata) {
-            console.log(sig.toString());
-        } else if (sig instanceof FailedTransactionMetadata) {
-            console.log(sig.toString());
-        }
-        svm.expireBlockhash();
-    }
+        //Current OrderBook
+        // ASK
+        // 150 | 2
+        // 100 | 2
+        // ----------
+        //
+        // BID
 
-    function retrieveMakerTakerMarketAccountsFromEventQueueCronMethod(programId: PublicKey, marketAccount: PublicKey, marketEventsAccount: PublicKey) {
-        let mktEventInfo = svm.getAccount(marketEventsAccount);
-        //@ts-ignore
-        const mktEventData = new MarketEventsAccount(borsh.deserialize(MarketEventsAccountSchema, mktEventInfo!.data));
+        //Market bid for 3 would spend 2 * 100 + 1 * 150 = 350 pc, more than the limit of 300
+        {
+            let args = {
+                side: 0,
+                limit_price: BigInt(0),
+                coin_qty: BigInt(3),
+                order_type: OrderType.Market,
+                pc_qty_limit: BigInt(300),
+                self_trade_behavior: SelfTradeBehavior.CancelMaker,
+                client_order_id: BigInt(0),
+                expiry_timestamp: null,
+
+                display_quantity: BigInt(0),
+
+                peg_offset: null,
+                referrer: null,
+                match_limit: null,
+                match_limit_behavior: MatchLimitBehavior.FailOrder
+            }
+            const sig = createAndSendPlaceOrderIx(user3, userMarketAccount3, openOrderAccount3, userPcAta3, pcVault, args);
+            expect(sig).toBeInstanceOf(FailedTransactionMetadata);
+            expect(customErrorCode(sig as FailedTransactionMetadata)).toBe(OrderBookError.MarketOrderLimitExceeded);
 
-        if (mktEventData.head == mktEventData.tail) {
-            return null;
-        } 
-        let size = mktEventData.size();
+            let asksInfo = svm.getAccount(asks.publicKey);
+            //@ts-ignore
+            const asksData = new OrderBook(borsh.deserialize(OrderBookSchema, asksInfo!.data));
+            expect(asksData.slots_filled).toBe(2);
+        }
+
+        //Nor does a limit of 350, which covers the fills but not the 2 of taker fees on them
+        {
+            let args = {
+                side: 0,
+                limit_price: BigInt(0),
+                coin_qty: BigInt(3),
+                order_type: OrderType.Market,
+                pc_qty_limit: BigInt(350),
+                self_trade_behavior: SelfTradeBehavior.CancelMaker,
+                client_order_id: BigInt(0),
+                expiry_timestamp: null,
+
+                display_quantity: BigInt(0),
+
+                peg_offset: null,
+                referrer: null,
+                match_limit: null,
+                match_limit_behavior: MatchLimitBehavior.FailOrder
+            }
+            const sig = createAndSendPlaceOrderIx(user3, userMarketAccount3, openOrderAccount3, userPcAta3, pcVault, args);
+            expect(sig).toBeInstanceOf(FailedTransactionMetadata);
+            expect(customErrorCode(sig as FailedTransactionMetadata)).toBe(OrderBookError.MarketOrderLimitExceeded);
+        }
+
+        //Market bid for 3 with a limit of 400 walks both price levels
+        {
+            let args = {
+                side: 0,
+                limit_price: BigInt(0),
+                coin_qty: BigInt(3),
+                order_type: OrderType.Market,
+                pc_qty_limit: BigInt(400),
+                self_trade_behavior: SelfTradeBehavior.CancelMaker,
+                client_order_id: BigInt(0),
+                expiry_timestamp: null,
+
+                display_quantity: BigInt(0),
+
+                peg_offset: null,
+                referrer: null,
+                match_limit: null,
+                match_limit_behavior: MatchLimitBehavior.FailOrder
+            }
+            const sig = createAndSendPlaceOrderIx(user3, userMarketAccount3, openOrderAccount3, userPcAta3, pcVault, args);
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+
+            let asksInfo = svm.getAccount(asks.publicKey);
+            //@ts-ignore
+            const asksData = new OrderBook(borsh.deserialize(OrderBookSchema, asksInfo!.data));
+            expect(asksData.slots_filled).toBe(1);
+            expect(asksData.orders[0].price).toBe(BigInt(150));
+            expect(asksData.orders[0].filled_quantity).toBe(BigInt(1));
+
+            let bidsInfo = svm.getAccount(bids.publicKey);
+            //@ts-ignore
+            const bidsData = new OrderBook(borsh.deserialize(OrderBookSchema, bidsInfo!.data));
+            expect(bidsData.slots_filled).toBe(0);
+
+            //only the 350 pc spent and 2 of taker fees got locked
+            let userMarketInfo = svm.getAccount(userMarketAccount3);
+            //@ts-ignore
+            const userMarketData = new UserMarketAccount(borsh.deserialize(UserMarketAccountSchema, userMarketInfo?.data));
+            expect(userMarketData.free_pc).toBe(BigInt(0));
+            expect(userMarketData.locked_pc).toBe(BigInt(401 + 350 + 2));
+
+            let mktEventInfo = svm.getAccount(marketEventsAccount.publicKey);
+            //@ts-ignore
+            const mktEventData = new MarketEventsAccount(borsh.deserialize(MarketEventsAccountSchema, mktEventInfo!.data));
+            expect(mktEventData.head).toBe(6);
+            expect(mktEventData.events[4].pc_qty).toBe(BigInt(200));
+            expect(mktEventData.events[5].pc_qty).toBe(BigInt(150));
+        }
+
+        //Current OrderBook
+        // ASK
+        // 150 | 1
+        // ----------
+        //
+        // BID
+
+        //Bid by user
+        //1 at 90
+        {
+            let args = {
+                side: 0,
+                limit_price: BigInt(90),
+                coin_qty: BigInt(1),
+                order_type: OrderType.Limit,
+                pc_qty_limit: BigInt(0),
+                self_trade_behavior: SelfTradeBehavior.CancelMaker,
+                client_order_id: BigInt(0),
+                expiry_timestamp: null,
+
+                display_quantity: BigInt(0),
+
+                peg_offset: null,
+                referrer: null,
+                match_limit: null,
+                match_limit_behavior: MatchLimitBehavior.FailOrder
+            }
+            const sig = createAndSendPlaceOrderIx(user, userMarketAccount, openOrderAccount, userPcAta, pcVault, args);
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+        }
+
+        //Market ask for 2 only finds 1 at 90, less than the 100 pc it must receive
+        {
+            let args = {
+                side: 1,
+                limit_price: BigInt(0),
+                coin_qty: BigInt(2),
+                order_type: OrderType.Market,
+                pc_qty_limit: BigInt(100),
+                self_trade_behavior: SelfTradeBehavior.CancelMaker,
+                client_order_id: BigInt(0),
+                expiry_timestamp: null,
+
+                display_quantity: BigInt(0),
+
+                peg_offset: null,
+                referrer: null,
+                match_limit: null,
+                match_limit_behavior: MatchLimitBehavior.FailOrder
+            }
+            const sig = createAndSendPlaceOrderIx(user3, userMarketAccount3, openOrderAccount3, userCoinAta3, coinVault, args);
+            expect(sig).toBeInstanceOf(FailedTransactionMetadata);
+            expect(customErrorCode(sig as FailedTransactionMetadata)).toBe(OrderBookError.MarketOrderLimitExceeded);
+        }
+
+        //Nor with a limit of 90, the fill at 90 is received net of a taker fee of 1
+        {
+            let args = {
+                side: 1,
+                limit_price: BigInt(0),
+                coin_qty: BigInt(2),
+                order_type: OrderType.Market,
+                pc_qty_limit: BigInt(90),
+                self_trade_behavior: SelfTradeBehavior.CancelMaker,
+                client_order_id: BigInt(0),
+                expiry_timestamp: null,
+
+                display_quantity: BigInt(0),
+
+                peg_offset: null,
+                referrer: null,
+                match_limit: null,
+                match_limit_behavior: MatchLimitBehavior.FailOrder
+            }
+            const sig = createAndSendPlaceOrderIx(user3, userMarketAccount3, openOrderAccount3, userCoinAta3, coinVault, args);
+            expect(sig).toBeInstanceOf(FailedTransactionMetadata);
+            expect(customErrorCode(sig as FailedTransactionMetadata)).toBe(OrderBookError.MarketOrderLimitExceeded);
+        }
+
+        //Market ask for 2 with a limit of 89 sells 1 and drops the other
+        {
+            let args = {
+                side: 1,
+                limit_price: BigInt(0),
+                coin_qty: BigInt(2),
+                order_type: OrderType.Market,
+                pc_qty_limit: BigInt(89),
+                self_trade_behavior: SelfTradeBehavior.CancelMaker,
+                client_order_id: BigInt(0),
+                expiry_timestamp: null,
+
+                display_quantity: BigInt(0),
+
+                peg_offset: null,
+                referrer: null,
+                match_limit: null,
+                match_limit_behavior: MatchLimitBehavior.FailOrder
+            }
+            const sig = createAndSendPlaceOrderIx(user3, userMarketAccount3, openOrderAccount3, userCoinAta3, coinVault, args);
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+
+            let bidsInfo = svm.getAccount(bids.publicKey);
+            //@ts-ignore
+            const bidsData = new OrderBook(borsh.deserialize(OrderBookSchema, bidsInfo!.data));
+            expect(bidsData.slots_filled).toBe(0);
+
+            let asksInfo = svm.getAccount(asks.publicKey);
+            //@ts-ignore
+            const asksData = new OrderBook(borsh.deserialize(OrderBookSchema, asksInfo!.data));
+            expect(asksData.slots_filled).toBe(1);
+
+            let userMarketInfo = svm.getAccount(userMarketAccount3);
+            //@ts-ignore
+            const userMarketData = new UserMarketAccount(borsh.deserialize(UserMarketAccountSchema, userMarketInfo?.data));
+            expect(userMarketData.free_coin).toBe(BigInt(1));
+            expect(userMarketData.locked_coin).toBe(BigInt(1));
+
+            let mktEventInfo = svm.getAccount(marketEventsAccount.publicKey);
+            //@ts-ignore
+            const mktEventData = new MarketEventsAccount(borsh.deserialize(MarketEventsAccountSchema, mktEventInfo!.data));
+            expect(mktEventData.head).toBe(7);
+            let event = mktEventData.events[6];
+            expect(event.side).toBe(Side.Bid);
+            expect(new PublicKey(event.maker)).toStrictEqual(user.publicKey);
+            expect(event.coin_qty).toBe(BigInt(1));
+            expect(event.pc_qty).toBe(BigInt(90));
+        }
+
+        //Current OrderBook
+        // ASK
+        // 150 | 1
+        // ----------
+        //
+        // BID
+    });
+
+    test("Self trade prevention by user2", async () => {
+        //Current OrderBook
+        // ASK
+        // 150 | 1     (user2)
+        // ----------
+        //
+        // BID
+
+        let bidArgs = (selfTradeBehavior: SelfTradeBehavior) => {
+            return {
+                side: 0,
+                limit_price: BigInt(150),
+                coin_qty: BigInt(1),
+                order_type: OrderType.Limit,
+                pc_qty_limit: BigInt(0),
+                self_trade_behavior: selfTradeBehavior,
+                client_order_id: BigInt(0),
+                expiry_timestamp: null,
+
+                display_quantity: BigInt(0),
+
+                peg_offset: null,
+                referrer: null,
+                match_limit: null,
+                match_limit_behavior: MatchLimitBehavior.FailOrder
+            }
+        };
+
+        //Abort Transaction fails the whole instruction
+        {
+            const sig = createAndSendPlaceOrderIx(user2, userMarketAccount2, openOrderAccount2, userPcAta2, pcVault, bidArgs(SelfTradeBehavior.AbortTransaction));
+            expect(sig).toBeInstanceOf(FailedTransactionMetadata);
+            expect(customErrorCode(sig as FailedTransactionMetadata)).toBe(OrderBookError.SelfTradeAborted);
+        }
+
+        //Cancel Taker leaves the ask alone and drops the bid
+        {
+            const sig = createAndSendPlaceOrderIx(user2, userMarketAccount2, openOrderAccount2, userPcAta2, pcVault, bidArgs(SelfTradeBehavior.CancelTaker));
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+
+            let asksInfo = svm.getAccount(asks.publicKey);
+            //@ts-ignore
+            const asksData = new OrderBook(borsh.deserialize(OrderBookSchema, asksInfo!.data));
+            expect(asksData.slots_filled).toBe(1);
+
+            let bidsInfo = svm.getAccount(bids.publicKey);
+            //@ts-ignore
+            const bidsData = new OrderBook(borsh.deserialize(OrderBookSchema, bidsInfo!.data));
+            expect(bidsData.slots_filled).toBe(0);
+
+            let userMarketInfo = svm.getAccount(userMarketAccount2);
+            //@ts-ignore
+            const userMarketData = new UserMarketAccount(borsh.deserialize(UserMarketAccountSchema, userMarketInfo?.data));
+            expect(userMarketData.free_pc).toBe(BigInt(0));    //nothing traded or rested, so nothing was locked
+            expect(userMarketData.locked_pc).toBe(BigInt(0));
+
+            let mktEventInfo = svm.getAccount(marketEventsAccount.publicKey);
+            //@ts-ignore
+            const mktEventData = new MarketEventsAccount(borsh.deserialize(MarketEventsAccountSchema, mktEventInfo!.data));
+            expect(mktEventData.head).toBe(7);
+        }
+
+        //Cancel Maker removes the ask with an out event and rests the bid
+        {
+            const sig = createAndSendPlaceOrderIx(user2, userMarketAccount2, openOrderAccount2, userPcAta2, pcVault, bidArgs(SelfTradeBehavior.CancelMaker));
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+
+            let asksInfo = svm.getAccount(asks.publicKey);
+            //@ts-ignore
+            const asksData = new OrderBook(borsh.deserialize(OrderBookSchema, asksInfo!.data));
+            expect(asksData.slots_filled).toBe(0);
+
+            let bidsInfo = svm.getAccount(bids.publicKey);
+            //@ts-ignore
+            const bidsData = new OrderBook(borsh.deserialize(OrderBookSchema, bidsInfo!.data));
+            expect(bidsData.slots_filled).toBe(1);
+            expect(new PublicKey(bidsData.orders[0].owner)).toStrictEqual(user2.publicKey);
+
+            let mktEventInfo = svm.getAccount(marketEventsAccount.publicKey);
+            //@ts-ignore
+            const mktEventData = new MarketEventsAccount(borsh.deserialize(MarketEventsAccountSchema, mktEventInfo!.data));
+            expect(mktEventData.head).toBe(8);
+            let event = mktEventData.events[7];
+            expect(event.event_type).toBe(EventType.Out);
+            expect(event.side).toBe(Side.Ask);
+            expect(new PublicKey(event.maker)).toStrictEqual(user2.publicKey);
+            expect(event.coin_qty).toBe(BigInt(1));
+            expect(event.pc_qty).toBe(BigInt(150));
+        }
+
+        //Current OrderBook
+        // ASK
+        //
+        // ----------
+        // 150 | 1     (user2)
+        // BID
+
+        //Decrement And Cancel reduces both sides by 1, nothing trades and nothing rests
+        {
+            let args = {
+                side: 1,
+                limit_price: BigInt(150),
+                coin_qty: BigInt(1),
+                order_type: OrderType.Limit,
+                pc_qty_limit: BigInt(0),
+                self_trade_behavior: SelfTradeBehavior.DecrementAndCancel,
+                client_order_id: BigInt(0),
+                expiry_timestamp: null,
+
+                display_quantity: BigInt(0),
+
+                peg_offset: null,
+                referrer: null,
+                match_limit: null,
+                match_limit_behavior: MatchLimitBehavior.FailOrder
+            }
+            const sig = createAndSendPlaceOrderIx(user2, userMarketAccount2, openOrderAccount2, userCoinAta2, coinVault, args);
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+
+            let asksInfo = svm.getAccount(asks.publicKey);
+            //@ts-ignore
+            const asksData = new OrderBook(borsh.deserialize(OrderBookSchema, asksInfo!.data));
+            expect(asksData.slots_filled).toBe(0);
+
+            let bidsInfo = svm.getAccount(bids.publicKey);
+            //@ts-ignore
+            const bidsData = new OrderBook(borsh.deserialize(OrderBookSchema, bidsInfo!.data));
+            expect(bidsData.slots_filled).toBe(0);
+
+            let userMarketInfo = svm.getAccount(userMarketAccount2);
+            //@ts-ignore
+            const userMarketData = new UserMarketAccount(borsh.deserialize(UserMarketAccountSchema, userMarketInfo?.data));
+            expect(userMarketData.free_coin).toBe(BigInt(1));
+
+            let mktEventInfo = svm.getAccount(marketEventsAccount.publicKey);
+            //@ts-ignore
+            const mktEventData = new MarketEventsAccount(borsh.deserialize(MarketEventsAccountSchema, mktEventInfo!.data));
+            expect(mktEventData.head).toBe(9);
+            let event = mktEventData.events[8];
+            expect(event.event_type).toBe(EventType.Out);
+            expect(event.side).toBe(Side.Bid);
+            expect(event.coin_qty).toBe(BigInt(1));
+            expect(event.pc_qty).toBe(BigInt(150));
+        }
+
+        //Current OrderBook
+        // ASK
+        //
+        // ----------
+        //
+        // BID
+    });
+
+    test("Client order ids and Cancel Order By Client Id by user", async () => {
+        //Bid by user with client order id 42
+        //Price: 80
+        //Qty: 1
+        {
+            let args = {
+                side: 0,
+                limit_price: BigInt(80),
+                coin_qty: BigInt(1),
+                order_type: OrderType.Limit,
+                pc_qty_limit: BigInt(0),
+                self_trade_behavior: SelfTradeBehavior.CancelMaker,
+                client_order_id: BigInt(42),
+                expiry_timestamp: null,
+
+                display_quantity: BigInt(0),
+
+                peg_offset: null,
+                referrer: null,
+                match_limit: null,
+                match_limit_behavior: MatchLimitBehavior.FailOrder
+            }
+            const sig = createAndSendPlaceOrderIx(user, userMarketAccount, openOrderAccount, userPcAta, pcVault, args);
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+
+            let bidsInfo = svm.getAccount(bids.publicKey);
+            //@ts-ignore
+            const bidsData = new OrderBook(borsh.deserialize(OrderBookSchema, bidsInfo!.data));
+            expect(bidsData.slots_filled).toBe(1);
+            expect(bidsData.orders[0].client_order_id).toBe(BigInt(42));
+
+            let openorderInfo = svm.getAccount(openOrderAccount);
+            //@ts-ignore
+            const openOrderData = new OpenOrderAccount(borsh.deserialize(OpenOrderAccountSchema, openorderInfo?.data));
+            expect(openOrderData.next_array_index).toBe(3);
+            expect(openOrderData.client_order_ids[2]).toBe(BigInt(42));
+        }
+
+        //Current OrderBook
+        // ASK
+        //
+        // ----------
+        // 80 | 1     (user, client order id 42)
+        // BID
+
+        //Cancelling an unknown client order id fails
+        {
+            const sig = createAndSendCancelOrderByClientIdIx(user, openOrderAccount, BigInt(7));
+            expect(sig).toBeInstanceOf(FailedTransactionMetadata);
+            expect(customErrorCode(sig as FailedTransactionMetadata)).toBe(OrderBookError.OrderNotFound);
+        }
+
+        //Cancel by client order id 42
+        {
+            const sig = createAndSendCancelOrderByClientIdIx(user, openOrderAccount, BigInt(42));
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+
+            let bidsInfo = svm.getAccount(bids.publicKey);
+            //@ts-ignore
+            const bidsData = new OrderBook(borsh.deserialize(OrderBookSchema, bidsInfo!.data));
+            expect(bidsData.slots_filled).toBe(0);
+
+            let mktEventInfo = svm.getAccount(marketEventsAccount.publicKey);
+            //@ts-ignore
+            const mktEventData = new MarketEventsAccount(borsh.deserialize(MarketEventsAccountSchema, mktEventInfo!.data));
+            expect(mktEventData.head).toBe(10);
+            let event = mktEventData.events[9];
+            expect(event.event_type).toBe(EventType.Out);
+            expect(event.side).toBe(Side.Bid);
+            expect(new PublicKey(event.maker)).toStrictEqual(user.publicKey);
+            expect(event.coin_qty).toBe(BigInt(1));
+            expect(event.pc_qty).toBe(BigInt(80));
+            expect(event.maker_client_order_id).toBe(BigInt(42));
+        }
+
+        //Current OrderBook
+        // ASK
+        //
+        // ----------
+        //
+        // BID
+    });
+
+    test("Good till time orders expire and get pruned", async () => {
+        const clock = svm.getClock();
+        const now = clock.unixTimestamp;
+
+        //Bids by user that expire in 100 seconds
+        //1 at 70 and 1 at 60
+        [70, 60].forEach(price => {
+            let args = {
+                side: 0,
+                limit_price: BigInt(price),
+                coin_qty: BigInt(1),
+                order_type: OrderType.Limit,
+                pc_qty_limit: BigInt(0),
+                self_trade_behavior: SelfTradeBehavior.CancelMaker,
+                client_order_id: BigInt(0),
+                expiry_timestamp: now + BigInt(100),
+
+                display_quantity: BigInt(0),
+
+                peg_offset: null,
+                referrer: null,
+                match_limit: null,
+                match_limit_behavior: MatchLimitBehavior.FailOrder
+            }
+            const sig = createAndSendPlaceOrderIx(user, userMarketAccount, openOrderAccount, userPcAta, pcVault, args);
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+        });
+
+        let bidsInfo = svm.getAccount(bids.publicKey);
+        //@ts-ignore
+        let bidsData = new OrderBook(borsh.deserialize(OrderBookSchema, bidsInfo!.data));
+        expect(bidsData.slots_filled).toBe(2);
+        expect(bidsData.orders[0].expiry_timestamp).toBe(now + BigInt(100));
+
+        //Current OrderBook
+        // ASK
+        //
+        // ----------
+        // 70 | 1     (user, expires)
+        // 60 | 1     (user, expires)
+        // BID
+
+        clock.unixTimestamp = now + BigInt(200);
+        svm.setClock(clock);
+
+        //Ask by user2 at 70 finds the expired bid, removes it and rests instead of filling
+        {
+            let args = {
+                side: 1,
+                limit_price: BigInt(70),
+                coin_qty: BigInt(1),
+                order_type: OrderType.Limit,
+                pc_qty_limit: BigInt(0),
+                self_trade_behavior: SelfTradeBehavior.CancelMaker,
+                client_order_id: BigInt(0),
+                expiry_timestamp: null,
+
+                display_quantity: BigInt(0),
+
+                peg_offset: null,
+                referrer: null,
+                match_limit: null,
+                match_limit_behavior: MatchLimitBehavior.FailOrder
+            }
+            const sig = createAndSendPlaceOrderIx(user2, userMarketAccount2, openOrderAccount2, userCoinAta2, coinVault, args);
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+
+            bidsInfo = svm.getAccount(bids.publicKey);
+            //@ts-ignore
+            bidsData = new OrderBook(borsh.deserialize(OrderBookSchema, bidsInfo!.data));
+            expect(bidsData.slots_filled).toBe(1);
+            expect(bidsData.orders[0].price).toBe(BigInt(60));
+
+            let asksInfo = svm.getAccount(asks.publicKey);
+            //@ts-ignore
+            const asksData = new OrderBook(borsh.deserialize(OrderBookSchema, asksInfo!.data));
+            expect(asksData.slots_filled).toBe(1);
+            expect(asksData.orders[0].filled_quantity).toBe(BigInt(0));
+
+            let mktEventInfo = svm.getAccount(marketEventsAccount.publicKey);
+            //@ts-ignore
+            const mktEventData = new MarketEventsAccount(borsh.deserialize(MarketEventsAccountSchema, mktEventInfo!.data));
+            expect(mktEventData.head).toBe(11);
+            let event = mktEventData.events[10];
+            expect(event.event_type).toBe(EventType.Out);
+            expect(event.side).toBe(Side.Bid);
+            expect(new PublicKey(event.maker)).toStrictEqual(user.publicKey);
+            expect(event.pc_qty).toBe(BigInt(70));
+        }
+
+        //Prune Expired Orders on the bids side is permissionless
+        {
+            let ix = new TransactionInstruction({
+                keys: [
+                    {pubkey: market, isSigner: false, isWritable: true},
+                    {pubkey: marketEventsAccount.publicKey, isSigner: false, isWritable: true},
+                    {pubkey: coinMint.publicKey, isSigner: false, isWritable: false},
+                    {pubkey: pcMint.publicKey, isSigner: false, isWritable: false},
+                    {pubkey: bids.publicKey, isSigner: false, isWritable: true}
+                ],
+                programId: programId,
+                data: Buffer.concat([Buffer.from([6]), Buffer.from(borsh.serialize(PruneExpiredOrdersSchema, {side: Side.Bid, prune_limit: 10}))])
+            });
+
+            let tx = new Transaction().add(ix);
+            tx.recentBlockhash = svm.latestBlockhash();
+            tx.feePayer = accountsAuthority.publicKey;
+            tx.sign(accountsAuthority);
+            const sig = svm.sendTransaction(tx);
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+
+            bidsInfo = svm.getAccount(bids.publicKey);
+            //@ts-ignore
+            bidsData = new OrderBook(borsh.deserialize(OrderBookSchema, bidsInfo!.data));
+            expect(bidsData.slots_filled).toBe(0);
+
+            let mktEventInfo = svm.getAccount(marketEventsAccount.publicKey);
+            //@ts-ignore
+            const mktEventData = new MarketEventsAccount(borsh.deserialize(MarketEventsAccountSchema, mktEventInfo!.data));
+            expect(mktEventData.head).toBe(12);
+            let event = mktEventData.events[11];
+            expect(event.event_type).toBe(EventType.Out);
+            expect(event.pc_qty).toBe(BigInt(60));
+        }
+
+        //Current OrderBook
+        // ASK
+        // 70 | 1     (user2)
+        // ----------
+        //
+        // BID
+    });
+
+    test("Amend Order by user3", async () => {
+        //Ask by user3
+        //Price: 300
+        //Qty: 4
+        {
+            let args = {
+                side: 1,
+                limit_price: BigInt(300),
+                coin_qty: BigInt(4),
+                order_type: OrderType.Limit,
+                pc_qty_limit: BigInt(0),
+                self_trade_behavior: SelfTradeBehavior.CancelMaker,
+                client_order_id: BigInt(0),
+                expiry_timestamp: null,
+
+                display_quantity: BigInt(0),
+
+                peg_offset: null,
+                referrer: null,
+                match_limit: null,
+                match_limit_behavior: MatchLimitBehavior.FailOrder
+            }
+            const sig = createAndSendPlaceOrderIx(user3, userMarketAccount3, openOrderAccount3, userCoinAta3, coinVault, args);
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+        }
+
+        let asksInfo = svm.getAccount(asks.publicKey);
+        //@ts-ignore
+        let asksData = new OrderBook(borsh.deserialize(OrderBookSchema, asksInfo!.data));
+        expect(asksData.slots_filled).toBe(2);
+        expect(asksData.orders[1].price).toBe(BigInt(300));
+        const orderId = asksData.orders[1].order_id;
+
+        let umaInfo = svm.getAccount(userMarketAccount3);
+        //@ts-ignore
+        let umaData = new UserMarketAccount(borsh.deserialize(UserMarketAccountSchema, umaInfo!.data));
+        expect(umaData.free_coin).toBe(BigInt(0));
+        expect(umaData.locked_coin).toBe(BigInt(5));
+
+        //Current OrderBook
+        // ASK
+        // 300 | 4     (user3)
+        // 70  | 1     (user2)
+        // ----------
+        //
+        // BID
+
+        //Amending someone else's order fails
+        {
+            const sig = createAndSendAmendOrderIx(user2, userMarketAccount2, {order_id: orderId, side: Side.Ask, new_price: BigInt(300), new_quantity: BigInt(2)});
+            expect(sig).toBeInstanceOf(FailedTransactionMetadata);
+        }
+
+        //Reduce quantity to 2 at the same price, keeps its position and releases 2 coin
+        {
+            const sig = createAndSendAmendOrderIx(user3, userMarketAccount3, {order_id: orderId, side: Side.Ask, new_price: BigInt(300), new_quantity: BigInt(2)});
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+
+            asksInfo = svm.getAccount(asks.publicKey);
+            //@ts-ignore
+            asksData = new OrderBook(borsh.deserialize(OrderBookSchema, asksInfo!.data));
+            expect(asksData.slots_filled).toBe(2);
+            expect(asksData.orders[1].order_id).toBe(orderId);
+            expect(asksData.orders[1].quantity).toBe(BigInt(2));
+
+            let mktEventInfo = svm.getAccount(marketEventsAccount.publicKey);
+            //@ts-ignore
+            const mktEventData = new MarketEventsAccount(borsh.deserialize(MarketEventsAccountSchema, mktEventInfo!.data));
+            expect(mktEventData.head).toBe(13);
+            let event = mktEventData.events[12];
+            expect(event.event_type).toBe(EventType.Out);
+            expect(event.side).toBe(Side.Ask);
+            expect(new PublicKey(event.maker)).toStrictEqual(user3.publicKey);
+            expect(event.coin_qty).toBe(BigInt(2));
+            expect(event.maker_order_id).toBe(orderId);
+        }
+
+        //Amending the price to 60 moves the order ahead of user2's ask
+        {
+            const sig = createAndSendAmendOrderIx(user3, userMarketAccount3, {order_id: orderId, side: Side.Ask, new_price: BigInt(60), new_quantity: BigInt(2)});
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+
+            asksInfo = svm.getAccount(asks.publicKey);
+            //@ts-ignore
+            asksData = new OrderBook(borsh.deserialize(OrderBookSchema, asksInfo!.data));
+            expect(asksData.slots_filled).toBe(2);
+            expect(asksData.orders[0].order_id).toBe(orderId);
+            expect(asksData.orders[0].price).toBe(BigInt(60));
+
+            let mktEventInfo = svm.getAccount(marketEventsAccount.publicKey);
+            //@ts-ignore
+            const mktEventData = new MarketEventsAccount(borsh.deserialize(MarketEventsAccountSchema, mktEventInfo!.data));
+            expect(mktEventData.head).toBe(13);
+        }
+
+        //Increasing the quantity needs free coin balance, user3 has none until events are consumed
+        {
+            const sig = createAndSendAmendOrderIx(user3, userMarketAccount3, {order_id: orderId, side: Side.Ask, new_price: BigInt(60), new_quantity: BigInt(3)});
+            expect(sig).toBeInstanceOf(FailedTransactionMetadata);
+            expect(customErrorCode(sig as FailedTransactionMetadata)).toBe(OrderBookError.InsufficientFunds);
+        }
+
+        //Current OrderBook
+        // ASK
+        // 70 | 1     (user2)
+        // 60 | 2     (user3)
+        // ----------
+        //
+        // BID
+    });
+
+    test("Cancel All Orders by user3", async () => {
+        //Bid by user3
+        //Price: 20
+        //Qty: 1
+        {
+            let args = {
+                side: 0,
+                limit_price: BigInt(20),
+                coin_qty: BigInt(1),
+                order_type: OrderType.Limit,
+                pc_qty_limit: BigInt(0),
+                self_trade_behavior: SelfTradeBehavior.CancelMaker,
+                client_order_id: BigInt(0),
+                expiry_timestamp: null,
+
+                display_quantity: BigInt(0),
+
+                peg_offset: null,
+                referrer: null,
+                match_limit: null,
+                match_limit_behavior: MatchLimitBehavior.FailOrder
+            }
+            const sig = createAndSendPlaceOrderIx(user3, userMarketAccount3, openOrderAccount3, userPcAta3, pcVault, args);
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+        }
+
+        //Current OrderBook
+        // ASK
+        // 70 | 1     (user2)
+        // 60 | 2     (user3)
+        // ----------
+        // 20 | 1     (user3)
+        // BID
+
+        //Cancel all bids only
+        {
+            const sig = createAndSendCancelAllOrdersIx(user3, openOrderAccount3, {side: Side.Bid, limit: 10});
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+
+            let bidsInfo = svm.getAccount(bids.publicKey);
+            //@ts-ignore
+            const bidsData = new OrderBook(borsh.deserialize(OrderBookSchema, bidsInfo!.data));
+            expect(bidsData.slots_filled).toBe(0);
+
+            let asksInfo = svm.getAccount(asks.publicKey);
+            //@ts-ignore
+            const asksData = new OrderBook(borsh.deserialize(OrderBookSchema, asksInfo!.data));
+            expect(asksData.slots_filled).toBe(2);
+
+            let mktEventInfo = svm.getAccount(marketEventsAccount.publicKey);
+            //@ts-ignore
+            const mktEventData = new MarketEventsAccount(borsh.deserialize(MarketEventsAccountSchema, mktEventInfo!.data));
+            expect(mktEventData.head).toBe(14);
+            let event = mktEventData.events[13];
+            expect(event.event_type).toBe(EventType.Out);
+            expect(event.side).toBe(Side.Bid);
+            expect(new PublicKey(event.maker)).toStrictEqual(user3.publicKey);
+            expect(event.pc_qty).toBe(BigInt(20));
+        }
+
+        //Cancel everything left on both sides, user2's ask stays
+        {
+            const sig = createAndSendCancelAllOrdersIx(user3, openOrderAccount3, {side: null, limit: 10});
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+
+            let asksInfo = svm.getAccount(asks.publicKey);
+            //@ts-ignore
+            const asksData = new OrderBook(borsh.deserialize(OrderBookSchema, asksInfo!.data));
+            expect(asksData.slots_filled).toBe(1);
+            expect(new PublicKey(asksData.orders[0].owner)).toStrictEqual(user2.publicKey);
+
+            let mktEventInfo = svm.getAccount(marketEventsAccount.publicKey);
+            //@ts-ignore
+            const mktEventData = new MarketEventsAccount(borsh.deserialize(MarketEventsAccountSchema, mktEventInfo!.data));
+            expect(mktEventData.head).toBe(15);
+            let event = mktEventData.events[14];
+            expect(event.event_type).toBe(EventType.Out);
+            expect(event.side).toBe(Side.Ask);
+            expect(event.coin_qty).toBe(BigInt(2));
+        }
+
+        //Current OrderBook
+        // ASK
+        // 70 | 1     (user2)
+        // ----------
+        //
+        // BID
+    });
+
+    test("Cancel and Replace by user3 reuses freed funds", async () => {
+        //Bid by user3
+        //Price: 50
+        //Qty: 2
+        {
+            let args = {
+                side: 0,
+                limit_price: BigInt(50),
+                coin_qty: BigInt(2),
+                order_type: OrderType.Limit,
+                pc_qty_limit: BigInt(0),
+                self_trade_behavior: SelfTradeBehavior.CancelMaker,
+                client_order_id: BigInt(0),
+                expiry_timestamp: null,
+
+                display_quantity: BigInt(0),
+
+                peg_offset: null,
+                referrer: null,
+                match_limit: null,
+                match_limit_behavior: MatchLimitBehavior.FailOrder
+            }
+            const sig = createAndSendPlaceOrderIx(user3, userMarketAccount3, openOrderAccount3, userPcAta3, pcVault, args);
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+        }
+
+        let bidsInfo = svm.getAccount(bids.publicKey);
+        //@ts-ignore
+        let bidsData = new OrderBook(borsh.deserialize(OrderBookSchema, bidsInfo!.data));
+        expect(bidsData.slots_filled).toBe(1);
+        const orderId = bidsData.orders[0].order_id;
+
+        let umaInfo = svm.getAccount(userMarketAccount3);
+        //@ts-ignore
+        let umaData = new UserMarketAccount(borsh.deserialize(UserMarketAccountSchema, umaInfo!.data));
+        const freePcBefore = umaData.free_pc;
+        const lockedPcBefore = umaData.locked_pc;
+        const pcAtaBefore = AccountLayout.decode(svm.getAccount(userPcAta3)!.data).amount;
+
+        //Current OrderBook
+        // ASK
+        // 70 | 1     (user2)
+        // ----------
+        // 50 | 2     (user3)
+        // BID
+
+        //Replace the bid at 50 with a bid at 55, the 100 pc released by the cancel pays for most of it
+        {
+            let args = {
+                cancel_orders: [{order_id: orderId, side: Side.Bid}],
+                new_orders: [{
+                    side: 0,
+                    limit_price: BigInt(55),
+                    coin_qty: BigInt(2),
+                    order_type: OrderType.Limit,
+                    pc_qty_limit: BigInt(0),
+                    self_trade_behavior: SelfTradeBehavior.CancelMaker,
+                    client_order_id: BigInt(0),
+                    expiry_timestamp: null,
+
+                    display_quantity: BigInt(0),
+
+                    peg_offset: null,
+                    referrer: null,
+                    match_limit: null,
+                    match_limit_behavior: MatchLimitBehavior.FailOrder
+                }]
+            }
+            const sig = createAndSendCancelAndReplaceIx(user3, userMarketAccount3, openOrderAccount3, userCoinAta3, userPcAta3, args);
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+
+            bidsInfo = svm.getAccount(bids.publicKey);
+            //@ts-ignore
+            bidsData = new OrderBook(borsh.deserialize(OrderBookSchema, bidsInfo!.data));
+            expect(bidsData.slots_filled).toBe(1);
+            expect(bidsData.orders[0].price).toBe(BigInt(55));
+            expect(bidsData.orders[0].order_id).not.toBe(orderId);
+
+            umaInfo = svm.getAccount(userMarketAccount3);
+            //@ts-ignore
+            umaData = new UserMarketAccount(borsh.deserialize(UserMarketAccountSchema, umaInfo!.data));
+            expect(umaData.free_pc).toBe(freePcBefore);
+            expect(umaData.locked_pc).toBe(lockedPcBefore + BigInt(10));
+            expect(AccountLayout.decode(svm.getAccount(userPcAta3)!.data).amount).toBe(pcAtaBefore - BigInt(10));   //only the missing 10 is deposited
+
+            //cancels settle directly, nothing is added to the event queue
+            let mktEventInfo = svm.getAccount(marketEventsAccount.publicKey);
+            //@ts-ignore
+            const mktEventData = new MarketEventsAccount(borsh.deserialize(MarketEventsAccountSchema, mktEventInfo!.data));
+            expect(mktEventData.head).toBe(15);
+        }
+
+        //Current OrderBook
+        // ASK
+        // 70 | 1     (user2)
+        // ----------
+        // 55 | 2     (user3)
+        // BID
+    });
+
+    test("Create Orders places a ladder of quotes by user3", async () => {
+        const coinAtaBefore = AccountLayout.decode(svm.getAccount(userCoinAta3)!.data).amount;
+        const pcAtaBefore = AccountLayout.decode(svm.getAccount(userPcAta3)!.data).amount;
+
+        //Bids at 40 and 30 need 110 pc and asks at 90 and 100 need 2 coin, all from the ATAs
+        {
+            const orders = [[0, 40, 2], [0, 30, 1], [1, 90, 1], [1, 100, 1]].map(([side, price, qty]) => {
+                return {
+                    side,
+                    limit_price: BigInt(price),
+                    coin_qty: BigInt(qty),
+                    order_type: OrderType.Limit,
+                    pc_qty_limit: BigInt(0),
+                    self_trade_behavior: SelfTradeBehavior.CancelMaker,
+                    client_order_id: BigInt(0),
+                    expiry_timestamp: null,
+
+                    display_quantity: BigInt(0),
+
+                    peg_offset: null,
+                    referrer: null,
+                    match_limit: null,
+                    match_limit_behavior: MatchLimitBehavior.FailOrder
+                }
+            });
+            const sig = createAndSendCreateOrdersIx(user3, userMarketAccount3, openOrderAccount3, userCoinAta3, userPcAta3, orders);
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+        }
+
+        let bidsInfo = svm.getAccount(bids.publicKey);
+        //@ts-ignore
+        const bidsData = new OrderBook(borsh.deserialize(OrderBookSchema, bidsInfo!.data));
+        expect(bidsData.slots_filled).toBe(3);
+        expect(bidsData.orders.slice(0, 3).map(order => order.price)).toStrictEqual([BigInt(55), BigInt(40), BigInt(30)]);
+
+        let asksInfo = svm.getAccount(asks.publicKey);
+        //@ts-ignore
+        const asksData = new OrderBook(borsh.deserialize(OrderBookSchema, asksInfo!.data));
+        expect(asksData.slots_filled).toBe(3);
+        expect(asksData.orders.slice(0, 3).map(order => order.price)).toStrictEqual([BigInt(70), BigInt(90), BigInt(100)]);
+
+        expect(AccountLayout.decode(svm.getAccount(userCoinAta3)!.data).amount).toBe(coinAtaBefore - BigInt(2));
+        expect(AccountLayout.decode(svm.getAccount(userPcAta3)!.data).amount).toBe(pcAtaBefore - BigInt(110));
+
+        //A trader whose accounts do not exist yet is told to place a first order with CreateOrder
+        {
+            const trader = createTrader();
+            const order = {
+                side: Side.Bid,
+                limit_price: BigInt(5),
+                coin_qty: BigInt(1),
+                order_type: OrderType.Limit,
+                pc_qty_limit: BigInt(0),
+                self_trade_behavior: SelfTradeBehavior.CancelMaker,
+                client_order_id: BigInt(0),
+                expiry_timestamp: null,
+                display_quantity: BigInt(0),
+                peg_offset: null,
+                referrer: null,
+                match_limit: null,
+                match_limit_behavior: MatchLimitBehavior.FailOrder
+            };
+            const sig = createAndSendCreateOrdersIx(trader.trader, trader.userMarket, trader.openOrder, trader.coinAta, trader.pcAta, [order]);
+            expect(sig).toBeInstanceOf(FailedTransactionMetadata);
+            expect(customErrorCode(sig as FailedTransactionMetadata)).toBe(OrderBookError.UserAccountsNotCreated);
+        }
+
+        //Current OrderBook
+        // ASK
+        // 100 | 1     (user3)
+        // 90  | 1     (user3)
+        // 70  | 1     (user2)
+        // ----------
+        // 55  | 2     (user3)
+        // 40  | 2     (user3)
+        // 30  | 1     (user3)
+        // BID
+    });
+
+    test("Iceberg orders refill from their reserve", async () => {
+        //top up user2 with coin
+        createAndSendMintToIx(accountsAuthority, user2, userCoinAta2, userPcAta2, coinMint.publicKey, pcMint.publicKey);
+
+        //Iceberg ask by user3 showing 2 of 5 at 80
+        {
+            let args = {
+                side: 1,
+                limit_price: BigInt(80),
+                coin_qty: BigInt(5),
+                order_type: OrderType.Limit,
+                pc_qty_limit: BigInt(0),
+                self_trade_behavior: SelfTradeBehavior.CancelMaker,
+                client_order_id: BigInt(0),
+                expiry_timestamp: null,
+                display_quantity: BigInt(2),
+                peg_offset: null,
+                referrer: null,
+                match_limit: null,
+                match_limit_behavior: MatchLimitBehavior.FailOrder
+            }
+            const sig = createAndSendPlaceOrderIx(user3, userMarketAccount3, openOrderAccount3, userCoinAta3, coinVault, args);
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+        }
+
+        let asksInfo = svm.getAccount(asks.publicKey);
+        //@ts-ignore
+        let asksData = new OrderBook(borsh.deserialize(OrderBookSchema, asksInfo!.data));
+        expect(asksData.slots_filled).toBe(4);
+        expect(asksData.orders[1].price).toBe(BigInt(80));
+        expect(asksData.orders[1].quantity).toBe(BigInt(2));
+        expect(asksData.orders[1].reserve_quantity).toBe(BigInt(3));
+        const icebergOrderId = asksData.orders[1].order_id;
+
+        //Ask by user2 at 80 queues behind the iceberg
+        {
+            let args = {
+                side: 1,
+                limit_price: BigInt(80),
+                coin_qty: BigInt(1),
+                order_type: OrderType.Limit,
+                pc_qty_limit: BigInt(0),
+                self_trade_behavior: SelfTradeBehavior.CancelMaker,
+                client_order_id: BigInt(0),
+                expiry_timestamp: null,
+                display_quantity: BigInt(0),
+                peg_offset: null,
+                referrer: null,
+                match_limit: null,
+                match_limit_behavior: MatchLimitBehavior.FailOrder
+            }
+            const sig = createAndSendPlaceOrderIx(user2, userMarketAccount2, openOrderAccount2, userCoinAta2, coinVault, args);
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+        }
+
+        //Current OrderBook
+        // ASK
+        // 100 | 1     (user3)
+        // 90  | 1     (user3)
+        // 80  | 1     (user2)
+        // 80  | 2 + 3 hidden (user3)
+        // 70  | 1     (user2)
+        // ----------
+        // 55  | 2     (user3)
+        // 40  | 2     (user3)
+        // 30  | 1     (user3)
+        // BID
+
+        //Bid by user for 3 at 80 fills user2 at 70 and the visible 2 of the iceberg
+        {
+            let args = {
+                side: 0,
+                limit_price: BigInt(80),
+                coin_qty: BigInt(3),
+                order_type: OrderType.Limit,
+                pc_qty_limit: BigInt(0),
+                self_trade_behavior: SelfTradeBehavior.CancelMaker,
+                client_order_id: BigInt(0),
+                expiry_timestamp: null,
+                display_quantity: BigInt(0),
+                peg_offset: null,
+                referrer: null,
+                match_limit: null,
+                match_limit_behavior: MatchLimitBehavior.FailOrder
+            }
+            const sig = createAndSendPlaceOrderIx(user, userMarketAccount, openOrderAccount, userPcAta, pcVault, args);
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+
+            //the iceberg refilled 2 from its reserve and moved behind user2's ask at 80
+            asksInfo = svm.getAccount(asks.publicKey);
+            //@ts-ignore
+            asksData = new OrderBook(borsh.deserialize(OrderBookSchema, asksInfo!.data));
+            expect(asksData.slots_filled).toBe(4);
+            expect(asksData.orders[0].price).toBe(BigInt(80));
+            expect(new PublicKey(asksData.orders[0].owner)).toStrictEqual(user2.publicKey);
+            expect(asksData.orders[1].order_id).toBe(icebergOrderId);
+            expect(asksData.orders[1].quantity).toBe(BigInt(4));
+            expect(asksData.orders[1].filled_quantity).toBe(BigInt(2));
+            expect(asksData.orders[1].reserve_quantity).toBe(BigInt(1));
+
+            let mktEventInfo = svm.getAccount(marketEventsAccount.publicKey);
+            //@ts-ignore
+            const mktEventData = new MarketEventsAccount(borsh.deserialize(MarketEventsAccountSchema, mktEventInfo!.data));
+            expect(mktEventData.head).toBe(17);
+            let event = mktEventData.events[16];
+            expect(event.event_type).toBe(EventType.Fill);
+            expect(new PublicKey(event.maker)).toStrictEqual(user3.publicKey);
+            expect(event.coin_qty).toBe(BigInt(2));
+            expect(event.maker_order_id).toBe(icebergOrderId);
+        }
+
+        //Current OrderBook
+        // ASK
+        // 100 | 1     (user3)
+        // 90  | 1     (user3)
+        // 80  | 2 + 1 hidden (user3)
+        // 80  | 1     (user2)
+        // ----------
+        // 55  | 2     (user3)
+        // 40  | 2     (user3)
+        // 30  | 1     (user3)
+        // BID
+    });
+
+    test("Stop and stop limit trigger orders", async () => {
+        let marketInfo = svm.getAccount(market);
+        //@ts-ignore
+        let marketData = new MarketState(borsh.deserialize(MarketStateSchema, marketInfo!.data));
+        expect(marketData.last_traded_price).toBe(BigInt(80));
+
+        //Stop bid by user2 triggering at 85, buys 1 up to 100 and drops the rest
+        {
+            let args = {
+                side: Side.Bid,
+                trigger_price: BigInt(85),
+                limit_price: BigInt(100),
+                coin_qty: BigInt(1),
+                order_type: OrderType.ImmediateOrCancel,
+                self_trade_behavior: SelfTradeBehavior.CancelMaker,
+                client_order_id: BigInt(0)
+            }
+            const sig = createAndSendCreateTriggerOrderIx(user2, userMarketAccount2, userPcAta2, pcVault, args);
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+            //locks 1 at 100 and the taker fee on it
+            let triggerOrdersInfo = svm.getAccount(triggerOrders.publicKey);
+            //@ts-ignore
+            const triggerOrdersData = new TriggerOrderBook(borsh.deserialize(TriggerOrderBookSchema, triggerOrdersInfo!.data));
+            expect(triggerOrdersData.orders[0].pc_qty).toBe(BigInt(100 + 1));
+        }
+
+        //A stop bid at 75 would trigger right away and is rejected
+        {
+            let args = {
+                side: Side.Bid,
+                trigger_price: BigInt(75),
+                limit_price: BigInt(100),
+                coin_qty: BigInt(1),
+                order_type: OrderType.ImmediateOrCancel,
+                self_trade_behavior: SelfTradeBehavior.CancelMaker,
+                client_order_id: BigInt(0)
+            }
+            const sig = createAndSendCreateTriggerOrderIx(user2, userMarketAccount2, userPcAta2, pcVault, args);
+            expect(sig).toBeInstanceOf(FailedTransactionMetadata);
+        }
+
+        //Stop limit ask by user2 triggering at 50, then cancelled
+        {
+            let args = {
+                side: Side.Ask,
+                trigger_price: BigInt(50),
+                limit_price: BigInt(45),
+                coin_qty: BigInt(1),
+                order_type: OrderType.Limit,
+                self_trade_behavior: SelfTradeBehavior.CancelMaker,
+                client_order_id: BigInt(0)
+            }
+            let sig = createAndSendCreateTriggerOrderIx(user2, userMarketAccount2, userCoinAta2, coinVault, args);
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+
+            let triggerOrdersInfo = svm.getAccount(triggerOrders.publicKey);
+            //@ts-ignore
+            let triggerOrdersData = new TriggerOrderBook(borsh.deserialize(TriggerOrderBookSchema, triggerOrdersInfo!.data));
+            expect(triggerOrdersData.slots_filled).toBe(2);
+            expect(triggerOrdersData.orders[0].trigger_price).toBe(BigInt(50));
+            expect(triggerOrdersData.orders[0].trigger_id).toBe(BigInt(1));
+
+            let umaInfo = svm.getAccount(userMarketAccount2);
+            //@ts-ignore
+            let umaData = new UserMarketAccount(borsh.deserialize(UserMarketAccountSchema, umaInfo!.data));
+            const freeCoinBefore = umaData.free_coin;
+
+            sig = createAndSendCancelTriggerOrderIx(user2, userMarketAccount2, BigInt(1));
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+
+            triggerOrdersInfo = svm.getAccount(triggerOrders.publicKey);
+            //@ts-ignore
+            triggerOrdersData = new TriggerOrderBook(borsh.deserialize(TriggerOrderBookSchema, triggerOrdersInfo!.data));
+            expect(triggerOrdersData.slots_filled).toBe(1);
+            expect(triggerOrdersData.orders[0].trigger_id).toBe(BigInt(0));
+
+            umaInfo = svm.getAccount(userMarketAccount2);
+            //@ts-ignore
+            umaData = new UserMarketAccount(borsh.deserialize(UserMarketAccountSchema, umaInfo!.data));
+            expect(umaData.free_coin).toBe(freeCoinBefore + BigInt(1));
+        }
+
+        //Stop limit bid by user2 for 3 at 100 locks the taker fee rounded up on every lot, then cancelled
+        {
+            let args = {
+                side: Side.Bid,
+                trigger_price: BigInt(95),
+                limit_price: BigInt(100),
+                coin_qty: BigInt(3),
+                order_type: OrderType.Limit,
+                self_trade_behavior: SelfTradeBehavior.CancelMaker,
+                client_order_id: BigInt(0)
+            }
+            let sig = createAndSendCreateTriggerOrderIx(user2, userMarketAccount2, userPcAta2, pcVault, args);
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+
+            let triggerOrdersInfo = svm.getAccount(triggerOrders.publicKey);
+            //@ts-ignore
+            const triggerOrdersData = new TriggerOrderBook(borsh.deserialize(TriggerOrderBookSchema, triggerOrdersInfo!.data));
+            expect(triggerOrdersData.slots_filled).toBe(2);
+            const triggerOrder = triggerOrdersData.orders.find((order) => order.trigger_id == BigInt(2));
+            expect(triggerOrder!.pc_qty).toBe(BigInt(300 + 3));
+
+            let umaInfo = svm.getAccount(userMarketAccount2);
+            //@ts-ignore
+            let umaData = new UserMarketAccount(borsh.deserialize(UserMarketAccountSchema, umaInfo!.data));
+            const freePcBefore = umaData.free_pc;
+
+            sig = createAndSendCancelTriggerOrderIx(user2, userMarketAccount2, BigInt(2));
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+
+            umaInfo = svm.getAccount(userMarketAccount2);
+            //@ts-ignore
+            umaData = new UserMarketAccount(borsh.deserialize(UserMarketAccountSchema, umaInfo!.data));
+            expect(umaData.free_pc).toBe(freePcBefore + BigInt(300 + 3));
+        }
+
+        //Nothing triggers while the market trades at 80
+        {
+            const sig = createAndSendExecuteTriggerOrdersIx(10, [userMarketAccount2, openOrderAccount2]);
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+
+            let triggerOrdersInfo = svm.getAccount(triggerOrders.publicKey);
+            //@ts-ignore
+            const triggerOrdersData = new TriggerOrderBook(borsh.deserialize(TriggerOrderBookSchema, triggerOrdersInfo!.data));
+            expect(triggerOrdersData.slots_filled).toBe(1);
+        }
+
+        //Bid by user for 5 at 90 takes every ask up to 90, last traded price moves to 90
+        {
+            let args = {
+                side: 0,
+                limit_price: BigInt(90),
+                coin_qty: BigInt(5),
+                order_type: OrderType.Limit,
+                pc_qty_limit: BigInt(0),
+                self_trade_behavior: SelfTradeBehavior.CancelMaker,
+                client_order_id: BigInt(0),
+                expiry_timestamp: null,
+                display_quantity: BigInt(0),
+                peg_offset: null,
+                referrer: null,
+                match_limit: null,
+                match_limit_behavior: MatchLimitBehavior.FailOrder
+            }
+            const sig = createAndSendPlaceOrderIx(user, userMarketAccount, openOrderAccount, userPcAta, pcVault, args);
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+
+            marketInfo = svm.getAccount(market);
+            //@ts-ignore
+            marketData = new MarketState(borsh.deserialize(MarketStateSchema, marketInfo!.data));
+            expect(marketData.last_traded_price).toBe(BigInt(90));
+        }
+
+        //Current OrderBook
+        // ASK
+        // 100 | 1     (user3)
+        // ----------
+        // 55  | 2     (user3)
+        // 40  | 2     (user3)
+        // 30  | 1     (user3)
+        // BID
+
+        //Crank places the triggered stop bid, which takes the ask at 100
+        {
+            const sig = createAndSendExecuteTriggerOrdersIx(10, [userMarketAccount2, openOrderAccount2]);
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+
+            let triggerOrdersInfo = svm.getAccount(triggerOrders.publicKey);
+            //@ts-ignore
+            const triggerOrdersData = new TriggerOrderBook(borsh.deserialize(TriggerOrderBookSchema, triggerOrdersInfo!.data));
+            expect(triggerOrdersData.slots_filled).toBe(0);
+
+            let asksInfo = svm.getAccount(asks.publicKey);
+            //@ts-ignore
+            const asksData = new OrderBook(borsh.deserialize(OrderBookSchema, asksInfo!.data));
+            expect(asksData.slots_filled).toBe(0);
+
+            let mktEventInfo = svm.getAccount(marketEventsAccount.publicKey);
+            //@ts-ignore
+            const mktEventData = new MarketEventsAccount(borsh.deserialize(MarketEventsAccountSchema, mktEventInfo!.data));
+            expect(mktEventData.head).toBe(22);
+            let event = mktEventData.events[21];
+            expect(event.event_type).toBe(EventType.Fill);
+            expect(new PublicKey(event.maker)).toStrictEqual(user3.publicKey);
+            expect(new PublicKey(event.taker)).toStrictEqual(user2.publicKey);
+            expect(event.pc_qty).toBe(BigInt(100));
+
+            marketInfo = svm.getAccount(market);
+            //@ts-ignore
+            marketData = new MarketState(borsh.deserialize(MarketStateSchema, marketInfo!.data));
+            expect(marketData.last_traded_price).toBe(BigInt(100));
+        }
+
+        //Current OrderBook
+        // ASK
+        //
+        // ----------
+        // 55  | 2     (user3)
+        // 40  | 2     (user3)
+        // 30  | 1     (user3)
+        // BID
+    });
+
+    test("Oracle pegged orders follow the oracle price", async () => {
+        //top up user2 with coin
+        createAndSendMintToIx(accountsAuthority, user2, userCoinAta2, userPcAta2, coinMint.publicKey, pcMint.publicKey);
+        setOraclePrice(BigInt(60));
+
+        //Pegged ask by user2 at oracle + 5, never below 50
+        {
+            let args = {
+                side: 1,
+                limit_price: BigInt(50),
+                coin_qty: BigInt(2),
+                order_type: OrderType.Limit,
+                pc_qty_limit: BigInt(0),
+                self_trade_behavior: SelfTradeBehavior.CancelMaker,
+                client_order_id: BigInt(0),
+                expiry_timestamp: null,
+                display_quantity: BigInt(0),
+                peg_offset: BigInt(5),
+                referrer: null,
+                match_limit: null,
+                match_limit_behavior: MatchLimitBehavior.FailOrder
+            }
+            const sig = createAndSendPlaceOrderIx(user2, userMarketAccount2, openOrderAccount2, userCoinAta2, coinVault, args);
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+
+            let asksInfo = svm.getAccount(asks.publicKey);
+            //@ts-ignore
+            const asksData = new OrderBook(borsh.deserialize(OrderBookSchema, asksInfo!.data));
+            expect(asksData.slots_filled).toBe(1);
+            expect(asksData.orders[0].pegged).toBe(1);
+            expect(asksData.orders[0].peg_offset).toBe(BigInt(5));
+            expect(asksData.orders[0].price).toBe(BigInt(50));
+        }
+
+        //Current OrderBook
+        // ASK
+        // oracle + 5 | 2  (user2, 65 now)
+        // ----------
+        // 55  | 2     (user3)
+        // 40  | 2     (user3)
+        // 30  | 1     (user3)
+        // BID
+
+        //Bid by user for 1 at 70 fills the pegged ask at 65
+        {
+            let args = {
+                side: 0,
+                limit_price: BigInt(70),
+                coin_qty: BigInt(1),
+                order_type: OrderType.ImmediateOrCancel,
+                pc_qty_limit: BigInt(0),
+                self_trade_behavior: SelfTradeBehavior.CancelMaker,
+                client_order_id: BigInt(0),
+                expiry_timestamp: null,
+                display_quantity: BigInt(0),
+                peg_offset: null,
+                referrer: null,
+                match_limit: null,
+                match_limit_behavior: MatchLimitBehavior.FailOrder
+            }
+            const sig = createAndSendPlaceOrderIx(user, userMarketAccount, openOrderAccount, userPcAta, pcVault, args);
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+
+            let mktEventInfo = svm.getAccount(marketEventsAccount.publicKey);
+            //@ts-ignore
+            const mktEventData = new MarketEventsAccount(borsh.deserialize(MarketEventsAccountSchema, mktEventInfo!.data));
+            expect(mktEventData.head).toBe(23);
+            let event = mktEventData.events[22];
+            expect(event.event_type).toBe(EventType.Fill);
+            expect(new PublicKey(event.maker)).toStrictEqual(user2.publicKey);
+            expect(event.coin_qty).toBe(BigInt(1));
+            expect(event.pc_qty).toBe(BigInt(65));
+
+            let marketInfo = svm.getAccount(market);
+            //@ts-ignore
+            const marketData = new MarketState(borsh.deserialize(MarketStateSchema, marketInfo!.data));
+            expect(marketData.last_traded_price).toBe(BigInt(65));
+        }
+
+        //Oracle moves to 80, the same bid no longer reaches the pegged ask at 85
+        {
+            setOraclePrice(BigInt(80));
+
+            let args = {
+                side: 0,
+                limit_price: BigInt(70),
+                coin_qty: BigInt(1),
+                order_type: OrderType.ImmediateOrCancel,
+                pc_qty_limit: BigInt(0),
+                self_trade_behavior: SelfTradeBehavior.CancelMaker,
+                client_order_id: BigInt(0),
+                expiry_timestamp: null,
+                display_quantity: BigInt(0),
+                peg_offset: null,
+                referrer: null,
+                match_limit: null,
+                match_limit_behavior: MatchLimitBehavior.FailOrder
+            }
+            const sig = createAndSendPlaceOrderIx(user, userMarketAccount, openOrderAccount, userPcAta, pcVault, args);
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+
+            let mktEventInfo = svm.getAccount(marketEventsAccount.publicKey);
+            //@ts-ignore
+            const mktEventData = new MarketEventsAccount(borsh.deserialize(MarketEventsAccountSchema, mktEventInfo!.data));
+            expect(mktEventData.head).toBe(23);
+        }
+
+        //Post only pegged bid by user3 at oracle - 10 rests at 70, behind nothing on the fixed bids
+        {
+            let args = {
+                side: 0,
+                limit_price: BigInt(75),
+                coin_qty: BigInt(1),
+                order_type: OrderType.PostOnly,
+                pc_qty_limit: BigInt(0),
+                self_trade_behavior: SelfTradeBehavior.CancelMaker,
+                client_order_id: BigInt(0),
+                expiry_timestamp: null,
+                display_quantity: BigInt(0),
+                peg_offset: BigInt(-10),
+                referrer: null,
+                match_limit: null,
+                match_limit_behavior: MatchLimitBehavior.FailOrder
+            }
+            const sig = createAndSendPlaceOrderIx(user3, userMarketAccount3, openOrderAccount3, userPcAta3, pcVault, args);
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+
+            let bidsInfo = svm.getAccount(bids.publicKey);
+            //@ts-ignore
+            const bidsData = new OrderBook(borsh.deserialize(OrderBookSchema, bidsInfo!.data));
+            expect(bidsData.slots_filled).toBe(4);
+            expect(bidsData.orders[3].pegged).toBe(1);
+            expect(bidsData.orders[3].peg_offset).toBe(BigInt(-10));
+        }
+
+        //Current OrderBook
+        // ASK
+        // oracle + 5  | 1  (user2, 85 now)
+        // ----------
+        // oracle - 10 | 1  (user3, 70 now, capped at 75)
+        // 55  | 2     (user3)
+        // 40  | 2     (user3)
+        // 30  | 1     (user3)
+        // BID
+
+        //Ask by user for 1 at 50 takes the pegged bid at 70 ahead of the fixed bid at 55
+        {
+            let args = {
+                side: 1,
+                limit_price: BigInt(50),
+                coin_qty: BigInt(1),
+                order_type: OrderType.ImmediateOrCancel,
+                pc_qty_limit: BigInt(0),
+                self_trade_behavior: SelfTradeBehavior.CancelMaker,
+                client_order_id: BigInt(0),
+                expiry_timestamp: null,
+                display_quantity: BigInt(0),
+                peg_offset: null,
+                referrer: null,
+                match_limit: null,
+                match_limit_behavior: MatchLimitBehavior.FailOrder
+            }
+            const sig = createAndSendPlaceOrderIx(user, userMarketAccount, openOrderAccount, userCoinAta, coinVault, args);
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+
+            let mktEventInfo = svm.getAccount(marketEventsAccount.publicKey);
+            //@ts-ignore
+            const mktEventData = new MarketEventsAccount(borsh.deserialize(MarketEventsAccountSchema, mktEventInfo!.data));
+            expect(mktEventData.head).toBe(24);
+            let event = mktEventData.events[23];
+            expect(event.event_type).toBe(EventType.Fill);
+            expect(new PublicKey(event.maker)).toStrictEqual(user3.publicKey);
+            expect(event.pc_qty).toBe(BigInt(70));
+
+            let bidsInfo = svm.getAccount(bids.publicKey);
+            //@ts-ignore
+            const bidsData = new OrderBook(borsh.deserialize(OrderBookSchema, bidsInfo!.data));
+            expect(bidsData.slots_filled).toBe(3);
+        }
+
+        //A stale oracle keeps pegged orders out of matching and rejects new ones
+        {
+            const clock = svm.getClock();
+            clock.unixTimestamp = clock.unixTimestamp + BigInt(120);
+            svm.setClock(clock);
+
+            let args = {
+                side: 1,
+                limit_price: BigInt(50),
+                coin_qty: BigInt(1),
+                order_type: OrderType.Limit,
+                pc_qty_limit: BigInt(0),
+                self_trade_behavior: SelfTradeBehavior.CancelMaker,
+                client_order_id: BigInt(0),
+                expiry_timestamp: null,
+                display_quantity: BigInt(0),
+                peg_offset: BigInt(0),
+                referrer: null,
+                match_limit: null,
+                match_limit_behavior: MatchLimitBehavior.FailOrder
+            }
+            const sig = createAndSendPlaceOrderIx(user2, userMarketAccount2, openOrderAccount2, userCoinAta2, coinVault, args);
+            expect(sig).toBeInstanceOf(FailedTransactionMetadata);
+
+            setOraclePrice(BigInt(80));
+        }
+
+        //Current OrderBook
+        // ASK
+        // oracle + 5 | 1  (user2, 85 now)
+        // ----------
+        // 55  | 2     (user3)
+        // 40  | 2     (user3)
+        // 30  | 1     (user3)
+        // BID
+    });
+
+    test("Tick size and minimum order size are enforced", async () => {
+        const baseArgs = {
+            side: 0,
+            limit_price: BigInt(45),
+            coin_qty: BigInt(1),
+            order_type: OrderType.Limit,
+            pc_qty_limit: BigInt(0),
+            self_trade_behavior: SelfTradeBehavior.CancelMaker,
+            client_order_id: BigInt(0),
+            expiry_timestamp: null,
+            display_quantity: BigInt(0),
+            peg_offset: null,
+            referrer: null,
+            match_limit: null,
+            match_limit_behavior: MatchLimitBehavior.FailOrder
+        };
+
+        //Price off the tick of 5
+        {
+            const sig = createAndSendPlaceOrderIx(user, userMarketAccount, openOrderAccount, userPcAta, pcVault, {...baseArgs, limit_price: BigInt(47)});
+            expect(sig).toBeInstanceOf(FailedTransactionMetadata);
+            expect(customErrorCode(sig as FailedTransactionMetadata)).toBe(OrderBookError.InvalidTickSize);
+        }
+
+        //Zero price
+        {
+            const sig = createAndSendPlaceOrderIx(user, userMarketAccount, openOrderAccount, userPcAta, pcVault, {...baseArgs, limit_price: BigInt(0)});
+            expect(sig).toBeInstanceOf(FailedTransactionMetadata);
+            expect(customErrorCode(sig as FailedTransactionMetadata)).toBe(OrderBookError.InvalidTickSize);
+        }
+
+        //Quantity below the minimum order size
+        {
+            const sig = createAndSendPlaceOrderIx(user, userMarketAccount, openOrderAccount, userPcAta, pcVault, {...baseArgs, coin_qty: BigInt(0)});
+            expect(sig).toBeInstanceOf(FailedTransactionMetadata);
+            expect(customErrorCode(sig as FailedTransactionMetadata)).toBe(OrderBookError.BelowMinOrderSize);
+        }
+
+        //Peg offset off the tick
+        {
+            setOraclePrice(BigInt(80));
+            const sig = createAndSendPlaceOrderIx(user, userMarketAccount, openOrderAccount, userPcAta, pcVault, {...baseArgs, peg_offset: BigInt(-33)});
+            expect(sig).toBeInstanceOf(FailedTransactionMetadata);
+            expect(customErrorCode(sig as FailedTransactionMetadata)).toBe(OrderBookError.InvalidTickSize);
+        }
+
+        let bidsInfo = svm.getAccount(bids.publicKey);
+        //@ts-ignore
+        const bidsData = new OrderBook(borsh.deserialize(OrderBookSchema, bidsInfo!.data));
+        expect(bidsData.slots_filled).toBe(3);
+    });
+
+    test("Accrued fees are swept by the market authority", async () => {
+        //fills consumed so far paid a taker fee of 1 each, maker rebates rounded down to 0
+        let marketInfo = svm.getAccount(market);
+        //@ts-ignore
+        let marketData = new MarketState(borsh.deserialize(MarketStateSchema, marketInfo!.data));
+        expect(marketData.fees_accrued).toBe(BigInt(2));
+
+        const feeDestinationBefore = AccountLayout.decode(svm.getAccount(feeDestination)!.data).amount;
+
+        //Anyone other than the authority is rejected
+        {
+            const sig = createAndSendSweepFeesIx(user3);
+            expect(sig).toBeInstanceOf(FailedTransactionMetadata);
+        }
+
+        {
+            const sig = createAndSendSweepFeesIx(accountsAuthority);
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+
+            expect(AccountLayout.decode(svm.getAccount(feeDestination)!.data).amount).toBe(feeDestinationBefore + BigInt(2));
+
+            marketInfo = svm.getAccount(market);
+            //@ts-ignore
+            marketData = new MarketState(borsh.deserialize(MarketStateSchema, marketInfo!.data));
+            expect(marketData.fees_accrued).toBe(BigInt(0));
+        }
+    });
+
+    test("Referred taker orders share their fee with the referrer", async () => {
+        //drain the events left by the previous tests
+        while (true) {
+            const sig = createAndSendConsumeEventsIx();
+            if (!sig) {
+                break;
+            }
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+        }
+
+        createAndSendMintToIx(accountsAuthority, user2, userCoinAta2, userPcAta2, coinMint.publicKey, pcMint.publicKey);
+        createAndSendMintToIx(accountsAuthority, user3, userCoinAta3, userPcAta3, coinMint.publicKey, pcMint.publicKey);
+
+        //Ask by user2 for 4 at 300
+        {
+            let args = {
+                side: 1,
+                limit_price: BigInt(300),
+                coin_qty: BigInt(4),
+                order_type: OrderType.Limit,
+                pc_qty_limit: BigInt(0),
+                self_trade_behavior: SelfTradeBehavior.CancelMaker,
+                client_order_id: BigInt(0),
+                expiry_timestamp: null,
+                display_quantity: BigInt(0),
+                peg_offset: null,
+                referrer: null,
+                match_limit: null,
+                match_limit_behavior: MatchLimitBehavior.FailOrder
+            }
+            const sig = createAndSendPlaceOrderIx(user2, userMarketAccount2, openOrderAccount2, userCoinAta2, coinVault, args);
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+        }
+
+        //Current OrderBook
+        // ASK
+        // 300        | 4  (user2)
+        // oracle + 5 | 1  (user2, 85 now)
+        // ----------
+        // 55  | 2     (user3)
+        // 40  | 2     (user3)
+        // 30  | 1     (user3)
+        // BID
+
+        const referredArgs = {
+            side: 0,
+            limit_price: BigInt(300),
+            coin_qty: BigInt(5),
+            order_type: OrderType.ImmediateOrCancel,
+            pc_qty_limit: BigInt(0),
+            self_trade_behavior: SelfTradeBehavior.CancelMaker,
+            client_order_id: BigInt(0),
+            expiry_timestamp: null,
+            display_quantity: BigInt(0),
+            peg_offset: null,
+            referrer: user.publicKey.toBytes(),
+            match_limit: null,
+            match_limit_behavior: MatchLimitBehavior.FailOrder
+        };
+
+        //The referrer's User Market account must be passed
+        {
+            const sig = createAndSendPlaceOrderIx(user3, userMarketAccount3, openOrderAccount3, userPcAta3, pcVault, referredArgs);
+            expect(sig).toBeInstanceOf(FailedTransactionMetadata);
+        }
+
+        //Self referral is rejected
+        {
+            const sig = createAndSendPlaceOrderIx(user3, userMarketAccount3, openOrderAccount3, userPcAta3, pcVault, {...referredArgs, referrer: user3.publicKey.toBytes()}, userMarketAccount3);
+            expect(sig).toBeInstanceOf(FailedTransactionMetadata);
+        }
+
+        //Bid by user3 for 5 at 300 referred by user sweeps both asks
+        {
+            const sig = createAndSendPlaceOrderIx(user3, userMarketAccount3, openOrderAccount3, userPcAta3, pcVault, referredArgs, userMarketAccount);
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+
+            let mktEventInfo = svm.getAccount(marketEventsAccount.publicKey);
+            //@ts-ignore
+            const mktEventData = new MarketEventsAccount(borsh.deserialize(MarketEventsAccountSchema, mktEventInfo!.data));
+            expect(mktEventData.size()).toBe(2);
+            for (let i = 0; i < 2; i++) {
+                let event = mktEventData.events[mktEventData.tail.valueOf() + i];
+                expect(event.event_type).toBe(EventType.Fill);
+                expect(new PublicKey(event.referrer)).toStrictEqual(user.publicKey);
+            }
+        }
+
+        let marketInfo = svm.getAccount(market);
+        //@ts-ignore
+        let marketData = new MarketState(borsh.deserialize(MarketStateSchema, marketInfo!.data));
+        const feesBefore = marketData.fees_accrued;
+        //@ts-ignore
+        const userMarketDataBefore = new UserMarketAccount(borsh.deserialize(UserMarketAccountSchema, svm.getAccount(userMarketAccount)!.data));
+
+        {
+            const sig = createAndSendConsumeEventsIx();
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+        }
+
+        //taker fees of 1 on 85 and 2 on 1200, the referrer gets half of each rounded down
+        //@ts-ignore
+        const userMarketData = new UserMarketAccount(borsh.deserialize(UserMarketAccountSchema, svm.getAccount(userMarketAccount)!.data));
+        expect(userMarketData.free_pc).toBe(userMarketDataBefore.free_pc + BigInt(1));
+
+        marketInfo = svm.getAccount(market);
+        //@ts-ignore
+        marketData = new MarketState(borsh.deserialize(MarketStateSchema, marketInfo!.data));
+        expect(marketData.fees_accrued).toBe(feesBefore + BigInt(1 + 2 - 1));
+    });
+
+    test("Match limit bounds the fills of a taker order", async () => {
+        createAndSendMintToIx(accountsAuthority, user, userCoinAta, userPcAta, coinMint.publicKey, pcMint.publicKey);
+
+        //Current OrderBook
+        // ASK
+        // ----------
+        // 55  | 2     (user3)
+        // 40  | 2     (user3)
+        // 30  | 1     (user3)
+        // BID
+
+        const baseArgs = {
+            side: 1,
+            limit_price: BigInt(30),
+            coin_qty: BigInt(5),
+            order_type: OrderType.Limit,
+            pc_qty_limit: BigInt(0),
+            self_trade_behavior: SelfTradeBehavior.CancelMaker,
+            client_order_id: BigInt(0),
+            expiry_timestamp: null,
+            display_quantity: BigInt(0),
+            peg_offset: null,
+            referrer: null,
+            match_limit: 1,
+            match_limit_behavior: MatchLimitBehavior.DropRemainder
+        };
+
+        //A match limit of 0 is rejected
+        {
+            const sig = createAndSendPlaceOrderIx(user, userMarketAccount, openOrderAccount, userCoinAta, coinVault, {...baseArgs, match_limit: 0});
+            expect(sig).toBeInstanceOf(FailedTransactionMetadata);
+        }
+
+        //Ask by user for 5 at 30 stops after one fill and drops the rest
+        {
+            const sig = createAndSendPlaceOrderIx(user, userMarketAccount, openOrderAccount, userCoinAta, coinVault, baseArgs);
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+
+            let mktEventInfo = svm.getAccount(marketEventsAccount.publicKey);
+            //@ts-ignore
+            const mktEventData = new MarketEventsAccount(borsh.deserialize(MarketEventsAccountSchema, mktEventInfo!.data));
+            expect(mktEventData.size()).toBe(1);
+            let event = mktEventData.events[mktEventData.tail.valueOf()];
+            expect(event.event_type).toBe(EventType.Fill);
+            expect(event.pc_qty).toBe(BigInt(110));
+
+            let bidsInfo = svm.getAccount(bids.publicKey);
+            //@ts-ignore
+            const bidsData = new OrderBook(borsh.deserialize(OrderBookSchema, bidsInfo!.data));
+            expect(bidsData.slots_filled).toBe(2);
+
+            let asksInfo = svm.getAccount(asks.publicKey);
+            //@ts-ignore
+            const asksData = new OrderBook(borsh.deserialize(OrderBookSchema, asksInfo!.data));
+            expect(asksData.slots_filled).toBe(0);
+        }
+
+        //Ask by user for 3 at 30 stops after one fill, its remainder would rest across the bid at 30 so it fails
+        {
+            const sig = createAndSendPlaceOrderIx(user, userMarketAccount, openOrderAccount, userCoinAta, coinVault, {...baseArgs, coin_qty: BigInt(3), match_limit_behavior: MatchLimitBehavior.FailOrder});
+            expect(sig).toBeInstanceOf(FailedTransactionMetadata);
+            expect(customErrorCode(sig as FailedTransactionMetadata)).toBe(OrderBookError.MatchLimitReached);
+
+            let bidsInfo = svm.getAccount(bids.publicKey);
+            //@ts-ignore
+            const bidsData = new OrderBook(borsh.deserialize(OrderBookSchema, bidsInfo!.data));
+            expect(bidsData.slots_filled).toBe(2);
+        }
+
+        //The same ask dropping its remainder only takes the bid at 40
+        {
+            svm.expireBlockhash();
+            const sig = createAndSendPlaceOrderIx(user, userMarketAccount, openOrderAccount, userCoinAta, coinVault, {...baseArgs, coin_qty: BigInt(3)});
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+
+            let bidsInfo = svm.getAccount(bids.publicKey);
+            //@ts-ignore
+            const bidsData = new OrderBook(borsh.deserialize(OrderBookSchema, bidsInfo!.data));
+            expect(bidsData.slots_filled).toBe(1);
+            expect(bidsData.orders[0].price).toBe(BigInt(30));
+
+            let asksInfo = svm.getAccount(asks.publicKey);
+            //@ts-ignore
+            const asksData = new OrderBook(borsh.deserialize(OrderBookSchema, asksInfo!.data));
+            expect(asksData.slots_filled).toBe(0);
+        }
+
+        //Bids by user2, 1 at 40 and 1 at 35 that expires, an ask limited to one fill still takes the expired bid out
+        {
+            createAndSendMintToIx(accountsAuthority, user2, userCoinAta2, userPcAta2, coinMint.publicKey, pcMint.publicKey);
+            const clock = svm.getClock();
+            [[40, null], [35, clock.unixTimestamp + BigInt(10)]].forEach(([price, expiry]) => {
+                const sig = createAndSendPlaceOrderIx(user2, userMarketAccount2, openOrderAccount2, userPcAta2, pcVault, {...baseArgs, side: 0, limit_price: BigInt(price!), coin_qty: BigInt(1), expiry_timestamp: expiry as bigint | null, match_limit: null});
+                expect(sig).toBeInstanceOf(TransactionMetadata);
+            });
+            clock.unixTimestamp = clock.unixTimestamp + BigInt(20);
+            svm.setClock(clock);
+
+            const sig = createAndSendPlaceOrderIx(user, userMarketAccount, openOrderAccount, userCoinAta, coinVault, {...baseArgs, limit_price: BigInt(35), coin_qty: BigInt(2), order_type: OrderType.ImmediateOrCancel});
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+
+            let mktEventInfo = svm.getAccount(marketEventsAccount.publicKey);
+            //@ts-ignore
+            const mktEventData = new MarketEventsAccount(borsh.deserialize(MarketEventsAccountSchema, mktEventInfo!.data));
+            const head = mktEventData.head.valueOf();
+            const len = mktEventData.capacity.valueOf();
+            expect(mktEventData.events[(head + len - 2) % len].event_type).toBe(EventType.Fill);
+            expect(mktEventData.events[(head + len - 1) % len].event_type).toBe(EventType.Out);
+
+            let bidsInfo = svm.getAccount(bids.publicKey);
+            //@ts-ignore
+            const bidsData = new OrderBook(borsh.deserialize(OrderBookSchema, bidsInfo!.data));
+            expect(bidsData.slots_filled).toBe(1);
+            expect(bidsData.orders[0].price).toBe(BigInt(30));
+        }
+
+        //Current OrderBook
+        // ASK
+        // ----------
+        // 30  | 1     (user3)
+        // BID
+    });
+
+    test("Orders never emit more events than the queue can hold", async () => {
+        while (true) {
+            const sig = createAndSendConsumeEventsIx();
+            if (!sig) {
+                break;
+            }
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+        }
+
+        //fill the event queue up by moving its head right behind the tail
+        const eventsAccount = svm.getAccount(marketEventsAccount.publicKey)!;
+        const fullData = Buffer.from(eventsAccount.data);
+        const tail = fullData.readUInt32LE(36);
+        fullData.writeUInt32LE((tail + MAX_EVENT - 1) % MAX_EVENT, 32);
+        svm.setAccount(marketEventsAccount.publicKey, {...eventsAccount, data: fullData});
+
+        const baseArgs = {
+            side: 1,
+            limit_price: BigInt(30),
+            coin_qty: BigInt(1),
+            order_type: OrderType.Limit,
+            pc_qty_limit: BigInt(0),
+            self_trade_behavior: SelfTradeBehavior.CancelMaker,
+            client_order_id: BigInt(0),
+            expiry_timestamp: null,
+            display_quantity: BigInt(0),
+            peg_offset: null,
+            referrer: null,
+            match_limit: null,
+            match_limit_behavior: MatchLimitBehavior.FailOrder
+        };
+
+        //A limit ask crossing the bid at 30 can neither fill nor rest
+        {
+            const sig = createAndSendPlaceOrderIx(user, userMarketAccount, openOrderAccount, userCoinAta, coinVault, baseArgs);
+            expect(sig).toBeInstanceOf(FailedTransactionMetadata);
+            expect(customErrorCode(sig as FailedTransactionMetadata)).toBe(OrderBookError.EventQueueFull);
+        }
+
+        //An immediate or cancel ask is shrunk to nothing instead
+        {
+            const sig = createAndSendPlaceOrderIx(user, userMarketAccount, openOrderAccount, userCoinAta, coinVault, {...baseArgs, order_type: OrderType.ImmediateOrCancel});
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+
+            let bidsInfo = svm.getAccount(bids.publicKey);
+            //@ts-ignore
+            const bidsData = new OrderBook(borsh.deserialize(OrderBookSchema, bidsInfo!.data));
+            expect(bidsData.slots_filled).toBe(1);
+            expect(bidsData.orders[0].quantity).toBe(BigInt(1));
+        }
+
+        //So is a limit ask that drops its remainder
+        {
+            const sig = createAndSendPlaceOrderIx(user, userMarketAccount, openOrderAccount, userCoinAta, coinVault, {...baseArgs, match_limit_behavior: MatchLimitBehavior.DropRemainder});
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+
+            let bidsInfo = svm.getAccount(bids.publicKey);
+            //@ts-ignore
+            const bidsData = new OrderBook(borsh.deserialize(OrderBookSchema, bidsInfo!.data));
+            expect(bidsData.slots_filled).toBe(1);
+            expect(bidsData.orders[0].quantity).toBe(BigInt(1));
+        }
+
+        //Resting away from the book needs no event, cancelling it does
+        {
+            const sig = createAndSendPlaceOrderIx(user, userMarketAccount, openOrderAccount, userCoinAta, coinVault, {...baseArgs, limit_price: BigInt(100), client_order_id: BigInt(17)});
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+        }
+        {
+            const sig = createAndSendCancelOrderByClientIdIx(user, openOrderAccount, BigInt(17));
+            expect(sig).toBeInstanceOf(FailedTransactionMetadata);
+            expect(customErrorCode(sig as FailedTransactionMetadata)).toBe(OrderBookError.EventQueueFull);
+        }
+
+        //once the queue is drained the cancel goes through
+        svm.setAccount(marketEventsAccount.publicKey, eventsAccount);
+        svm.expireBlockhash();
+        {
+            const sig = createAndSendCancelOrderByClientIdIx(user, openOrderAccount, BigInt(17));
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+        }
+    });
+
+    test("Bids lock the pc derived from their price and quantity", async () => {
+        const baseArgs = {
+            side: 0,
+            limit_price: BigInt(5),
+            coin_qty: BigInt(2),
+            order_type: OrderType.Limit,
+            pc_qty_limit: BigInt(0),
+            self_trade_behavior: SelfTradeBehavior.CancelMaker,
+            client_order_id: BigInt(19),
+            expiry_timestamp: null,
+
+            display_quantity: BigInt(0),
+
+            peg_offset: null,
+            referrer: null,
+            match_limit: null,
+            match_limit_behavior: MatchLimitBehavior.FailOrder
+        };
+        //A price and quantity whose product does not fit in a u64 can never be funded
+        {
+            const sig = createAndSendPlaceOrderIx(user, userMarketAccount, openOrderAccount, userPcAta, pcVault, {...baseArgs, limit_price: BigInt("18446744073709551610"), coin_qty: BigInt(2)});
+            expect(sig).toBeInstanceOf(FailedTransactionMetadata);
+            expect(customErrorCode(sig as FailedTransactionMetadata)).toBe(OrderBookError.ArithmeticOverflow);
+        }
+        //Bid by user for 2 at 5 locks exactly 10 pc
+        {
+            const userMarketDataBefore = new UserMarketAccount(borsh.deserialize(UserMarketAccountSchema, svm.getAccount(userMarketAccount)!.data));
+            const pcAtaBefore = AccountLayout.decode(svm.getAccount(userPcAta)!.data).amount;
+            const sig = createAndSendPlaceOrderIx(user, userMarketAccount, openOrderAccount, userPcAta, pcVault, baseArgs);
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+            //@ts-ignore
+            const userMarketData = new UserMarketAccount(borsh.deserialize(UserMarketAccountSchema, svm.getAccount(userMarketAccount)!.data));
+            const lockedFromFree = userMarketDataBefore.free_pc - userMarketData.free_pc;
+            const deposited = pcAtaBefore - AccountLayout.decode(svm.getAccount(userPcAta)!.data).amount;
+            expect(lockedFromFree + deposited).toBe(BigInt(10));
+            expect(userMarketData.locked_pc - userMarketDataBefore.locked_pc).toBe(BigInt(10));
+        }
+        {
+            const sig = createAndSendCancelOrderByClientIdIx(user, openOrderAccount, BigInt(19));
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+        }
+    });
+
+    test("The book keeps price time priority as orders come and go", async () => {
+        const baseArgs = {
+            side: 0,
+            limit_price: BigInt(10),
+            coin_qty: BigInt(1),
+            order_type: OrderType.Limit,
+            pc_qty_limit: BigInt(0),
+            self_trade_behavior: SelfTradeBehavior.CancelMaker,
+            client_order_id: BigInt(0),
+            expiry_timestamp: null,
+            display_quantity: BigInt(0),
+            peg_offset: null,
+            referrer: null,
+            match_limit: null,
+            match_limit_behavior: MatchLimitBehavior.FailOrder
+        };
+
+        //client order ids of user's bids below, in the order a taker meets them
+        const bidClientOrderIds = () => {
+            let bidsInfo = svm.getAccount(bids.publicKey);
+            //@ts-ignore
+            const bidsData = new OrderBook(borsh.deserialize(OrderBookSchema, bidsInfo!.data));
+            return bidsData.orders
+                .map(order => Number(order.client_order_id))
+                .filter(clientOrderId => clientOrderId >= 20 && clientOrderId <= 25);
+        };
+
+        //Bids by user at 10, 5, 10, 15 and 5 with client order ids 20 to 24
+        const prices = [10, 5, 10, 15, 5];
+        for (let i = 0; i < prices.length; i++) {
+            const sig = createAndSendPlaceOrderIx(user, userMarketAccount, openOrderAccount, userPcAta, pcVault, {...baseArgs, limit_price: BigInt(prices[i]), client_order_id: BigInt(20 + i)});
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+        }
+        expect(bidClientOrderIds()).toStrictEqual([23, 20, 22, 21, 24]);
+
+        //Cancelling from the middle of a level keeps the others in place
+        {
+            const sig = createAndSendCancelOrderByClientIdIx(user, openOrderAccount, BigInt(22));
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+            expect(bidClientOrderIds()).toStrictEqual([23, 20, 21, 24]);
+        }
+
+        //A new bid at 10 joins the back of its level, taking the cancelled order's slot
+        {
+            const sig = createAndSendPlaceOrderIx(user, userMarketAccount, openOrderAccount, userPcAta, pcVault, {...baseArgs, client_order_id: BigInt(25)});
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+            expect(bidClientOrderIds()).toStrictEqual([23, 20, 25, 21, 24]);
+        }
+
+        for (const clientOrderId of [20, 21, 23, 24, 25]) {
+            const sig = createAndSendCancelOrderByClientIdIx(user, openOrderAccount, BigInt(clientOrderId));
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+        }
+        expect(bidClientOrderIds()).toStrictEqual([]);
+    });
+
+    test("The market authority grows the book and event queue in place", async () => {
+        //rest a few bids so there is something to keep
+        for (const [clientOrderId, price] of [[30, 10], [31, 15], [32, 10]]) {
+            const sig = createAndSendPlaceOrderIx(user2, userMarketAccount2, openOrderAccount2, userPcAta2, pcVault, {
+                side: 0,
+                limit_price: BigInt(price),
+                coin_qty: BigInt(1),
+                order_type: OrderType.Limit,
+                pc_qty_limit: BigInt(0),
+                self_trade_behavior: SelfTradeBehavior.CancelMaker,
+                client_order_id: BigInt(clientOrderId),
+                expiry_timestamp: null,
+                display_quantity: BigInt(0),
+                peg_offset: null,
+                referrer: null,
+                match_limit: null,
+                match_limit_behavior: MatchLimitBehavior.FailOrder
+            });
+
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+        }
+        //@ts-ignore
+        const bidsBefore = new OrderBook(borsh.deserialize(OrderBookSchema, svm.getAccount(bids.publicKey)!.data));
+        expect(bidsBefore.orders.map(order => order.client_order_id)).toStrictEqual([BigInt(31), BigInt(30), BigInt(32)]);
+
+        //Anyone other than the authority is rejected
+        {
+            const sig = createAndSendReallocMarketAccountIx(user3, bids.publicKey, MarketAccountKind.Bids, MAX_ORDERS + 40);
+            expect(sig).toBeInstanceOf(FailedTransactionMetadata);
+        }
+
+        //Accounts can not shrink or grow by more than 10KiB at once
+        {
+            const sig = createAndSendReallocMarketAccountIx(accountsAuthority, bids.publicKey, MarketAccountKind.Bids, MAX_ORDERS);
+            expect(sig).toBeInstanceOf(FailedTransactionMetadata);
+        }
+        {
+            const sig = createAndSendReallocMarketAccountIx(accountsAuthority, bids.publicKey, MarketAccountKind.Bids, MAX_ORDERS + 100);
+            expect(sig).toBeInstanceOf(FailedTransactionMetadata);
+        }
+
+        //The asks account is not the bids account
+        {
+            const sig = createAndSendReallocMarketAccountIx(accountsAuthority, asks.publicKey, MarketAccountKind.Bids, MAX_ORDERS + 40);
+            expect(sig).toBeInstanceOf(FailedTransactionMetadata);
+        }
+
+        //The bids grow with their orders and priority intact
+        {
+            const sig = createAndSendReallocMarketAccountIx(accountsAuthority, bids.publicKey, MarketAccountKind.Bids, MAX_ORDERS + 40);
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+
+            const bidsAccount = svm.getAccount(bids.publicKey)!;
+            expect(bidsAccount.data.length).toBe(orderBookAccountLen(MAX_ORDERS + 40));
+            expect(BigInt(bidsAccount.lamports)).toBeGreaterThanOrEqual(svm.minimumBalanceForRentExemption(BigInt(orderBookAccountLen(MAX_ORDERS + 40))));
+
+            //@ts-ignore
+            const bidsData = new OrderBook(borsh.deserialize(OrderBookSchema, bidsAccount.data));
+            expect(bidsData.capacity).toBe(MAX_ORDERS + 40);
+            expect(bidsData.slots_filled).toBe(bidsBefore.slots_filled);
+            expect(bidsData.orders).toStrictEqual(bidsBefore.orders);
+        }
+
+        //New bids still queue behind the old ones at the same price
+        {
+            const sig = createAndSendPlaceOrderIx(user2, userMarketAccount2, openOrderAccount2, userPcAta2, pcVault, {
+                side: 0,
+                limit_price: BigInt(10),
+                coin_qty: BigInt(1),
+                order_type: OrderType.Limit,
+                pc_qty_limit: BigInt(0),
+                self_trade_behavior: SelfTradeBehavior.CancelMaker,
+                client_order_id: BigInt(33),
+                expiry_timestamp: null,
+                display_quantity: BigInt(0),
+                peg_offset: null,
+                referrer: null,
+                match_limit: null,
+                match_limit_behavior: MatchLimitBehavior.FailOrder
+            });
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+
+            //@ts-ignore
+            const bidsData = new OrderBook(borsh.deserialize(OrderBookSchema, svm.getAccount(bids.publicKey)!.data));
+            expect(bidsData.orders.map(order => order.client_order_id)).toStrictEqual([BigInt(31), BigInt(30), BigInt(32), BigInt(33)]);
+        }
+
+        //wrap three marked events around the end of the event queue
+        const eventsAccount = svm.getAccount(marketEventsAccount.publicKey)!;
+        const wrappedData = Buffer.from(eventsAccount.data);
+        [MAX_EVENT - 2, MAX_EVENT - 1, 0].forEach((index, i) => {
+            wrappedData.writeBigUInt64LE(BigInt(i + 1), 44 + 147 * index + 66);
+        });
+        wrappedData.writeUInt32LE(1, 32);
+        wrappedData.writeUInt32LE(MAX_EVENT - 2, 36);
+        svm.setAccount(marketEventsAccount.publicKey, {...eventsAccount, data: wrappedData});
+
+        //The event queue is unwrapped so pending events keep their order
+        {
+            const sig = createAndSendReallocMarketAccountIx(accountsAuthority, marketEventsAccount.publicKey, MarketAccountKind.Events, MAX_EVENT + 64);
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+
+            const eventsInfo = svm.getAccount(marketEventsAccount.publicKey)!;
+            expect(eventsInfo.data.length).toBe(eventsAccountLen(MAX_EVENT + 64));
+
+            //@ts-ignore
+            const eventsData = new MarketEventsAccount(borsh.deserialize(MarketEventsAccountSchema, eventsInfo.data));
+            expect(eventsData.capacity).toBe(MAX_EVENT + 64);
+            expect(eventsData.tail).toBe(0);
+            expect(eventsData.head).toBe(3);
+            expect(eventsData.events.slice(0, 3).map(event => event.coin_qty)).toStrictEqual([BigInt(1), BigInt(2), BigInt(3)]);
+        }
+
+        //drop the marked events and the bids again
+        {
+            const data = Buffer.from(svm.getAccount(marketEventsAccount.publicKey)!.data);
+            data.writeUInt32LE(0, 32);
+            svm.setAccount(marketEventsAccount.publicKey, {...svm.getAccount(marketEventsAccount.publicKey)!, data});
+        }
+        for (const clientOrderId of [30, 31, 32, 33]) {
+            const sig = createAndSendCancelOrderByClientIdIx(user2, openOrderAccount2, BigInt(clientOrderId));
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+        }
+    });
+
+    test("Open order slots are freed as orders leave the book and reused", async () => {
+        const maker = createTrader();
+        const taker = createTrader();
+        const orderArgs = (side: Side, orderType: OrderType, clientOrderId: number) => ({
+            side,
+            limit_price: BigInt(5),
+            coin_qty: BigInt(1),
+            order_type: orderType,
+            pc_qty_limit: BigInt(0),
+            self_trade_behavior: SelfTradeBehavior.CancelMaker,
+            client_order_id: BigInt(clientOrderId),
+            expiry_timestamp: null,
+            display_quantity: BigInt(0),
+            peg_offset: null,
+            referrer: null,
+            match_limit: null,
+            match_limit_behavior: MatchLimitBehavior.FailOrder
+        });
+        const makerClientOrderIds = () => {
+            //@ts-ignore
+            const openOrderData = new OpenOrderAccount(borsh.deserialize(OpenOrderAccountSchema, svm.getAccount(maker.openOrder)!.data));
+            return openOrderData.client_order_ids.slice(0, openOrderData.next_array_index.valueOf());
+        };
+        const placeMakerBid = (clientOrderId: number) => {
+            svm.expireBlockhash();
+            return createAndSendPlaceOrderIx(maker.trader, maker.userMarket, maker.openOrder, maker.pcAta, pcVault, orderArgs(Side.Bid, OrderType.Limit, clientOrderId));
+        };
+
+        //every slot of the open order account gets taken
+        for (let clientOrderId = 100; clientOrderId < 164; clientOrderId++) {
+            expect(placeMakerBid(clientOrderId)).toBeInstanceOf(TransactionMetadata);
+        }
+        expect(makerClientOrderIds().length).toBe(64);
+
+        //The 65th resting order fails cleanly
+        {
+            const sig = placeMakerBid(164);
+            expect(sig).toBeInstanceOf(FailedTransactionMetadata);
+            expect(customErrorCode(sig as FailedTransactionMetadata)).toBe(OrderBookError.TooManyOpenOrders);
+        }
+
+        //Cancelling frees the slot right away, the last open order moves into it
+        {
+            const sig = createAndSendCancelOrderByClientIdIx(maker.trader, maker.openOrder, BigInt(100));
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+
+            const clientOrderIds = makerClientOrderIds();
+            expect(clientOrderIds.length).toBe(63);
+            expect(clientOrderIds).not.toContain(BigInt(100));
+            expect(clientOrderIds[0]).toBe(BigInt(163));
+        }
+
+        {
+            expect(placeMakerBid(164)).toBeInstanceOf(TransactionMetadata);
+            expect(makerClientOrderIds()[63]).toBe(BigInt(164));
+        }
+
+        //A fill keeps the slot until consume events sees the maker fully filled
+        {
+            svm.expireBlockhash();
+            const sig = createAndSendPlaceOrderIx(taker.trader, taker.userMarket, taker.openOrder, taker.coinAta, coinVault, orderArgs(Side.Ask, OrderType.ImmediateOrCancel, 7));
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+
+            //@ts-ignore
+            const mktEventData = new MarketEventsAccount(borsh.deserialize(MarketEventsAccountSchema, svm.getAccount(marketEventsAccount.publicKey)!.data));
+            const event = mktEventData.events[(mktEventData.head.valueOf() + mktEventData.capacity - 1) % mktEventData.capacity];
+            expect(event.event_type).toBe(EventType.Fill);
+            expect(event.maker_client_order_id).toBe(BigInt(101));
+            expect(event.taker_client_order_id).toBe(BigInt(7));
+            expect(event.maker_out).toBe(1);
+            expect(makerClientOrderIds()).toContain(BigInt(101));
+        }
+
+        while (true) {
+            const sig = createAndSendConsumeEventsIx();
+            if (!sig) {
+                break;
+            }
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+        }
+        expect(makerClientOrderIds().length).toBe(63);
+        expect(makerClientOrderIds()).not.toContain(BigInt(101));
+
+        //cancel the rest of the maker's bids
+        while (makerClientOrderIds().length > 0) {
+            const sig = createAndSendCancelAllOrdersIx(maker.trader, maker.openOrder, {side: null, limit: 16});
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+            svm.expireBlockhash();
+        }
+        while (true) {
+            const sig = createAndSendConsumeEventsIx();
+            if (!sig) {
+                break;
+            }
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+        }
+        //@ts-ignore
+        const bidsData = new OrderBook(borsh.deserialize(OrderBookSchema, svm.getAccount(bids.publicKey)!.data));
+        expect(bidsData.slots_filled).toBe(0);
+    });
+
+    test("A trader with nothing left on the market closes its accounts", async () => {
+        const trader = createTrader();
+        const maker = createTrader();
+        const taker = createTrader();
+        const orderArgs = (side: Side, orderType: OrderType, referrer: Uint8Array | null) => ({
+            side,
+            limit_price: BigInt(5),
+            coin_qty: BigInt(1),
+            order_type: orderType,
+            pc_qty_limit: BigInt(0),
+            self_trade_behavior: SelfTradeBehavior.CancelMaker,
+            client_order_id: BigInt(1),
+            expiry_timestamp: null,
+            display_quantity: BigInt(0),
+            peg_offset: null,
+            referrer,
+            match_limit: null,
+            match_limit_behavior: MatchLimitBehavior.FailOrder
+        });
+        const consumeAllEvents = () => {
+            while (true) {
+                const sig = createAndSendConsumeEventsIx();
+                if (!sig) {
+                    break;
+                }
+                expect(sig).toBeInstanceOf(TransactionMetadata);
+            }
+        };
+
+        //A resting order keeps the accounts open
+        {
+            const sig = createAndSendPlaceOrderIx(trader.trader, trader.userMarket, trader.openOrder, trader.pcAta, pcVault, orderArgs(Side.Bid, OrderType.Limit, null));
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+            let closeSig = createAndSendCloseUserAccountsIx(trader.trader, trader.openOrder, trader.userMarket);
+            expect(closeSig).toBeInstanceOf(FailedTransactionMetadata);
+            expect(customErrorCode(closeSig as FailedTransactionMetadata)).toBe(OrderBookError.OpenOrdersRemaining);
+        }
+
+        //So do locked funds until the cancel is consumed, and free funds until they are settled
+        {
+            const sig = createAndSendCancelOrderByClientIdIx(trader.trader, trader.openOrder, BigInt(1));
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+            let closeSig = createAndSendCloseUserAccountsIx(trader.trader, trader.openOrder, trader.userMarket);
+            expect(closeSig).toBeInstanceOf(FailedTransactionMetadata);
+            expect(customErrorCode(closeSig as FailedTransactionMetadata)).toBe(OrderBookError.LockedBalanceRemaining);
+
+            consumeAllEvents();
+            closeSig = createAndSendCloseUserAccountsIx(trader.trader, trader.openOrder, trader.userMarket);
+            expect(closeSig).toBeInstanceOf(FailedTransactionMetadata);
+            expect(customErrorCode(closeSig as FailedTransactionMetadata)).toBe(OrderBookError.FreeBalanceRemaining);
+
+            createAndSendSettleFundsIx(trader.trader, trader.userMarket, trader.coinAta, trader.pcAta);
+            //@ts-ignore
+            const userMarketData = new UserMarketAccount(borsh.deserialize(UserMarketAccountSchema, svm.getAccount(trader.userMarket)!.data));
+            expect(userMarketData.free_pc).toBe(BigInt(0));
+            expect(userMarketData.locked_pc).toBe(BigInt(0));
+        }
+
+        //A pending fill naming the trader as referrer keeps the accounts open too
+        {
+            let sig = createAndSendPlaceOrderIx(maker.trader, maker.userMarket, maker.openOrder, maker.coinAta, coinVault, orderArgs(Side.Ask, OrderType.Limit, null));
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+            sig = createAndSendPlaceOrderIx(taker.trader, taker.userMarket, taker.openOrder, taker.pcAta, pcVault, orderArgs(Side.Bid, OrderType.ImmediateOrCancel, trader.trader.publicKey.toBytes()), trader.userMarket);
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+            let closeSig = createAndSendCloseUserAccountsIx(trader.trader, trader.openOrder, trader.userMarket);
+            expect(closeSig).toBeInstanceOf(FailedTransactionMetadata);
+            expect(customErrorCode(closeSig as FailedTransactionMetadata)).toBe(OrderBookError.PendingEvents);
+
+            //a taker fee of 1 leaves no referrer share to settle
+            consumeAllEvents();
+        }
+
+        //Both accounts are closed and their rent goes back to the trader
+        {
+            const lamportsBefore = svm.getBalance(trader.trader.publicKey)!;
+            const rent = svm.getAccount(trader.openOrder)!.lamports + svm.getAccount(trader.userMarket)!.lamports;
+
+            const sig = createAndSendCloseUserAccountsIx(trader.trader, trader.openOrder, trader.userMarket);
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+
+            expect(svm.getAccount(trader.openOrder)).toBeNull();
+            expect(svm.getAccount(trader.userMarket)).toBeNull();
+            expect(svm.getBalance(trader.trader.publicKey)).toBe(lamportsBefore + BigInt(rent));
+        }
+
+        //The accounts are created again by the trader's next order
+        {
+            svm.expireBlockhash();
+            const sig = createAndSendPlaceOrderIx(trader.trader, trader.userMarket, trader.openOrder, trader.pcAta, pcVault, orderArgs(Side.Bid, OrderType.Limit, null));
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+
+            //@ts-ignore
+            const openOrderData = new OpenOrderAccount(borsh.deserialize(OpenOrderAccountSchema, svm.getAccount(trader.openOrder)!.data));
+            expect(openOrderData.next_array_index).toBe(1);
+
+            expect(createAndSendCancelOrderByClientIdIx(trader.trader, trader.openOrder, BigInt(1))).toBeInstanceOf(TransactionMetadata);
+            consumeAllEvents();
+        }
+    });
+
+    test("Orders only deposit into the market's own vaults", async () => {
+        const trader = createTrader();
+        const args = {
+            side: Side.Bid,
+            limit_price: BigInt(5),
+            coin_qty: BigInt(1),
+            order_type: OrderType.Limit,
+            pc_qty_limit: BigInt(0),
+            self_trade_behavior: SelfTradeBehavior.CancelMaker,
+            client_order_id: BigInt(0),
+            expiry_timestamp: null,
+            display_quantity: BigInt(0),
+            peg_offset: null,
+            referrer: null,
+            match_limit: null,
+            match_limit_behavior: MatchLimitBehavior.FailOrder
+        };
+
+        //A pc token account of the trader's own is not the pc vault
+        {
+            const sig = createAndSendPlaceOrderIx(trader.trader, trader.userMarket, trader.openOrder, trader.pcAta, trader.pcAta, args);
+            expect(sig).toBeInstanceOf(FailedTransactionMetadata);
+            expect(svm.getAccount(trader.userMarket)).toBeNull();
+        }
+
+        //Neither is the coin vault for a bid
+        {
+            const sig = createAndSendPlaceOrderIx(trader.trader, trader.userMarket, trader.openOrder, trader.pcAta, coinVault, args);
+            expect(sig).toBeInstanceOf(FailedTransactionMetadata);
+        }
+
+        //Trigger orders lock their funds up front and are held to the same vault
+        {
+            expect(createAndSendPlaceOrderIx(trader.trader, trader.userMarket, trader.openOrder, trader.pcAta, pcVault, args)).toBeInstanceOf(TransactionMetadata);
+
+            const triggerArgs = {
+                side: Side.Bid,
+                trigger_price: BigInt(1_000_000),
+                limit_price: BigInt(5),
+                coin_qty: BigInt(1),
+                order_type: OrderType.ImmediateOrCancel,
+                self_trade_behavior: SelfTradeBehavior.CancelMaker,
+                client_order_id: BigInt(0)
+            };
+            const sig = createAndSendCreateTriggerOrderIx(trader.trader, trader.userMarket, trader.pcAta, trader.pcAta, triggerArgs);
+            expect(sig).toBeInstanceOf(FailedTransactionMetadata);
+
+            expect(createAndSendCancelOrderByClientIdIx(trader.trader, trader.openOrder, BigInt(0))).toBeInstanceOf(TransactionMetadata);
+        }
+    });
+
+    test("Only the owner can place and cancel its orders", async () => {
+        const victim = createTrader();
+        const attacker = createTrader();
+        const args = {
+            side: Side.Bid,
+            limit_price: BigInt(5),
+            coin_qty: BigInt(1),
+            order_type: OrderType.Limit,
+            pc_qty_limit: BigInt(0),
+            self_trade_behavior: SelfTradeBehavior.CancelMaker,
+            client_order_id: BigInt(1),
+            expiry_timestamp: null,
+            display_quantity: BigInt(0),
+            peg_offset: null,
+            referrer: null,
+            match_limit: null,
+            match_limit_behavior: MatchLimitBehavior.FailOrder
+        };
+        const sendWithoutOwner = (keys: AccountMeta[], data: Buffer, ...signers: Keypair[]) => {
+            let tx = new Transaction().add(new TransactionInstruction({keys, programId, data}));
+            tx.recentBlockhash = svm.latestBlockhash();
+            tx.feePayer = attacker.trader.publicKey;
+            tx.sign(attacker.trader, ...signers);
+            return svm.sendTransaction(tx);
+        };
+
+        expect(createAndSendPlaceOrderIx(victim.trader, victim.userMarket, victim.openOrder, victim.pcAta, pcVault, args)).toBeInstanceOf(TransactionMetadata);
+        //@ts-ignore
+        let bidsData = new OrderBook(borsh.deserialize(OrderBookSchema, svm.getAccount(bids.publicKey)!.data));
+        const victimOrder = bidsData.orders.find((order) => new PublicKey(order.owner).equals(victim.trader.publicKey))!;
+        const slotsFilled = bidsData.slots_filled;
+
+        //An order for the victim without their signature is rejected
+        {
+            const sig = sendWithoutOwner([
+                {pubkey: accountsAuthority.publicKey, isSigner: true, isWritable: true},
+                {pubkey: market, isSigner: false, isWritable: true},
+                {pubkey: marketEventsAccount.publicKey, isSigner: false, isWritable: true},
+                {pubkey: victim.trader.publicKey, isSigner: false, isWritable: true},
+                {pubkey: victim.userMarket, isSigner: false, isWritable: true},
+                {pubkey: victim.openOrder, isSigner: false, isWritable: true},
+                {pubkey: victim.pcAta, isSigner: false, isWritable: true},
+                {pubkey: pcVault, isSigner: false, isWritable: true},
+                {pubkey: coinMint.publicKey, isSigner: false, isWritable: true},
+                {pubkey: pcMint.publicKey, isSigner: false, isWritable: true},
+                {pubkey: bids.publicKey, isSigner: false, isWritable: true},
+                {pubkey: asks.publicKey, isSigner: false, isWritable: true},
+                {pubkey: SystemProgram.programId, isSigner: false, isWritable: false},
+                {pubkey: TOKEN_PROGRAM_ID, isSigner: false, isWritable: false},
+                {pubkey: oracle, isSigner: false, isWritable: false},
+            ], Buffer.concat([Buffer.from([1]), Buffer.from(borsh.serialize(CreateOrderSchema, {...args, client_order_id: BigInt(2)}))]), accountsAuthority);
+            expect(sig).toBeInstanceOf(FailedTransactionMetadata);
+        }
+
+        //So is cancelling the victim's order, and cancelling it through the other side's book
+        {
+            const cancelKeys = (book: PublicKey, ownerSigns: boolean) => [
+                {pubkey: market, isSigner: false, isWritable: true},
+                {pubkey: marketEventsAccount.publicKey, isSigner: false, isWritable: true},
+                {pubkey: victim.trader.publicKey, isSigner: ownerSigns, isWritable: true},
+                {pubkey: coinMint.publicKey, isSigner: false, isWritable: true},
+                {pubkey: pcMint.publicKey, isSigner: false, isWritable: true},
+                {pubkey: book, isSigner: false, isWritable: true}
+            ];
+            const data = Buffer.concat([Buffer.from([4]), Buffer.from(borsh.serialize(CancelOrderSchema, {order_id: victimOrder.order_id, side: Side.Bid}))]);
+            expect(sendWithoutOwner(cancelKeys(bids.publicKey, false), data)).toBeInstanceOf(FailedTransactionMetadata);
+
+            let tx = new Transaction().add(new TransactionInstruction({keys: cancelKeys(asks.publicKey, true), programId, data}));
+            tx.recentBlockhash = svm.latestBlockhash();
+            tx.feePayer = victim.trader.publicKey;
+            tx.sign(victim.trader);
+            expect(svm.sendTransaction(tx)).toBeInstanceOf(FailedTransactionMetadata);
+
+            //@ts-ignore
+            bidsData = new OrderBook(borsh.deserialize(OrderBookSchema, svm.getAccount(bids.publicKey)!.data));
+            expect(bidsData.slots_filled).toBe(slotsFilled);
+            expect(bidsData.orders.map((order) => order.order_id)).toContain(victimOrder.order_id);
+        }
+
+        expect(createAndSendCancelOrderByClientIdIx(victim.trader, victim.openOrder, BigInt(1))).toBeInstanceOf(TransactionMetadata);
+    });
+
+    test("Bids keep locked only what their fills cost and their remainder at its resting price", async () => {
+        const maker = createTrader();
+        const peggedMaker = createTrader();
+        const taker = createTrader();
+        const orderArgs = (side: Side, limitPrice: number, coinQty: number, clientOrderId: number) => ({
+            side,
+            limit_price: BigInt(limitPrice),
+            coin_qty: BigInt(coinQty),
+            order_type: OrderType.Limit,
+            pc_qty_limit: BigInt(0),
+            self_trade_behavior: SelfTradeBehavior.CancelMaker,
+            client_order_id: BigInt(clientOrderId),
+            expiry_timestamp: null,
+            display_quantity: BigInt(0),
+            peg_offset: null,
+            referrer: null,
+            match_limit: null,
+            match_limit_behavior: MatchLimitBehavior.FailOrder
+        });
+        const consumeAllEvents = () => {
+            while (true) {
+                const sig = createAndSendConsumeEventsIx();
+                if (!sig) {
+                    break;
+                }
+                expect(sig).toBeInstanceOf(TransactionMetadata);
+            }
+        };
+        const userMarketData = (userMarket: PublicKey) => {
+            //@ts-ignore
+            return new UserMarketAccount(borsh.deserialize(UserMarketAccountSchema, svm.getAccount(userMarket)!.data));
+        };
+
+        //the taker starts with 360 free pc from a cancelled bid for 3 at 120
+        {
+            expect(createAndSendPlaceOrderIx(taker.trader, taker.userMarket, taker.openOrder, taker.pcAta, pcVault, orderArgs(Side.Bid, 120, 3, 1))).toBeInstanceOf(TransactionMetadata);
+            expect(createAndSendCancelOrderByClientIdIx(taker.trader, taker.openOrder, BigInt(1))).toBeInstanceOf(TransactionMetadata);
+            consumeAllEvents();
+            expect(userMarketData(taker.userMarket).free_pc).toBe(BigInt(360));
+            expect(userMarketData(taker.userMarket).locked_pc).toBe(BigInt(0));
+        }
+
+        //Asks by maker, 1 at 100 and 1 at 110
+        [100, 110].forEach(price => {
+            expect(createAndSendPlaceOrderIx(maker.trader, maker.userMarket, maker.openOrder, maker.coinAta, coinVault, orderArgs(Side.Ask, price, 1, price))).toBeInstanceOf(TransactionMetadata);
+        });
+
+        //Bid by taker for 3 at 120 fills both asks below its limit and rests 1 at 120
+        {
+            svm.expireBlockhash();
+            const sig = createAndSendPlaceOrderIx(taker.trader, taker.userMarket, taker.openOrder, taker.pcAta, pcVault, orderArgs(Side.Bid, 120, 3, 2));
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+
+            //fills of 100 and 110 with taker fees of 1 each plus 120 for the remainder, the other 28 stay free
+            let takerData = userMarketData(taker.userMarket);
+            expect(takerData.locked_pc).toBe(BigInt(100 + 1 + 110 + 1 + 120));
+            expect(takerData.free_pc).toBe(BigInt(28));
+
+            //@ts-ignore
+            const bidsData = new OrderBook(borsh.deserialize(OrderBookSchema, svm.getAccount(bids.publicKey)!.data));
+            expect(bidsData.slots_filled).toBe(1);
+            expect(bidsData.orders[0].price).toBe(BigInt(120));
+            expect(bidsData.orders[0].quantity).toBe(BigInt(1));
+
+            //the fills take their cost out of the lock, the remainder keeps exactly its price locked
+            consumeAllEvents();
+            takerData = userMarketData(taker.userMarket);
+            expect(takerData.locked_pc).toBe(BigInt(120));
+            expect(takerData.free_pc).toBe(BigInt(28));
+            expect(takerData.free_coin).toBe(BigInt(2));
+        }
+
+        //Pegged bid by peggedMaker for 1 at oracle - 10, up to 200, locks at its peg limit
+        {
+            setOraclePrice(BigInt(150));
+            const sig = createAndSendPlaceOrderIx(peggedMaker.trader, peggedMaker.userMarket, peggedMaker.openOrder, peggedMaker.pcAta, pcVault, {...orderArgs(Side.Bid, 200, 1, 3), peg_offset: BigInt(-10)});
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+            expect(userMarketData(peggedMaker.userMarket).locked_pc).toBe(BigInt(200));
+        }
+
+        //Ask by maker for 1 at 130 fills the pegged bid at 140 ahead of the bid at 120
+        {
+            svm.expireBlockhash();
+            const sig = createAndSendPlaceOrderIx(maker.trader, maker.userMarket, maker.openOrder, maker.coinAta, coinVault, {...orderArgs(Side.Ask, 130, 1, 4), order_type: OrderType.ImmediateOrCancel});
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+
+            //@ts-ignore
+            const mktEventData = new MarketEventsAccount(borsh.deserialize(MarketEventsAccountSchema, svm.getAccount(marketEventsAccount.publicKey)!.data));
+            const event = mktEventData.events[(mktEventData.head.valueOf() + mktEventData.capacity - 1) % mktEventData.capacity];
+            expect(event.event_type).toBe(EventType.Fill);
+            expect(event.pc_qty).toBe(BigInt(140));
+            expect(event.maker_pc_locked).toBe(BigInt(200));
+
+            //the 60 locked above the fill price goes back to free pc, a rebate of 0.07 rounds down to nothing
+            consumeAllEvents();
+            const peggedMakerData = userMarketData(peggedMaker.userMarket);
+            expect(peggedMakerData.locked_pc).toBe(BigInt(0));
+            expect(peggedMakerData.free_pc).toBe(BigInt(60));
+            expect(peggedMakerData.free_coin).toBe(BigInt(1));
+        }
+
+        expect(createAndSendCancelOrderByClientIdIx(taker.trader, taker.openOrder, BigInt(2))).toBeInstanceOf(TransactionMetadata);
+        consumeAllEvents();
+    });
+
+    test("Amending an order across the book is rejected", async () => {
+        const maker = createTrader();
+        const taker = createTrader();
+        const orderArgs = (side: Side, limitPrice: number) => ({
+            side,
+            limit_price: BigInt(limitPrice),
+            coin_qty: BigInt(1),
+            order_type: OrderType.Limit,
+            pc_qty_limit: BigInt(0),
+            self_trade_behavior: SelfTradeBehavior.CancelMaker,
+            client_order_id: BigInt(1),
+            expiry_timestamp: null,
+            display_quantity: BigInt(0),
+            peg_offset: null,
+            referrer: null,
+            match_limit: null,
+            match_limit_behavior: MatchLimitBehavior.FailOrder
+        });
+
+        expect(createAndSendPlaceOrderIx(maker.trader, maker.userMarket, maker.openOrder, maker.coinAta, coinVault, orderArgs(Side.Ask, 130))).toBeInstanceOf(TransactionMetadata);
+        expect(createAndSendPlaceOrderIx(taker.trader, taker.userMarket, taker.openOrder, taker.pcAta, pcVault, orderArgs(Side.Bid, 120))).toBeInstanceOf(TransactionMetadata);
+        //@ts-ignore
+        let bidsData = new OrderBook(borsh.deserialize(OrderBookSchema, svm.getAccount(bids.publicKey)!.data));
+        const orderId = bidsData.orders.find((order) => new PublicKey(order.owner).equals(taker.trader.publicKey))!.order_id;
+
+        //Bid by taker amended from 120 to 130 would take the ask at 130
+        {
+            const sig = createAndSendAmendOrderIx(taker.trader, taker.userMarket, {order_id: orderId, side: Side.Bid, new_price: BigInt(130), new_quantity: BigInt(1)});
+            expect(sig).toBeInstanceOf(FailedTransactionMetadata);
+            expect(customErrorCode(sig as FailedTransactionMetadata)).toBe(OrderBookError.OrderWouldCross);
+
+            //@ts-ignore
+            bidsData = new OrderBook(borsh.deserialize(OrderBookSchema, svm.getAccount(bids.publicKey)!.data));
+            expect(bidsData.orders.find((order) => order.order_id === orderId)!.price).toBe(BigInt(120));
+        }
+
+        expect(createAndSendCancelOrderByClientIdIx(maker.trader, maker.openOrder, BigInt(1))).toBeInstanceOf(TransactionMetadata);
+        expect(createAndSendCancelOrderByClientIdIx(taker.trader, taker.openOrder, BigInt(1))).toBeInstanceOf(TransactionMetadata);
+        while (true) {
+            const sig = createAndSendConsumeEventsIx();
+            if (!sig) {
+                break;
+            }
+            expect(sig).toBeInstanceOf(TransactionMetadata);
+        }
+    });
+
+
+
+    //a fresh trader on the market with funded token accounts, its open order and user market accounts are created by its first order
+    function createTrader() {
+        const trader = new Keypair();
+        svm.airdrop(trader.publicKey, BigInt(100 * LAMPORTS_PER_SOL));
+        const openOrder = PublicKey.findProgramAddressSync([Buffer.from("open_order"), market.toBuffer(), trader.publicKey.toBuffer()], programId)[0];
+        const userMarket = PublicKey.findProgramAddressSync([Buffer.from("user_market_account"), market.toBuffer(), trader.publicKey.toBuffer()], programId)[0];
+        const coinAta = getAssociatedTokenAddressSync(coinMint.publicKey, trader.publicKey, false, TOKEN_PROGRAM_ID, ASSOCIATED_TOKEN_PROGRAM_ID);
+        const pcAta = getAssociatedTokenAddressSync(pcMint.publicKey, trader.publicKey, false, TOKEN_PROGRAM_ID, ASSOCIATED_TOKEN_PROGRAM_ID);
+        createAndSendCreateAtaIx(trader, coinAta, pcAta, coinMint.publicKey, pcMint.publicKey);
+        createAndSendMintToIx(accountsAuthority, trader, coinAta, pcAta, coinMint.publicKey, pcMint.publicKey);
+        return {trader, openOrder, userMarket, coinAta, pcAta};
+    }
+
+    function createAndSendCreateAtaIx(user: Keypair, userCoinAta: PublicKey, userPcAta: PublicKey, coinMint: PublicKey, pcMint: PublicKey) {
+        let createUserAtaIx = new Transaction().add(
+            createAssociatedTokenAccountInstruction(
+                user.publicKey,
+                userCoinAta,
+                user.publicKey,
+                coinMint,
+                TOKEN_PROGRAM_ID,
+                ASSOCIATED_TOKEN_PROGRAM_ID
+            ),
+            createAssociatedTokenAccountInstruction(
+                user.publicKey,
+                userPcAta,
+                user.publicKey,
+                pcMint,
+                TOKEN_PROGRAM_ID,
+                ASSOCIATED_TOKEN_PROGRAM_ID
+            )
+        );
+
+        createUserAtaIx.recentBlockhash = svm.latestBlockhash();
+        createUserAtaIx.feePayer = user.publicKey;
+        createUserAtaIx.sign(user);
+        svm.sendTransaction(createUserAtaIx);
+    }
+
+    function createAndSendMintToIx(accountsAuthority: Keypair, user: Keypair, userCoinAta: PublicKey, userPcAta: PublicKey, coinMint: PublicKey, pcMint: PublicKey) {
+        const mintCoinToUserIx = createMintToInstruction(
+            coinMint,
+            userCoinAta,
+            accountsAuthority.publicKey,
+            initialCoinQty,
+            [],
+            TOKEN_PROGRAM_ID
+        );
+
+        const mintPcToUserIx = createMintToInstruction(
+            pcMint,
+            userPcAta,
+            accountsAuthority.publicKey,
+            initialPcQty,
+            [],
+            TOKEN_PROGRAM_ID
+        );
+        
+        let mintCoinAndPcToUserTx = new Transaction().add(mintCoinToUserIx, mintPcToUserIx);
+        mintCoinAndPcToUserTx.recentBlockhash = svm.latestBlockhash();
+        mintCoinAndPcToUserTx.feePayer = user.publicKey;
+        mintCoinAndPcToUserTx.sign(accountsAuthority, user);
+        svm.sendTransaction(mintCoinAndPcToUserTx);
+    }
+
+    function createAndSendPlaceOrderIx(user: Keypair, userMarketAccount: PublicKey, userOpenOrderAccount: PublicKey, userAta: PublicKey, marketVault: PublicKey, args: Object, referrerMarketAccount?: PublicKey) {
+        let ix = new TransactionInstruction({
+                keys: [
+                    {pubkey: accountsAuthority.publicKey, isSigner: true, isWritable: true},
+                    {pubkey: market, isSigner: false, isWritable: true},
+                    {pubkey: marketEventsAccount.publicKey, isSigner: false, isWritable: true},
+                    {pubkey: user.publicKey, isSigner: true, isWritable: true},
+                    {pubkey: userMarketAccount, isSigner: false, isWritable: true},
+                    {pubkey: userOpenOrderAccount, isSigner: false, isWritable: true},
+                    {pubkey: userAta, isSigner: false, isWritable: true},
+                    {pubkey: marketVault, isSigner: false, isWritable: true},
+                    {pubkey: coinMint.publicKey, isSigner: false, isWritable: true},
+                    {pubkey: pcMint.publicKey, isSigner: false, isWritable: true},
+                    {pubkey: bids.publicKey, isSigner: false, isWritable: true},
+                    {pubkey: asks.publicKey, isSigner: false, isWritable: true},
+                    {pubkey: SystemProgram.programId, isSigner: false, isWritable: false},
+                    {pubkey: TOKEN_PROGRAM_ID, isSigner: false, isWritable: false},
+                    {pubkey: oracle, isSigner: false, isWritable: false},
+                ],
+                programId: programId,
+                data: Buffer.concat([Buffer.from([1]), Buffer.from(borsh.serialize(CreateOrderSchema, args))]) 
+            });
+        if (referrerMarketAccount) {
+            ix.keys.push({pubkey: referrerMarketAccount, isSigner: false, isWritable: false});
+        }
+
+        let tx = new Transaction().add(ix);
+        tx.recentBlockhash = svm.latestBlockhash();
+        tx.feePayer = user.publicKey;
+        tx.sign(accountsAuthority, user);
+        const sig = svm.sendTransaction(tx);
+        if (sig instanceof TransactionMetadata) {
+            console.log(sig.toString());
+        } else if (sig instanceof FailedTransactionMetadata) {
+            console.log(sig.toString());
+        }
+        return sig;
+    }
+
+    function createAndSendSettleFundsIx(user: Keypair, userMarketAccount: PublicKey, userCoinAta: PublicKey, userPcAta: PublicKey) {
+        let ix = new TransactionInstruction({
+            keys: [
+                {pubkey: market, isSigner: false, isWritable: true},
+                {pubkey: marketEventsAccount.publicKey, isSigner: false, isWritable: true},
+                {pubkey: user.publicKey, isSigner: true, isWritable: true},
+                {pubkey: userMarketAccount, isSigner: false, isWritable: true},
+                {pubkey: coinMint.publicKey, isSigner: false, isWritable: true},
+                {pubkey: pcMint.publicKey, isSigner: false, isWritable: true},
+                {pubkey: coinVault, isSigner: false, isWritable: true},
+                {pubkey: pcVault, isSigner: false, isWritable: true},
+                {pubkey: userCoinAta, isSigner: false, isWritable: true},
+                {pubkey: userPcAta, isSigner: false, isWritable: true},
+                {pubkey: TOKEN_PROGRAM_ID, isSigner: false, isWritable: false},
+            ],
+            programId: programId,
+            data: Buffer.from([3])
+        });
+
+        let tx = new Transaction().add(ix);
+        tx.recentBlockhash = svm.latestBlockhash();
+        tx.feePayer = user.publicKey;
+        tx.sign(user);
+        const sig = svm.sendTransaction(tx);
+        if (sig instanceof TransactionMetadata) {
+            console.log(sig.toString());
+        } else if (sig instanceof FailedTransactionMetadata) {
+            console.log(sig.toString());
+        }
+        svm.expireBlockhash();
+    }
+
+    function createAndSendCancelOrderByClientIdIx(user: Keypair, userOpenOrderAccount: PublicKey, clientOrderId: bigint) {
+        let ix = new TransactionInstruction({
+            keys: [
+                {pubkey: market, isSigner: false, isWritable: true},
+                {pubkey: marketEventsAccount.publicKey, isSigner: false, isWritable: true},
+                {pubkey: user.publicKey, isSigner: true, isWritable: true},
+                {pubkey: userOpenOrderAccount, isSigner: false, isWritable: true},
+                {pubkey: coinMint.publicKey, isSigner: false, isWritable: false},
+                {pubkey: pcMint.publicKey, isSigner: false, isWritable: false},
+                {pubkey: bids.publicKey, isSigner: false, isWritable: true},
+                {pubkey: asks.publicKey, isSigner: false, isWritable: true},
+            ],
+            programId: programId,
+            data: Buffer.concat([Buffer.from([5]), Buffer.from(borsh.serialize(CancelOrderByClientIdSchema, {client_order_id: clientOrderId}))])
+        });
+
+        let tx = new Transaction().add(ix);
+        tx.recentBlockhash = svm.latestBlockhash();
+        tx.feePayer = user.publicKey;
+        tx.sign(user);
+        const sig = svm.sendTransaction(tx);
+        if (sig instanceof TransactionMetadata) {
+            console.log(sig.toString());
+        } else if (sig instanceof FailedTransactionMetadata) {
+            console.log(sig.toString());
+        }
+        return sig;
+    }
+
+    function createAndSendAmendOrderIx(user: Keypair, userMarketAccount: PublicKey, args: Object) {
+        let ix = new TransactionInstruction({
+            keys: [
+                {pubkey: market, isSigner: false, isWritable: true},
+                {pubkey: marketEventsAccount.publicKey, isSigner: false, isWritable: true},
+                {pubkey: user.publicKey, isSigner: true, isWritable: true},
+                {pubkey: userMarketAccount, isSigner: false, isWritable: true},
+                {pubkey: coinMint.publicKey, isSigner: false, isWritable: false},
+                {pubkey: pcMint.publicKey, isSigner: false, isWritable: false},
+                {pubkey: bids.publicKey, isSigner: false, isWritable: true},
+                {pubkey: asks.publicKey, isSigner: false, isWritable: true},
+                {pubkey: oracle, isSigner: false, isWritable: false},
+            ],
+            programId: programId,
+            data: Buffer.concat([Buffer.from([7]), Buffer.from(borsh.serialize(AmendOrderSchema, args))])
+        });
+
+        let tx = new Transaction().add(ix);
+        tx.recentBlockhash = svm.latestBlockhash();
+        tx.feePayer = user.publicKey;
+        tx.sign(user);
+        const sig = svm.sendTransaction(tx);
+        if (sig instanceof TransactionMetadata) {
+            console.log(sig.toString());
+        } else if (sig instanceof FailedTransactionMetadata) {
+            console.log(sig.toString());
+        }
+        return sig;
+    }
+
+    function createAndSendCancelAllOrdersIx(user: Keypair, userOpenOrderAccount: PublicKey, args: Object) {
+        let ix = new TransactionInstruction({
+            keys: [
+                {pubkey: market, isSigner: false, isWritable: true},
+                {pubkey: marketEventsAccount.publicKey, isSigner: false, isWritable: true},
+                {pubkey: user.publicKey, isSigner: true, isWritable: true},
+                {pubkey: userOpenOrderAccount, isSigner: false, isWritable: true},
+                {pubkey: coinMint.publicKey, isSigner: false, isWritable: false},
+                {pubkey: pcMint.publicKey, isSigner: false, isWritable: false},
+                {pubkey: bids.publicKey, isSigner: false, isWritable: true},
+                {pubkey: asks.publicKey, isSigner: false, isWritable: true},
+            ],
+            programId: programId,
+            data: Buffer.concat([Buffer.from([8]), Buffer.from(borsh.serialize(CancelAllOrdersSchema, args))])
+        });
+
+        let tx = new Transaction().add(ix);
+        tx.recentBlockhash = svm.latestBlockhash();
+        tx.feePayer = user.publicKey;
+        tx.sign(user);
+        const sig = svm.sendTransaction(tx);
+        if (sig instanceof TransactionMetadata) {
+            console.log(sig.toString());
+        } else if (sig instanceof FailedTransactionMetadata) {
+            console.log(sig.toString());
+        }
+        return sig;
+    }
+
+    function createAndSendCancelAndReplaceIx(user: Keypair, userMarketAccount: PublicKey, userOpenOrderAccount: PublicKey, userCoinAta: PublicKey, userPcAta: PublicKey, args: Object) {
+        let ix = new TransactionInstruction({
+            keys: [
+                {pubkey: accountsAuthority.publicKey, isSigner: true, isWritable: true},
+                {pubkey: market, isSigner: false, isWritable: true},
+                {pubkey: marketEventsAccount.publicKey, isSigner: false, isWritable: true},
+                {pubkey: user.publicKey, isSigner: true, isWritable: true},
+                {pubkey: userMarketAccount, isSigner: false, isWritable: true},
+                {pubkey: userOpenOrderAccount, isSigner: false, isWritable: true},
+                {pubkey: userCoinAta, isSigner: false, isWritable: true},
+                {pubkey: userPcAta, isSigner: false, isWritable: true},
+                {pubkey: coinVault, isSigner: false, isWritable: true},
+                {pubkey: pcVault, isSigner: false, isWritable: true},
+                {pubkey: coinMint.publicKey, isSigner: false, isWritable: true},
+                {pubkey: pcMint.publicKey, isSigner: false, isWritable: true},
+                {pubkey: bids.publicKey, isSigner: false, isWritable: true},
+                {pubkey: asks.publicKey, isSigner: false, isWritable: true},
+                {pubkey: TOKEN_PROGRAM_ID, isSigner: false, isWritable: false},
+                {pubkey: oracle, isSigner: false, isWritable: false},
+            ],
+            programId: programId,
+            data: Buffer.concat([Buffer.from([9]), Buffer.from(borsh.serialize(CancelAndReplaceSchema, args))])
+        });
+
+        let tx = new Transaction().add(ix);
+        tx.recentBlockhash = svm.latestBlockhash();
+        tx.feePayer = user.publicKey;
+        tx.sign(accountsAuthority, user);
+        const sig = svm.sendTransaction(tx);
+        if (sig instanceof TransactionMetadata) {
+            console.log(sig.toString());
+        } else if (sig instanceof FailedTransactionMetadata) {
+            console.log(sig.toString());
+        }
+        return sig;
+    }
+
+    function createAndSendCreateOrdersIx(user: Keypair, userMarketAccount: PublicKey, userOpenOrderAccount: PublicKey, userCoinAta: PublicKey, userPcAta: PublicKey, args: Object) {
+        let ix = new TransactionInstruction({
+            keys: [
+                {pubkey: accountsAuthority.publicKey, isSigner: true, isWritable: true},
+                {pubkey: market, isSigner: false, isWritable: true},
+                {pubkey: marketEventsAccount.publicKey, isSigner: false, isWritable: true},
+                {pubkey: user.publicKey, isSigner: true, isWritable: true},
+                {pubkey: userMarketAccount, isSigner: false, isWritable: true},
+                {pubkey: userOpenOrderAccount, isSigner: false, isWritable: true},
+                {pubkey: userCoinAta, isSigner: false, isWritable: true},
+                {pubkey: userPcAta, isSigner: false, isWritable: true},
+                {pubkey: coinVault, isSigner: false, isWritable: true},
+                {pubkey: pcVault, isSigner: false, isWritable: true},
+                {pubkey: coinMint.publicKey, isSigner: false, isWritable: true},
+                {pubkey: pcMint.publicKey, isSigner: false, isWritable: true},
+                {pubkey: bids.publicKey, isSigner: false, isWritable: true},
+                {pubkey: asks.publicKey, isSigner: false, isWritable: true},
+                {pubkey: TOKEN_PROGRAM_ID, isSigner: false, isWritable: false},
+                {pubkey: oracle, isSigner: false, isWritable: false},
+            ],
+            programId: programId,
+            data: Buffer.concat([Buffer.from([10]), Buffer.from(borsh.serialize(CreateOrdersSchema, args))])
+        });
+
+        let tx = new Transaction().add(ix);
+        tx.recentBlockhash = svm.latestBlockhash();
+        tx.feePayer = user.publicKey;
+        tx.sign(accountsAuthority, user);
+        const sig = svm.sendTransaction(tx);
+        if (sig instanceof TransactionMetadata) {
+            console.log(sig.toString());
+        } else if (sig instanceof FailedTransactionMetadata) {
+            console.log(sig.toString());
+        }
+        return sig;
+    }
+
+    function createAndSendCreateTriggerOrderIx(user: Keypair, userMarketAccount: PublicKey, userAta: PublicKey, marketVault: PublicKey, args: Object) {
+        let ix = new TransactionInstruction({
+            keys: [
+                {pubkey: accountsAuthority.publicKey, isSigner: true, isWritable: true},
+                {pubkey: market, isSigner: false, isWritable: true},
+                {pubkey: user.publicKey, isSigner: true, isWritable: true},
+                {pubkey: userMarketAccount, isSigner: false, isWritable: true},
+                {pubkey: userAta, isSigner: false, isWritable: true},
+                {pubkey: marketVault, isSigner: false, isWritable: true},
+                {pubkey: coinMint.publicKey, isSigner: false, isWritable: true},
+                {pubkey: pcMint.publicKey, isSigner: false, isWritable: true},
+                {pubkey: triggerOrders.publicKey, isSigner: false, isWritable: true},
+                {pubkey: TOKEN_PROGRAM_ID, isSigner: false, isWritable: false},
+            ],
+            programId: programId,
+            data: Buffer.concat([Buffer.from([11]), Buffer.from(borsh.serialize(CreateTriggerOrderSchema, args))])
+        });
+
+        let tx = new Transaction().add(ix);
+        tx.recentBlockhash = svm.latestBlockhash();
+        tx.feePayer = user.publicKey;
+        tx.sign(accountsAuthority, user);
+        const sig = svm.sendTransaction(tx);
+        if (sig instanceof TransactionMetadata) {
+            console.log(sig.toString());
+        } else if (sig instanceof FailedTransactionMetadata) {
+            console.log(sig.toString());
+        }
+        return sig;
+    }
+
+    function createAndSendCancelTriggerOrderIx(user: Keypair, userMarketAccount: PublicKey, triggerId: bigint) {
+        let ix = new TransactionInstruction({
+            keys: [
+                {pubkey: market, isSigner: false, isWritable: true},
+                {pubkey: user.publicKey, isSigner: true, isWritable: true},
+                {pubkey: userMarketAccount, isSigner: false, isWritable: true},
+                {pubkey: coinMint.publicKey, isSigner: false, isWritable: false},
+                {pubkey: pcMint.publicKey, isSigner: false, isWritable: false},
+                {pubkey: triggerOrders.publicKey, isSigner: false, isWritable: true},
+            ],
+            programId: programId,
+            data: Buffer.concat([Buffer.from([12]), Buffer.from(borsh.serialize(CancelTriggerOrderSchema, {trigger_id: triggerId}))])
+        });
+
+        let tx = new Transaction().add(ix);
+        tx.recentBlockhash = svm.latestBlockhash();
+        tx.feePayer = user.publicKey;
+        tx.sign(user);
+        const sig = svm.sendTransaction(tx);
+        if (sig instanceof TransactionMetadata) {
+            console.log(sig.toString());
+        } else if (sig instanceof FailedTransactionMetadata) {
+            console.log(sig.toString());
+        }
+        return sig;
+    }
+
+    function createAndSendExecuteTriggerOrdersIx(limit: number, userAccounts: PublicKey[]) {
+        let ix = new TransactionInstruction({
+            keys: [
+                {pubkey: market, isSigner: false, isWritable: true},
+                {pubkey: marketEventsAccount.publicKey, isSigner: false, isWritable: true},
+                {pubkey: coinMint.publicKey, isSigner: false, isWritable: false},
+                {pubkey: pcMint.publicKey, isSigner: false, isWritable: false},
+                {pubkey: bids.publicKey, isSigner: false, isWritable: true},
+                {pubkey: asks.publicKey, isSigner: false, isWritable: true},
+                {pubkey: triggerOrders.publicKey, isSigner: false, isWritable: true},
+                {pubkey: oracle, isSigner: false, isWritable: false},
+                ...userAccounts.map(pubkey => ({pubkey, isSigner: false, isWritable: true}))
+            ],
+            programId: programId,
+            data: Buffer.concat([Buffer.from([13]), Buffer.from(borsh.serialize(ExecuteTriggerOrdersSchema, {limit}))])
+        });
+
+        let tx = new Transaction().add(ix);
+        tx.recentBlockhash = svm.latestBlockhash();
+        tx.feePayer = accountsAuthority.publicKey;
+        tx.sign(accountsAuthority);
+        const sig = svm.sendTransaction(tx);
+        if (sig instanceof TransactionMetadata) {
+            console.log(sig.toString());
+        } else if (sig instanceof FailedTransactionMetadata) {
+            console.log(sig.toString());
+        }
+        return sig;
+    }
+
+    //writes the mock oracle account the way an oracle program would, stamped with the current clock
+    function setOraclePrice(price: bigint) {
+        const data = Buffer.alloc(16);
+        data.writeBigUInt64LE(price, 0);
+        data.writeBigInt64LE(svm.getClock().unixTimestamp, 8);
+        svm.setAccount(oracle, {
+            lamports: Number(svm.minimumBalanceForRentExemption(BigInt(16))),
+            data,
+            owner: SystemProgram.programId,
+            executable: false
+        });
+    }
+
+    function createAndSendSweepFeesIx(authority: Keypair) {
+        let ix = new TransactionInstruction({
+            keys: [
+                {pubkey: authority.publicKey, isSigner: true, isWritable: true},
+                {pubkey: market, isSigner: false, isWritable: true},
+                {pubkey: coinMint.publicKey, isSigner: false, isWritable: false},
+                {pubkey: pcMint.publicKey, isSigner: false, isWritable: false},
+                {pubkey: pcVault, isSigner: false, isWritable: true},
+                {pubkey: feeDestination, isSigner: false, isWritable: true},
+                {pubkey: TOKEN_PROGRAM_ID, isSigner: false, isWritable: false},
+            ],
+            programId: programId,
+            data: Buffer.from([14])
+        });
+
+        let tx = new Transaction().add(ix);
+        tx.recentBlockhash = svm.latestBlockhash();
+        tx.feePayer = authority.publicKey;
+        tx.sign(authority);
+        const sig = svm.sendTransaction(tx);
+        if (sig instanceof TransactionMetadata) {
+            console.log(sig.toString());
+        } else if (sig instanceof FailedTransactionMetadata) {
+            console.log(sig.toString());
+        }
+        return sig;
+    }
+
+    function createAndSendReallocMarketAccountIx(authority: Keypair, account: PublicKey, kind: MarketAccountKind, newCapacity: number) {
+        let ix = new TransactionInstruction({
+            keys: [
+                {pubkey: authority.publicKey, isSigner: true, isWritable: true},
+                {pubkey: market, isSigner: false, isWritable: false},
+                {pubkey: coinMint.publicKey, isSigner: false, isWritable: false},
+                {pubkey: pcMint.publicKey, isSigner: false, isWritable: false},
+                {pubkey: account, isSigner: false, isWritable: true},
+                {pubkey: authority.publicKey, isSigner: true, isWritable: true},
+                {pubkey: SystemProgram.programId, isSigner: false, isWritable: false},
+            ],
+            programId: programId,
+            data: Buffer.concat([Buffer.from([15]), Buffer.from(borsh.serialize(ReallocMarketAccountSchema, {account: kind, new_capacity: newCapacity}))])
+        });
+
+        svm.expireBlockhash();
+        let tx = new Transaction().add(ix);
+        tx.recentBlockhash = svm.latestBlockhash();
+        tx.feePayer = authority.publicKey;
+        tx.sign(authority);
+        const sig = svm.sendTransaction(tx);
+        if (sig instanceof TransactionMetadata) {
+            console.log(sig.toString());
+        } else if (sig instanceof FailedTransactionMetadata) {
+            console.log(sig.toString());
+        }
+        return sig;
+    }
+
+    function createAndSendCloseUserAccountsIx(user: Keypair, userOpenOrderAccount: PublicKey, userMarketAccount: PublicKey) {
+        let ix = new TransactionInstruction({
+            keys: [
+                {pubkey: market, isSigner: false, isWritable: false},
+                {pubkey: marketEventsAccount.publicKey, isSigner: false, isWritable: false},
+                {pubkey: user.publicKey, isSigner: true, isWritable: true},
+                {pubkey: userOpenOrderAccount, isSigner: false, isWritable: true},
+                {pubkey: userMarketAccount, isSigner: false, isWritable: true},
+                {pubkey: coinMint.publicKey, isSigner: false, isWritable: false},
+                {pubkey: pcMint.publicKey, isSigner: false, isWritable: false},
+            ],
+            programId: programId,
+            data: Buffer.from([16])
+        });
+
+        svm.expireBlockhash();
+        let tx = new Transaction().add(ix);
+        tx.recentBlockhash = svm.latestBlockhash();
+        tx.feePayer = accountsAuthority.publicKey;
+        tx.sign(accountsAuthority, user);
+        const sig = svm.sendTransaction(tx);
+        if (sig instanceof TransactionMetadata) {
+            console.log(sig.toString());
+        } else if (sig instanceof FailedTransactionMetadata) {
+            console.log(sig.toString());
+        }
+        return sig;
+    }
+
+    function createAndSendConsumeEventsIx() {
+        let userMarketInfo = retrieveMakerTakerMarketAccountsFromEventQueueCronMethod(programId, market, marketEventsAccount.publicKey);
+        if (!userMarketInfo) {
+            return null;
+        }
+        let {userMarketList, count} = userMarketInfo;
+
+        let ix = new TransactionInstruction({
+            keys: [
+                {pubkey: market, isSigner: false, isWritable: true},
+                {pubkey: marketEventsAccount.publicKey, isSigner: false, isWritable: true},
+                {pubkey: coinMint.publicKey, isSigner: false, isWritable: true},
+                {pubkey: pcMint.publicKey, isSigner: false, isWritable: true}
+            ],
+            programId: programId,
+            data: Buffer.concat([Buffer.from([2]), Buffer.from(borsh.serialize(ConsumeEventsSchema, {drain_count: count}))])
+        });
+        userMarketList.forEach(pubKey => {
+            ix.keys.push({pubkey: pubKey, isSigner: false, isWritable: true});
+        });
+
+        svm.expireBlockhash();
+        let tx = new Transaction().add(ix);
+        tx.recentBlockhash = svm.latestBlockhash();
+        tx.feePayer = accountsAuthority.publicKey;
+        tx.sign(accountsAuthority);
+        const sig = svm.sendTransaction(tx);
+        if (sig instanceof TransactionMetadata) {
+            console.log(sig.toString());
+        } else if (sig instanceof FailedTransactionMetadata) {
+            console.log(sig.toString());
+        }
+        return sig;
+    }
+
+    function retrieveMakerTakerMarketAccountsFromEventQueueCronMethod(programId: PublicKey, marketAccount: PublicKey, marketEventsAccount: PublicKey) {
+        let mktEventInfo = svm.getAccount(marketEventsAccount);
+        //@ts-ignore
+        const mktEventData = new MarketEventsAccount(borsh.deserialize(MarketEventsAccountSchema, mktEventInfo!.data));
+
+        if (mktEventData.head == mktEventData.tail) {
+            return null;
+        } 
+        let size = mktEventData.size();
         let count = Math.min(size, MAX_DRAIN_COUNT);
         let tail = mktEventData.tail.valueOf();
         let pubKeySet = new Set();
+        let outMakerSet = new Set();
         for (let i = tail; i < tail+count; i++) {
             pubKeySet.add(new PublicKey(mktEventData.events[i].maker).toString());
+            if (mktEventData.events[i].maker_out == 1) {
+                outMakerSet.add(new PublicKey(mktEventData.events[i].maker).toString());
+            }
             pubKeySet.add(new PublicKey(mktEventData.events[i].taker).toString());
+            if (!new PublicKey(mktEventData.events[i].referrer).equals(PublicKey.default)) {
+                pubKeySet.add(new PublicKey(mktEventData.events[i].referrer).toString());
+            }
         }
         let userMarketList = new Array();
         pubKeySet.forEach(pubKey => {
@@ -1288,6 +4756,19 @@ describe("Orderbook tests", () => {
             )[0];
             userMarketList.push(userMarketAccount);
         });
+        //makers whose orders left the book get their open order slots freed
+        outMakerSet.forEach(pubKey => {
+            let publicKey = new PublicKey(pubKey as string);
+            let openOrderAccount = PublicKey.findProgramAddressSync(
+                [
+                    Buffer.from("open_order"),
+                    marketAccount.toBuffer(),
+                    publicKey.toBuffer()
+                ],
+                programId
+            )[0];
+            userMarketList.push(openOrderAccount);
+        });
         return {
             userMarketList: userMarketList,
             count: count
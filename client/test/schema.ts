@@ -1,6 +1,6 @@
 import * as borsh from "borsh";
 import BN from "bn.js";
-import { MAX_EVENT } from "./utils";
+import { BOOK_LEAF_TAG, BOOK_NIL } from "./utils";
 
 const PubKeyType = {
     "array": {
@@ -14,11 +14,60 @@ export enum Side {
     Ask = 1
 }
 
+export enum OrderType {
+    Limit = 0,
+    ImmediateOrCancel = 1,
+    FillOrKill = 2,
+    PostOnly = 3,
+    Market = 4
+}
+
+export enum SelfTradeBehavior {
+    CancelMaker = 0,
+    CancelTaker = 1,
+    DecrementAndCancel = 2,
+    AbortTransaction = 3
+}
+
+export enum MatchLimitBehavior {
+    FailOrder = 0,
+    DropRemainder = 1
+}
+
+export enum MarketAccountKind {
+    Bids = 0,
+    Asks = 1,
+    Events = 2
+}
+
 export enum EventType {
     Fill = 0,
     Out = 1 
 }
 
+//custom error codes returned by the program
+export enum OrderBookError {
+    BookFull = 1,
+    EventQueueFull = 2,
+    ArithmeticOverflow = 3,
+    InsufficientFunds = 4,
+    OrderNotFound = 5,
+    EventQueueEmpty = 6,
+    TooManyOpenOrders = 7,
+    MatchLimitReached = 8,
+    OpenOrdersRemaining = 9,
+    LockedBalanceRemaining = 10,
+    FreeBalanceRemaining = 11,
+    PendingEvents = 12,
+    UserAccountsNotCreated = 13,
+    OrderWouldCross = 14,
+    FillOrKillNotFilled = 15,
+    SelfTradeAborted = 16,
+    MarketOrderLimitExceeded = 17,
+    InvalidTickSize = 18,
+    BelowMinOrderSize = 19
+}
+
 
 export class MarketState {
     coin_vault: Uint8Array;
@@ -27,7 +76,20 @@ export class MarketState {
     pc_mint: Uint8Array;
     bids: Uint8Array;
     asks: Uint8Array;
+    trigger_orders: Uint8Array;
+    oracle: Uint8Array;
+    authority: Uint8Array;
+    fee_destination: Uint8Array;
     next_order_id: bigint;
+    last_traded_price: bigint;
+    tick_size: bigint;
+    base_lot_size: bigint;
+    quote_lot_size: bigint;
+    min_order_size: bigint;
+    maker_fee_bps: Number;
+    taker_fee_bps: Number;
+    referrer_fee_share_bps: Number;
+    fees_accrued: bigint;
     bump: Number;
 
     constructor(fields: {
@@ -37,7 +99,20 @@ export class MarketState {
         pc_mint: Uint8Array,
         bids: Uint8Array,
         asks: Uint8Array,
+        trigger_orders: Uint8Array,
+        oracle: Uint8Array,
+        authority: Uint8Array,
+        fee_destination: Uint8Array,
         next_order_id: bigint,
+        last_traded_price: bigint,
+        tick_size: bigint,
+        base_lot_size: bigint,
+        quote_lot_size: bigint,
+        min_order_size: bigint,
+        maker_fee_bps: Number,
+        taker_fee_bps: Number,
+        referrer_fee_share_bps: Number,
+        fees_accrued: bigint,
         bump: Number
     }) {
         this.coin_vault = fields.coin_vault;
@@ -46,7 +121,20 @@ export class MarketState {
         this.pc_mint = fields.pc_mint;
         this.bids = fields.bids;
         this.asks = fields.asks;
+        this.trigger_orders = fields.trigger_orders;
+        this.oracle = fields.oracle;
+        this.authority = fields.authority;
+        this.fee_destination = fields.fee_destination;
         this.next_order_id = fields.next_order_id;
+        this.last_traded_price = fields.last_traded_price;
+        this.tick_size = fields.tick_size;
+        this.base_lot_size = fields.base_lot_size;
+        this.quote_lot_size = fields.quote_lot_size;
+        this.min_order_size = fields.min_order_size;
+        this.maker_fee_bps = fields.maker_fee_bps;
+        this.taker_fee_bps = fields.taker_fee_bps;
+        this.referrer_fee_share_bps = fields.referrer_fee_share_bps;
+        this.fees_accrued = fields.fees_accrued;
         this.bump = fields.bump;
     }
 }
@@ -59,7 +147,20 @@ export const MarketStateSchema: borsh.Schema  = {
         pc_mint: PubKeyType,
         bids: PubKeyType,
         asks: PubKeyType,
+        trigger_orders: PubKeyType,
+        oracle: PubKeyType,
+        authority: PubKeyType,
+        fee_destination: PubKeyType,
         next_order_id: "u64",
+        last_traded_price: "u64",
+        tick_size: "u64",
+        base_lot_size: "u64",
+        quote_lot_size: "u64",
+        min_order_size: "u64",
+        maker_fee_bps: "i16",
+        taker_fee_bps: "u16",
+        referrer_fee_share_bps: "u16",
+        fees_accrued: "u64",
         bump: "u8"
     }
 }
@@ -72,6 +173,12 @@ export class Order {
     quantity: bigint;
     filled_quantity: bigint;
     side: Number;
+    client_order_id: bigint;
+    expiry_timestamp: bigint;
+    display_quantity: bigint;
+    reserve_quantity: bigint;
+    pegged: Number;
+    peg_offset: bigint;
 
     constructor(fields: {
         order_id: bigint;
@@ -81,6 +188,12 @@ export class Order {
         quantity: bigint;
         filled_quantity: bigint;
         side: Number;
+        client_order_id: bigint;
+        expiry_timestamp: bigint;
+        display_quantity: bigint;
+        reserve_quantity: bigint;
+        pegged: Number;
+        peg_offset: bigint;
     }) {
         this.order_id = fields.order_id;
         this.owner = fields.owner;
@@ -89,6 +202,12 @@ export class Order {
         this.quantity = fields.quantity;
         this.filled_quantity = fields.filled_quantity;
         this.side = fields.side
+        this.client_order_id = fields.client_order_id
+        this.expiry_timestamp = fields.expiry_timestamp
+        this.display_quantity = fields.display_quantity
+        this.reserve_quantity = fields.reserve_quantity
+        this.pegged = fields.pegged
+        this.peg_offset = fields.peg_offset
     }
 }
 
@@ -100,42 +219,264 @@ export const OrderSchema: borsh.Schema = {
         price: "u64",
         quantity: "u64",
         filled_quantity: "u64",
-        side: "u8"
+        side: "u8",
+        client_order_id: "u64",
+        expiry_timestamp: "i64",
+        display_quantity: "u64",
+        reserve_quantity: "u64",
+        pegged: "u8",
+        peg_offset: "i64"
+    }
+}
+
+
+export class LeafNode {
+    key: bigint;
+    order: Order;
+
+    constructor(fields: {
+        key: bigint;
+        order: Order;
+    }) {
+        this.key = fields.key;
+        this.order = fields.order;
+    }
+}
+
+export const LeafNodeSchema: borsh.Schema = {
+    struct: {
+        key: "u128",
+        order: OrderSchema
+    }
+}
+
+export class InnerNode {
+    prefix_len: number;
+    key: bigint;
+    children: number[];
+    peg_reach: bigint;
+
+    constructor(fields: {
+        prefix_len: number;
+        key: bigint;
+        children: number[];
+        peg_reach: bigint;
+    }) {
+        this.prefix_len = fields.prefix_len;
+        this.key = fields.key;
+        this.children = fields.children;
+        this.peg_reach = fields.peg_reach;
+    }
+}
+
+export const InnerNodeSchema: borsh.Schema = {
+    struct: {
+        prefix_len: "u32",
+        key: "u128",
+        children: {
+            "array": {
+                len: 2,
+                type: "u32"
+            }
+        },
+        peg_reach: "i128"
+    }
+}
+
+export class BookSlot {
+    leaf: LeafNode;
+    inner_node: InnerNode;
+    order_id_node: InnerNode;
+
+    constructor(fields: {
+        leaf: LeafNode;
+        inner_node: InnerNode;
+        order_id_node: InnerNode;
+    }) {
+        this.leaf = fields.leaf;
+        this.inner_node = fields.inner_node;
+        this.order_id_node = fields.order_id_node;
     }
 }
 
+export const BookSlotSchema: borsh.Schema = {
+    struct: {
+        leaf: LeafNodeSchema,
+        inner_node: InnerNodeSchema,
+        order_id_node: InnerNodeSchema
+    }
+}
 
 export class OrderBook {
     side: Number;
     market: Uint8Array;
     next_order_id: bigint;
-    orders: Order[];
+    next_seq_num: bigint;
+    fixed_root: number;
+    pegged_root: number;
+    order_id_root: number;
+    free_leaf: number;
+    free_inner_node: number;
+    free_order_id_node: number;
     slots_filled: Number;
+    slots: BookSlot[];
+    capacity: number;
+    orders: Order[];    //resting orders in the order a taker meets them, fixed price orders first
 
     constructor(fields: {
         side: Number;
         market: Uint8Array;
         next_order_id: bigint;
-        orders: Order[];
+        next_seq_num: bigint;
+        fixed_root: number;
+        pegged_root: number;
+        order_id_root: number;
+        free_leaf: number;
+        free_inner_node: number;
+        free_order_id_node: number;
         slots_filled: Number;
+        slots: BookSlot[];
     }) {
         this.side = fields.side;
         this.market = fields.market;
         this.next_order_id = fields.next_order_id;
-        this.orders = fields.orders;
+        this.next_seq_num = fields.next_seq_num;
+        this.fixed_root = fields.fixed_root;
+        this.pegged_root = fields.pegged_root;
+        this.order_id_root = fields.order_id_root;
+        this.free_leaf = fields.free_leaf;
+        this.free_inner_node = fields.free_inner_node;
+        this.free_order_id_node = fields.free_order_id_node;
         this.slots_filled = fields.slots_filled;
+        this.slots = fields.slots;
+        this.capacity = fields.slots.length;
+        this.orders = [...this.ordersByPriority(this.fixed_root), ...this.ordersByPriority(this.pegged_root)];
+    }
+
+    //walks a tree taking the better child first, bids from the highest key and asks from the lowest
+    ordersByPriority(root: number): Order[] {
+        const orders: Order[] = [];
+        const stack = root === BOOK_NIL ? [] : [root];
+        while (stack.length > 0) {
+            const node = stack.pop()!;
+            if (node >= BOOK_LEAF_TAG) {
+                orders.push(this.slots[node - BOOK_LEAF_TAG].leaf.order);
+                continue;
+            }
+            const [left, right] = this.slots[node].inner_node.children;
+            if (this.side === Side.Bid) {
+                stack.push(left, right);
+            } else {
+                stack.push(right, left);
+            }
+        }
+        return orders;
     }
 }
 
+//the stored capacity sits right before the slots, so it is read as their length prefix
 export const OrderBookSchema: borsh.Schema = {
     struct: {
         side: "u8",
         market: PubKeyType,
         next_order_id: "u64",
+        next_seq_num: "u64",
+        fixed_root: "u32",
+        pegged_root: "u32",
+        order_id_root: "u32",
+        free_leaf: "u32",
+        free_inner_node: "u32",
+        free_order_id_node: "u32",
+        slots_filled: "u32",
+        slots: {
+            "array": {
+                type: BookSlotSchema
+            }
+        }
+    }
+}
+
+
+export class TriggerOrder {
+    trigger_id: bigint;
+    owner: Uint8Array;
+    side: Number;
+    order_type: Number;
+    self_trade_behavior: Number;
+    trigger_price: bigint;
+    limit_price: bigint;
+    coin_qty: bigint;
+    pc_qty: bigint;
+    client_order_id: bigint;
+
+    constructor(fields: {
+        trigger_id: bigint;
+        owner: Uint8Array;
+        side: Number;
+        order_type: Number;
+        self_trade_behavior: Number;
+        trigger_price: bigint;
+        limit_price: bigint;
+        coin_qty: bigint;
+        pc_qty: bigint;
+        client_order_id: bigint;
+    }) {
+        this.trigger_id = fields.trigger_id;
+        this.owner = fields.owner;
+        this.side = fields.side;
+        this.order_type = fields.order_type;
+        this.self_trade_behavior = fields.self_trade_behavior;
+        this.trigger_price = fields.trigger_price;
+        this.limit_price = fields.limit_price;
+        this.coin_qty = fields.coin_qty;
+        this.pc_qty = fields.pc_qty;
+        this.client_order_id = fields.client_order_id;
+    }
+}
+
+export const TriggerOrderSchema: borsh.Schema = {
+    struct: {
+        trigger_id: "u64",
+        owner: PubKeyType,
+        side: "u8",
+        order_type: "u8",
+        self_trade_behavior: "u8",
+        trigger_price: "u64",
+        limit_price: "u64",
+        coin_qty: "u64",
+        pc_qty: "u64",
+        client_order_id: "u64"
+    }
+}
+
+
+export class TriggerOrderBook {
+    market: Uint8Array;
+    next_trigger_id: bigint;
+    orders: TriggerOrder[];
+    slots_filled: Number;
+
+    constructor(fields: {
+        market: Uint8Array;
+        next_trigger_id: bigint;
+        orders: TriggerOrder[];
+        slots_filled: Number;
+    }) {
+        this.market = fields.market;
+        this.next_trigger_id = fields.next_trigger_id;
+        this.orders = fields.orders;
+        this.slots_filled = fields.slots_filled;
+    }
+}
+
+export const TriggerOrderBookSchema: borsh.Schema = {
+    struct: {
+        market: PubKeyType,
+        next_trigger_id: "u64",
         orders: {
             "array": {
-                len: 1024,
-                type: OrderSchema
+                len: 256,
+                type: TriggerOrderSchema
             }
         },
         slots_filled: "u16"
@@ -146,6 +487,7 @@ export class OpenOrderAccount {
     owner: Uint8Array;
     market: Uint8Array;
     order_ids: BN[];
+    client_order_ids: BN[];
     next_array_index: Number;
     bump: Number;
 
@@ -153,12 +495,14 @@ export class OpenOrderAccount {
         owner: Uint8Array;
         market: Uint8Array;
         order_ids: BN[];
+        client_order_ids: BN[];
         next_array_index: Number;
         bump: Number;
     }) {
         this.owner = fields.owner;
         this.market = fields.market;
         this.order_ids = fields.order_ids;
+        this.client_order_ids = fields.client_order_ids;
         this.next_array_index = fields.next_array_index;
         this.bump = fields.bump;
     }
@@ -174,6 +518,12 @@ export const OpenOrderAccountSchema: borsh.Schema = {
                 type: "u64"
             }
         },
+        client_order_ids: {
+            array: {
+                len: 64,
+                type: "u64"
+            }
+        },
         next_array_index: "u8",
         bump: "u8"
     }
@@ -228,6 +578,11 @@ export class Event {
     maker: Uint8Array;
     taker: Uint8Array;
     maker_order_id: bigint;
+    maker_client_order_id: bigint;
+    referrer: Uint8Array;
+    maker_pc_locked: bigint;
+    maker_out: Number;
+    taker_client_order_id: bigint;
     coin_qty: bigint;
     pc_qty: bigint;
     event_type: Number;
@@ -237,6 +592,11 @@ export class Event {
         maker: Uint8Array;
         taker: Uint8Array;
         maker_order_id: bigint;
+        maker_client_order_id: bigint;
+        referrer: Uint8Array;
+        maker_pc_locked: bigint;
+        maker_out: Number;
+        taker_client_order_id: bigint;
         coin_qty: bigint;
         pc_qty: bigint;
         event_type: Number;
@@ -245,6 +605,11 @@ export class Event {
         this.maker = fields.maker
         this.taker = fields.taker
         this.maker_order_id = fields.maker_order_id
+        this.maker_client_order_id = fields.maker_client_order_id
+        this.referrer = fields.referrer
+        this.maker_pc_locked = fields.maker_pc_locked
+        this.maker_out = fields.maker_out
+        this.taker_client_order_id = fields.taker_client_order_id
         this.coin_qty = fields.coin_qty
         this.pc_qty = fields.pc_qty
         this.event_type = fields.event_type
@@ -260,7 +625,12 @@ export const EventSchema: borsh.Schema = {
         taker: PubKeyType,
         coin_qty: "u64",
         pc_qty: "u64",
-        maker_order_id: "u64"
+        maker_order_id: "u64",
+        maker_client_order_id: "u64",
+        referrer: PubKeyType,
+        maker_pc_locked: "u64",
+        maker_out: "u8",
+        taker_client_order_id: "u64"
     }
 }
 
@@ -269,6 +639,7 @@ export class MarketEventsAccount {
     head: Number;
     tail: Number;
     events: Event[];
+    capacity: number;
     constructor(fields: {
         market: Uint8Array;
         head: Number;
@@ -279,36 +650,66 @@ export class MarketEventsAccount {
         this.head = fields.head
         this.tail = fields.tail
         this.events = fields.events
+        this.capacity = fields.events.length
     }
 
     size() {
         if (this.head >= this.tail) {
             return (this.head.valueOf() - this.tail.valueOf());
         }
-        return MAX_EVENT - (this.tail.valueOf() - this.head.valueOf());
+        return this.capacity - (this.tail.valueOf() - this.head.valueOf());
     }
 }
 
+//the stored capacity sits right before the events, so it is read as their length prefix
 export const MarketEventsAccountSchema: borsh.Schema = {
     struct: {
         market: PubKeyType,
-        head: "u16", 
-        tail: "u16",
+        head: "u32", 
+        tail: "u32",
         events: {
             array: {
-                len: MAX_EVENT,
                 type: EventSchema
             }
         },
     }
 }
 
+export const InitializeMarketSchema: borsh.Schema = {
+    struct: {
+        tick_size: "u64",
+        base_lot_size: "u64",
+        quote_lot_size: "u64",
+        min_order_size: "u64",
+        maker_fee_bps: "i16",
+        taker_fee_bps: "u16",
+        referrer_fee_share_bps: "u16"
+    }
+}
+
 export const CreateOrderSchema: borsh.Schema = {
     struct: {
         side: "u8",
         limit_price: "u64",
         coin_qty: "u64",
-        pc_qty: "u64"
+        order_type: "u8",
+        pc_qty_limit: "u64",
+        self_trade_behavior: "u8",
+        client_order_id: "u64",
+        expiry_timestamp: {
+            option: "i64"
+        },
+        display_quantity: "u64",
+        peg_offset: {
+            option: "i64"
+        },
+        referrer: {
+            option: PubKeyType
+        },
+        match_limit: {
+            option: "u16"
+        },
+        match_limit_behavior: "u8"
     }
 }
 
@@ -325,3 +726,73 @@ export const CancelOrderSchema: borsh.Schema = {
     }
 }
 
+export const CancelOrderByClientIdSchema: borsh.Schema = {
+    struct : {
+        client_order_id: "u64"
+    }
+}
+
+export const PruneExpiredOrdersSchema: borsh.Schema = {
+    struct : {
+        side: "u8",
+        prune_limit: "u8"
+    }
+}
+
+export const AmendOrderSchema: borsh.Schema = {
+    struct : {
+        order_id: "u64",
+        side: "u8",
+        new_price: "u64",
+        new_quantity: "u64"
+    }
+}
+
+export const CancelAllOrdersSchema: borsh.Schema = {
+    struct : {
+        side: {option: "u8"},
+        limit: "u8"
+    }
+}
+
+export const CancelAndReplaceSchema: borsh.Schema = {
+    struct : {
+        cancel_orders: {array: {type: CancelOrderSchema}},
+        new_orders: {array: {type: CreateOrderSchema}}
+    }
+}
+
+export const CreateOrdersSchema: borsh.Schema = {
+    array: {type: CreateOrderSchema}
+}
+
+export const CreateTriggerOrderSchema: borsh.Schema = {
+    struct : {
+        side: "u8",
+        trigger_price: "u64",
+        limit_price: "u64",
+        coin_qty: "u64",
+        order_type: "u8",
+        self_trade_behavior: "u8",
+        client_order_id: "u64"
+    }
+}
+
+export const CancelTriggerOrderSchema: borsh.Schema = {
+    struct : {
+        trigger_id: "u64"
+    }
+}
+
+export const ExecuteTriggerOrdersSchema: borsh.Schema = {
+    struct : {
+        limit: "u8"
+    }
+}
+
+export const ReallocMarketAccountSchema: borsh.Schema = {
+    struct : {
+        account: "u8",
+        new_capacity: "u32"
+    }
+}
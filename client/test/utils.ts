@@ -1,11 +1,41 @@
-export const ORDERBOOK_LEN = 99371;
-export const EVENT_ACCOUNT_LEN = 46116;
+import { FailedTransactionMetadata } from "litesvm";
 
+//capacities the test market is created with, the program derives them from the account sizes
 export const MAX_EVENT = 512;
+export const MAX_ORDERS = 1024;
+
+export const ORDERBOOK_LEN = orderBookAccountLen(MAX_ORDERS);
+export const EVENT_ACCOUNT_LEN = eventsAccountLen(MAX_EVENT);
+export const TRIGGER_ORDERS_LEN = 21290;
+
+//book node handles with this bit set point at a leaf, BOOK_NIL marks an empty tree
+export const BOOK_LEAF_TAG = 0x80000000;
+export const BOOK_NIL = 0xffffffff;
 
 export const MAX_DRAIN_COUNT = 5;
 
+//header followed by one leaf and one inner node per order
+export function orderBookAccountLen(capacity: number) {
+    return 81 + 242 * capacity;
+}
+
+//header followed by the ring of events
+export function eventsAccountLen(capacity: number) {
+    return 44 + 147 * capacity;
+}
+
 export function createSideEncodedOrderId(plainOrderId: bigint, side: number) {
     const sideBytes = BigInt(side) << BigInt(63);
     return sideBytes | plainOrderId;
-}
\ No newline at end of file
+}
+
+//reads the custom program error code out of a failed transaction's logs
+export function customErrorCode(sig: FailedTransactionMetadata) {
+    for (const log of sig.meta().logs()) {
+        const matched = log.match(/custom program error: 0x([0-9a-f]+)/);
+        if (matched) {
+            return parseInt(matched[1], 16);
+        }
+    }
+    return null;
+}
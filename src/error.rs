@@ -0,0 +1,34 @@
+use solana_program::program_error::ProgramError;
+
+
+//program specific errors, returned as ProgramError::Custom with these codes so clients can match on them
+//codes are stable, new errors only ever get appended
+#[repr(u32)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum OrderBookError {
+    BookFull = 1,                   //bids, asks or trigger orders have no free slot left
+    EventQueueFull = 2,             //the event queue can not hold the events of the instruction
+    ArithmeticOverflow = 3,         //an amount does not fit in its type
+    InsufficientFunds = 4,          //a free, locked or token balance does not cover the amount needed
+    OrderNotFound = 5,              //no resting or trigger order with the given id
+    EventQueueEmpty = 6,            //more events were asked to be consumed than the queue holds
+    TooManyOpenOrders = 7,          //every slot of the user's open order account is taken
+    MatchLimitReached = 8,          //the match limit stopped an order whose remainder would rest across the book
+    OpenOrdersRemaining = 9,        //the user's accounts can not be closed while orders of theirs still rest in the book
+    LockedBalanceRemaining = 10,    //the user's accounts can not be closed while they have locked coin or pc
+    FreeBalanceRemaining = 11,      //the user's accounts can not be closed before their free coin and pc are settled
+    PendingEvents = 12,             //the user's accounts can not be closed while events of theirs wait to be consumed
+    UserAccountsNotCreated = 13,    //the user's open order or user market account does not exist yet, CreateOrder creates them
+    OrderWouldCross = 14,           //a post only order or an amended price would take liquidity from the book
+    FillOrKillNotFilled = 15,       //a fill or kill order could not be filled in full
+    SelfTradeAborted = 16,          //an order set to abort on self trade met its owner's order
+    MarketOrderLimitExceeded = 17,  //a market order moved more pc than its bound allows, or received less
+    InvalidTickSize = 18,           //a price or peg offset is not a positive multiple of the market's tick size
+    BelowMinOrderSize = 19          //an order quantity is below the market's minimum order size
+}
+
+impl From<OrderBookError> for ProgramError {
+    fn from(error: OrderBookError) -> Self {
+        ProgramError::Custom(error as u32)
+    }
+}
@@ -0,0 +1,242 @@
+use std::io::Cursor;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::{account_info::{next_account_info, AccountInfo}, clock::Clock, entrypoint::ProgramResult, msg, program_error::ProgramError, pubkey::Pubkey, sysvar::Sysvar};
+
+use crate::{error::OrderBookError, instructions::create_order::load_oracle_price, state::{AmendOrderArgs, Event, EventType, MarketEventsAccount, MarketState, OrderBook, Side, UserMarketAccount}};
+
+pub fn amend_order(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    args: AmendOrderArgs
+) -> ProgramResult {
+    let mut iter = accounts.iter();
+
+    let market_account = next_account_info(&mut iter)?;
+    let market_events_account = next_account_info(&mut iter)?;
+    let owner_account = next_account_info(&mut iter)?;
+    let user_market_account = next_account_info(&mut iter)?;
+    let coin_mint_account = next_account_info(&mut iter)?;
+    let pc_mint_account = next_account_info(&mut iter)?;
+    let bids_account = next_account_info(&mut iter)?;
+    let asks_account = next_account_info(&mut iter)?;
+    let oracle_account = next_account_info(&mut iter)?;
+
+    let now = Clock::get()?.unix_timestamp;
+
+
+    //verify owner
+    if !owner_account.is_signer {
+        msg!("Owner must sign to amend orders");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    //verify market account
+    let market_seeds = &[b"market", pc_mint_account.key.as_ref(), coin_mint_account.key.as_ref()];
+
+    let market_pda = Pubkey::find_program_address(
+        market_seeds,
+        program_id
+    ).0;
+
+    if *market_account.key != market_pda {
+        msg!("Invalid market account provided, expected: {}", market_pda);
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let market_data = MarketState::try_from_slice(&market_account.data.borrow())?;
+    let oracle_price = load_oracle_price(oracle_account, &market_data, now)?;
+
+
+    //verify market events account
+    if *market_events_account.owner != *program_id {
+        msg!("Invalid market events account provided, it has wrong owner");
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+
+    //verify user market account
+    let user_market_seeds = [b"user_market_account", market_account.key.as_ref(), owner_account.key.as_ref()];
+
+    let user_market_pda = Pubkey::find_program_address(&user_market_seeds, program_id).0;
+
+    if user_market_pda != *user_market_account.key {
+        msg!("Invalid user market account provided, expected: {}", user_market_pda);
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if user_market_account.lamports() == 0 {
+        msg!("User Market Account has not been initialised");
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let mut user_market_raw_data = user_market_account.data.borrow_mut();
+    let reader = &user_market_raw_data[..];
+    let mut user_market_data = UserMarketAccount::try_from_slice(reader)?;
+
+    if user_market_data.owner != *owner_account.key {
+        msg!("Invalid user market account, does not belongs to provided owner");
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if user_market_data.market != *market_account.key {
+        msg!("Invalid user market account, does not belongs to provided market");
+        return Err(ProgramError::InvalidAccountData);
+    }
+    msg!("User Market account verified");
+
+
+    //verify bids and asks accounts
+    if *bids_account.owner != *program_id {
+        msg!("Invalid bids account provided, it has wrong owner");
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if *asks_account.owner != *program_id {
+        msg!("Invalid asks account provided, it has wrong owner");
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let mut bids_raw_data = bids_account.data.borrow_mut();
+    let mut bids_data = OrderBook::load(&mut bids_raw_data)?;
+
+    let mut asks_raw_data = asks_account.data.borrow_mut();
+    let mut asks_data = OrderBook::load(&mut asks_raw_data)?;
+
+    if bids_data.header.side != Side::Bid || bids_data.header.market != *market_account.key {
+        msg!("Invalid bids account provided, it is not the bids of this market");
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if asks_data.header.side != Side::Ask || asks_data.header.market != *market_account.key {
+        msg!("Invalid asks account provided, it is not the asks of this market");
+        return Err(ProgramError::InvalidAccountData);
+    }
+    msg!("Accounts verification success");
+
+    let (order_book_data, opposite_book_data) = match args.side {
+        Side::Bid => (&mut bids_data, &mut asks_data),
+        Side::Ask => (&mut asks_data, &mut bids_data)
+    };
+
+
+    //find the order
+    let index = order_book_data.find_order_index(args.order_id).ok_or_else(|| {
+        msg!("Order Id is not present");
+        OrderBookError::OrderNotFound
+    })?;
+    let order = *order_book_data.order(index);
+
+    if order.owner != *owner_account.key {
+        msg!("Owner mismatch, you do not own the order");
+        return Err(ProgramError::IllegalOwner);
+    }
+
+    if args.new_quantity == 0 {
+        msg!("Amended quantity must be positive, cancel the order instead");
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    if args.new_price == 0 || args.new_price % market_data.tick_size != 0 {
+        msg!("Amended price {} is not a positive multiple of the tick size {}", args.new_price, market_data.tick_size);
+        return Err(OrderBookError::InvalidTickSize.into());
+    }
+
+    //amend the order, only a quantity reduction at the same price keeps its queue position
+    //pegged orders keep their offset, the new price becomes their peg limit
+    let quantity_remaining = order.remaining_quantity()?;
+    let keeps_priority = args.new_price == order.price && args.new_quantity <= quantity_remaining;
+
+    //iceberg orders keep showing at most their display quantity
+    let visible_quantity = if order.display_quantity > 0 {
+        args.new_quantity.min(order.display_quantity)
+    } else {
+        args.new_quantity
+    };
+
+    let mut amended_order = order;
+    amended_order.price = args.new_price;
+    amended_order.quantity = order.filled_quantity.checked_add(visible_quantity).ok_or(OrderBookError::ArithmeticOverflow)?;
+    amended_order.reserve_quantity = args.new_quantity - visible_quantity;
+
+    //amended price must not cross the book, expired makers do not count
+    let best_order = opposite_book_data.best_order(oracle_price, |order| order.is_expired(now));
+    if let (Some((_, best_price)), Some(amended_price)) = (best_order, amended_order.effective_price(oracle_price)) {
+        if args.side.is_crossed(amended_price, best_price) {
+            msg!("Amended order would cross the book at price {}", best_price);
+            return Err(OrderBookError::OrderWouldCross.into());
+        }
+    }
+
+    if keeps_priority {
+        *order_book_data.order_mut(index) = amended_order;
+        msg!("Amended order in place");
+    } else {
+        order_book_data.remove_order(index)?;
+        order_book_data.add_order(amended_order)?;
+        msg!("Amended order moved to the back of its price level");
+    }
+
+
+    //adjust locked balance for the amended order
+    let (locked_before, locked_after) = match args.side {
+        Side::Bid => (
+            market_data.bid_lock_between(order.price, order.filled_quantity, order.total_quantity()?)?,
+            market_data.bid_lock_between(args.new_price, amended_order.filled_quantity, amended_order.total_quantity()?)?
+        ),
+        Side::Ask => (market_data.coin_native(quantity_remaining)?, market_data.coin_native(args.new_quantity)?)
+    };
+
+    if locked_after > locked_before {
+        let lock_amount = locked_after - locked_before;
+        match args.side {
+            Side::Bid => {
+                if user_market_data.free_pc < lock_amount {
+                    msg!("Not enough free PC balance to amend the order, needs {}", lock_amount);
+                    return Err(OrderBookError::InsufficientFunds.into());
+                }
+                user_market_data.lock_free_pc(&lock_amount)?;
+            }
+            Side::Ask => {
+                if user_market_data.free_coin < lock_amount {
+                    msg!("Not enough free Coin balance to amend the order, needs {}", lock_amount);
+                    return Err(OrderBookError::InsufficientFunds.into());
+                }
+                user_market_data.lock_free_coin(&lock_amount)?;
+            }
+        };
+        msg!("Locked additional funds for amended order");
+    } else if locked_after < locked_before {
+        //emit out event so the released part gets unlocked when events are consumed
+        let released_amount = locked_before - locked_after;
+        let (coin_qty, pc_qty) = match args.side {
+            Side::Bid => (market_data.coin_native(quantity_remaining.saturating_sub(args.new_quantity))?, released_amount),
+            Side::Ask => (released_amount, market_data.pc_native(args.new_price, quantity_remaining.saturating_sub(args.new_quantity))?)
+        };
+
+        let market_events_raw_data = &mut market_events_account.data.borrow_mut();
+        let mut market_events_data = MarketEventsAccount::load(market_events_raw_data)?;
+
+        let event = Event {
+            event_type: EventType::Out,
+            side: args.side,
+            maker: *owner_account.key,
+            taker: *owner_account.key,
+            coin_qty,
+            pc_qty,
+            maker_order_id: order.order_id,
+            maker_client_order_id: order.client_order_id,
+            referrer: Pubkey::default(),
+            maker_pc_locked: 0,
+            maker_out: 0,
+            taker_client_order_id: 0
+        };
+        market_events_data.enqueue(event)?;
+        msg!("Emitted Out Event");
+    }
+
+    let mut writer = Cursor::new(&mut user_market_raw_data[..]);
+    user_market_data.serialize(&mut writer)?;
+
+    Ok(())
+}
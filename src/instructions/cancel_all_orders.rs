@@ -0,0 +1,147 @@
+use borsh::BorshDeserialize;
+use solana_program::{account_info::{next_account_info, AccountInfo}, entrypoint::ProgramResult, msg, program_error::ProgramError, pubkey::Pubkey};
+
+use crate::state::{CancelAllOrdersArgs, Event, MarketEventsAccount, MarketState, OpenOrderAccount, OrderBook, Side};
+
+pub fn cancel_all_orders(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    args: CancelAllOrdersArgs
+) -> ProgramResult {
+    let mut iter = accounts.iter();
+
+    let market_account = next_account_info(&mut iter)?;
+    let market_events_account = next_account_info(&mut iter)?;
+    let owner_account = next_account_info(&mut iter)?;
+    let open_order_account = next_account_info(&mut iter)?;
+    let coin_mint_account = next_account_info(&mut iter)?;
+    let pc_mint_account = next_account_info(&mut iter)?;
+    let bids_account = next_account_info(&mut iter)?;
+    let asks_account = next_account_info(&mut iter)?;
+
+
+    //verify owner
+    if !owner_account.is_signer {
+        msg!("Owner must sign to cancel orders");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    //verify market account
+    let market_seeds = &[b"market", pc_mint_account.key.as_ref(), coin_mint_account.key.as_ref()];
+
+    let market_pda = Pubkey::find_program_address(
+        market_seeds,
+        program_id
+    ).0;
+
+    if *market_account.key != market_pda {
+        msg!("Invalid market account provided, expected: {}", market_pda);
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let market_data = MarketState::try_from_slice(&market_account.data.borrow())?;
+
+
+    //verify market events account
+    if *market_events_account.owner != *program_id {
+        msg!("Invalid market events account provided, it has wrong owner");
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    //verify open order account
+    let open_order_seeds = [b"open_order", market_account.key.as_ref(), owner_account.key.as_ref()];
+
+    let open_order_pda = Pubkey::find_program_address(&open_order_seeds, program_id).0;
+
+    if open_order_pda != *open_order_account.key {
+        msg!("Invalid open order account provided, expected: {}", open_order_pda);
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if open_order_account.lamports() == 0 {
+        msg!("Open Order account has not been initialised");
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let mut open_order_raw_data = open_order_account.data.borrow_mut();
+    let open_order_data: &mut OpenOrderAccount = bytemuck::from_bytes_mut(&mut open_order_raw_data);
+
+    //verify bids and asks accounts
+    if *bids_account.owner != *program_id {
+        msg!("Invalid bids account provided, it has wrong owner");
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if *asks_account.owner != *program_id {
+        msg!("Invalid asks account provided, it has wrong owner");
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let mut bids_raw_data = bids_account.data.borrow_mut();
+    let mut bids_data = OrderBook::load(&mut bids_raw_data)?;
+
+    let mut asks_raw_data = asks_account.data.borrow_mut();
+    let mut asks_data = OrderBook::load(&mut asks_raw_data)?;
+
+    if bids_data.header.side != Side::Bid || bids_data.header.market != *market_account.key {
+        msg!("Invalid bids account provided, it is not the bids of this market");
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if asks_data.header.side != Side::Ask || asks_data.header.market != *market_account.key {
+        msg!("Invalid asks account provided, it is not the asks of this market");
+        return Err(ProgramError::InvalidAccountData);
+    }
+    msg!("Accounts verification success");
+
+
+    //get market events account data
+    let market_events_raw_data = &mut market_events_account.data.borrow_mut();
+    let mut market_events_data = MarketEventsAccount::load(market_events_raw_data)?;
+
+
+    //cancel every open order still resting in its book, up to the limit and the room left in the event queue
+    //slots get freed while cancelling, so walk a copy of the open orders
+    let open_order_ids = open_order_data.order_ids;
+    let mut cancelled_count: u8 = 0;
+    for &side_encoded_order_id in open_order_ids.iter().take(open_order_data.next_array_index as usize) {
+        if cancelled_count >= args.limit {
+            break;
+        }
+
+        if market_events_data.free_space() == 0 {
+            msg!("Event Queue is Full, stopping early");
+            break;
+        }
+
+        let (order_id, side) = OpenOrderAccount::decode_side_encoded_order_id(side_encoded_order_id)?;
+        if args.side.is_some_and(|filter_side| filter_side != side) {
+            continue;
+        }
+
+        let order_book_data = match side {
+            Side::Bid => &mut bids_data,
+            Side::Ask => &mut asks_data
+        };
+
+        //already filled or cancelled orders are no longer in the book
+        let Some(index) = order_book_data.find_order_index(order_id) else {
+            continue;
+        };
+
+        let removed_order = *order_book_data.order(index);
+        if removed_order.owner != *owner_account.key {
+            msg!("Owner mismatch, you do not own the order");
+            return Err(ProgramError::IllegalOwner);
+        }
+        order_book_data.remove_order(index)?;
+        open_order_data.remove_order(side_encoded_order_id);
+
+        let event = Event::out(&removed_order, removed_order.remaining_quantity()?, &market_data)?;
+        market_events_data.enqueue(event)?;
+        cancelled_count += 1;
+    }
+    msg!("Cancelled {} orders", cancelled_count);
+
+    Ok(())
+}
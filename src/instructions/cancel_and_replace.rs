@@ -0,0 +1,293 @@
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::{account_info::{next_account_info, AccountInfo}, clock::Clock, entrypoint::ProgramResult, msg, program_error::ProgramError, program_pack::Pack, pubkey::Pubkey, sysvar::Sysvar};
+use spl_token::state::Account as TokenAccount;
+
+use crate::{error::OrderBookError, instructions::create_order::{load_oracle_price, place_order, transfer_to_vault, verify_referrer, OrderContext}, state::{CancelAndReplaceArgs, MarketEventsAccount, MarketState, OpenOrderAccount, OrderBook, Side, UserMarketAccount}};
+
+pub fn cancel_and_replace(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    args: CancelAndReplaceArgs
+) -> ProgramResult {
+    let mut iter = accounts.iter();
+
+    let accounts_authority = next_account_info(&mut iter)?;
+    let market_account = next_account_info(&mut iter)?;
+    let market_events_account = next_account_info(&mut iter)?;
+    let owner_account = next_account_info(&mut iter)?;
+    let user_market_account = next_account_info(&mut iter)?;
+    let open_order_account = next_account_info(&mut iter)?;
+    let coin_payer_account = next_account_info(&mut iter)?;
+    let pc_payer_account = next_account_info(&mut iter)?;
+    let coin_vault_account = next_account_info(&mut iter)?;
+    let pc_vault_account = next_account_info(&mut iter)?;
+    let coin_mint_account = next_account_info(&mut iter)?;
+    let pc_mint_account = next_account_info(&mut iter)?;
+    let bids_account = next_account_info(&mut iter)?;
+    let asks_account = next_account_info(&mut iter)?;
+    let token_program_account = next_account_info(&mut iter)?;
+    let oracle_account = next_account_info(&mut iter)?;
+    let referrer_account = iter.next();
+
+    let now = Clock::get()?.unix_timestamp;
+
+
+    //verify owner
+    if !owner_account.is_signer {
+        msg!("Owner must sign to place orders");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    //verify market account
+    let market_seeds = &[b"market", pc_mint_account.key.as_ref(), coin_mint_account.key.as_ref()];
+
+    let market_pda = Pubkey::find_program_address(
+        market_seeds,
+        program_id
+    ).0;
+
+    if *market_account.key != market_pda {
+        msg!("Invalid market account provided, expected: {}", market_pda);
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let mut market_data = MarketState::try_from_slice(&market_account.data.borrow())?;
+
+
+    //verify market events account
+    if *market_events_account.owner != *program_id {
+        msg!("Invalid market events account provided, it has wrong owner");
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+
+    //verify open order account
+    let open_order_seeds = [b"open_order", market_account.key.as_ref(), owner_account.key.as_ref()];
+
+    let open_order_pda = Pubkey::find_program_address(&open_order_seeds, program_id).0;
+
+    if open_order_pda != *open_order_account.key {
+        msg!("Invalid open order account provided, expected: {}", open_order_pda);
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if open_order_account.lamports() == 0 {
+        msg!("Open Order account has not been initialised, place a first order with CreateOrder");
+        return Err(OrderBookError::UserAccountsNotCreated.into());
+    }
+
+    let mut open_order_raw_data = open_order_account.data.borrow_mut();
+    let open_order_data: &mut OpenOrderAccount = bytemuck::from_bytes_mut(&mut open_order_raw_data);
+
+    if open_order_data.owner != *owner_account.key {
+        msg!("Invalid open order, does not belongs to provided owner");
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if open_order_data.market != *market_account.key {
+        msg!("Invalid open order, does not belongs to provided market");
+        return Err(ProgramError::InvalidAccountData);
+    }
+    msg!("Open Order account verified");
+
+
+    //verify user market account
+    let user_market_seeds = [b"user_market_account", market_account.key.as_ref(), owner_account.key.as_ref()];
+
+    let user_market_pda = Pubkey::find_program_address(&user_market_seeds, program_id).0;
+
+    if user_market_pda != *user_market_account.key {
+        msg!("Invalid user market account provided, expected: {}", user_market_pda);
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if user_market_account.lamports() == 0 {
+        msg!("User Market Account has not been initialised, place a first order with CreateOrder");
+        return Err(OrderBookError::UserAccountsNotCreated.into());
+    }
+
+    let mut user_market_data = UserMarketAccount::try_from_slice(*user_market_account.data.borrow_mut())?;
+
+    if user_market_data.owner != *owner_account.key {
+        msg!("Invalid user market account, does not belongs to provided owner");
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if user_market_data.market != *market_account.key {
+        msg!("Invalid user market account, does not belongs to provided market");
+        return Err(ProgramError::InvalidAccountData);
+    }
+    msg!("User Market account verified");
+
+    //verify referrers, all new orders naming one share the referrer account
+    for new_order in args.new_orders.iter() {
+        if let Some(referrer) = new_order.referrer {
+            verify_referrer(program_id, market_account.key, owner_account.key, &referrer, referrer_account)?;
+        }
+    }
+
+
+    //get payer atas and verify them
+    let coin_payer = TokenAccount::unpack(*coin_payer_account.data.borrow_mut())?;
+    if coin_payer.mint != *coin_mint_account.key {
+        msg!("Given coin payer account is of wrong mint, expected {}", coin_mint_account.key);
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let pc_payer = TokenAccount::unpack(*pc_payer_account.data.borrow_mut())?;
+    if pc_payer.mint != *pc_mint_account.key {
+        msg!("Given pc payer account is of wrong mint, expected {}", pc_mint_account.key);
+        return Err(ProgramError::InvalidAccountData);
+    }
+    msg!("Payer accounts verified");
+
+    //verify vault accounts, deposits must land in the market's own vaults
+    if *coin_vault_account.key != market_data.coin_vault {
+        msg!("Invalid coin vault account provided, expected: {}", market_data.coin_vault);
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if *pc_vault_account.key != market_data.pc_vault {
+        msg!("Invalid pc vault account provided, expected: {}", market_data.pc_vault);
+        return Err(ProgramError::InvalidAccountData);
+    }
+    msg!("Vault accounts verified");
+
+
+    //verify bids and asks accounts
+    if *bids_account.owner != *program_id {
+        msg!("Invalid bids account provided, it has wrong owner");
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if *asks_account.owner != *program_id {
+        msg!("Invalid asks account provided, it has wrong owner");
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let mut bids_raw_data = bids_account.data.borrow_mut();
+    let mut bids_data = OrderBook::load(&mut bids_raw_data)?;
+
+    let mut asks_raw_data = asks_account.data.borrow_mut();
+    let mut asks_data = OrderBook::load(&mut asks_raw_data)?;
+
+    if bids_data.header.side != Side::Bid || bids_data.header.market != *market_account.key {
+        msg!("Invalid bids account provided, it is not the bids of this market");
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if asks_data.header.side != Side::Ask || asks_data.header.market != *market_account.key {
+        msg!("Invalid asks account provided, it is not the asks of this market");
+        return Err(ProgramError::InvalidAccountData);
+    }
+    msg!("Accounts verification success");
+
+
+    //cancel orders and release their locked funds right away so the new orders can use them
+    let mut cancelled_count = 0;
+    for cancel_order in args.cancel_orders {
+        let order_book_data = match cancel_order.side {
+            Side::Bid => &mut bids_data,
+            Side::Ask => &mut asks_data
+        };
+
+        //orders filled or cancelled in the meantime are skipped
+        let Some(index) = order_book_data.find_order_index(cancel_order.order_id) else {
+            msg!("Order {} is not present, skipping", cancel_order.order_id);
+            continue;
+        };
+
+        let removed_order = *order_book_data.order(index);
+        if removed_order.owner != *owner_account.key {
+            msg!("Owner mismatch, you do not own the order");
+            return Err(ProgramError::IllegalOwner);
+        }
+        order_book_data.remove_order(index)?;
+        open_order_data.remove_order(OpenOrderAccount::create_side_encoded_order_id(cancel_order.order_id, cancel_order.side));
+
+        let quantity_remaining = removed_order.remaining_quantity()?;
+        match cancel_order.side {
+            Side::Bid => {
+                user_market_data.unlock_pc(&market_data.bid_lock_between(removed_order.price, removed_order.filled_quantity, removed_order.total_quantity()?)?)?;
+            }
+            Side::Ask => {
+                user_market_data.unlock_coin(&market_data.coin_native(quantity_remaining)?)?;
+            }
+        };
+        cancelled_count += 1;
+    }
+    msg!("Cancelled {} orders", cancelled_count);
+
+
+    //get market events account data
+    let market_events_raw_data = &mut market_events_account.data.borrow_mut();
+    let mut market_events_data = MarketEventsAccount::load(market_events_raw_data)?;
+
+    //place the new orders
+    let mut coin_deposit_amount = 0u64;
+    let mut pc_deposit_amount = 0u64;
+    let mut ctx = OrderContext {
+        owner: owner_account.key,
+        market: market_account.key,
+        now,
+        market_data: &market_data,
+        user_market_data: &mut user_market_data,
+        open_order_data,
+        bids_data: &mut bids_data,
+        asks_data: &mut asks_data,
+        market_events_data: &mut market_events_data,
+        last_traded_price: market_data.last_traded_price,
+        oracle_price: load_oracle_price(oracle_account, &market_data, now)?
+    };
+    for new_order in args.new_orders {
+        let side = new_order.side;
+        let deposit_amount = place_order(&mut ctx, new_order)?;
+        match side {
+            Side::Bid => pc_deposit_amount = pc_deposit_amount.checked_add(deposit_amount).ok_or(OrderBookError::ArithmeticOverflow)?,
+            Side::Ask => coin_deposit_amount = coin_deposit_amount.checked_add(deposit_amount).ok_or(OrderBookError::ArithmeticOverflow)?
+        };
+    }
+    market_data.last_traded_price = ctx.last_traded_price;
+    msg!("Placed new orders");
+
+
+    //transfer extra funds to vaults if needed
+    if coin_payer.amount < coin_deposit_amount {
+        msg!("Owner's ATA does not have enough Coin balance");
+        return Err(OrderBookError::InsufficientFunds.into());
+    }
+
+    if pc_payer.amount < pc_deposit_amount {
+        msg!("Owner's ATA does not have enough PC balance");
+        return Err(OrderBookError::InsufficientFunds.into());
+    }
+
+    if coin_deposit_amount > 0 {
+        transfer_to_vault(
+            token_program_account,
+            coin_payer_account,
+            coin_vault_account,
+            owner_account,
+            accounts_authority,
+            coin_mint_account,
+            coin_deposit_amount
+        )?;
+    }
+
+    if pc_deposit_amount > 0 {
+        transfer_to_vault(
+            token_program_account,
+            pc_payer_account,
+            pc_vault_account,
+            owner_account,
+            accounts_authority,
+            pc_mint_account,
+            pc_deposit_amount
+        )?;
+    }
+
+    user_market_data.serialize(&mut *user_market_account.data.borrow_mut())?;
+    market_data.serialize(&mut *market_account.data.borrow_mut())?;
+
+    Ok(())
+}
@@ -1,6 +1,7 @@
+use borsh::BorshDeserialize;
 use solana_program::{account_info::{next_account_info, AccountInfo}, entrypoint::ProgramResult, msg, program_error::ProgramError, pubkey::Pubkey};
 
-use crate::state::{CancelOrderArgs, Event, EventType, MarketEventsAccount, OrderBook};
+use crate::state::{CancelOrderArgs, Event, MarketEventsAccount, MarketState, OrderBook};
 
 pub fn cancel_order(
     program_id: &Pubkey,
@@ -17,6 +18,12 @@ pub fn cancel_order(
     let order_side_account = next_account_info(&mut iter)?;
 
 
+    //verify owner
+    if !owner_account.is_signer {
+        msg!("Owner must sign to cancel orders");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
     //verify market account
     let market_seeds = &[b"market", pc_mint_account.key.as_ref(), coin_mint_account.key.as_ref()]; 
 
@@ -30,6 +37,8 @@ pub fn cancel_order(
         return Err(ProgramError::InvalidAccountData);
     }
 
+    let market_data = MarketState::try_from_slice(&market_account.data.borrow())?;
+
 
     //verify market events account
     if *market_events_account.owner != *program_id {
@@ -39,22 +48,22 @@ pub fn cancel_order(
 
     //verify Order Side account
     let mut order_side_raw_data = order_side_account.data.borrow_mut();
-    let order_book_data: &mut OrderBook = bytemuck::from_bytes_mut(&mut order_side_raw_data);
+    let mut order_book_data = OrderBook::load(&mut order_side_raw_data)?;
 
     if *order_side_account.owner != *program_id {
         msg!("Invalid order side account provided, it has wrong owner");
         return Err(ProgramError::InvalidAccountData);
     }
     
-    if order_book_data.side != args.side {
-        msg!("Invalid order side account provided, expected: {:?}", args.side);
+    if order_book_data.header.side != args.side || order_book_data.header.market != *market_account.key {
+        msg!("Invalid order side account provided, it is not the {:?} book of this market", args.side);
         return Err(ProgramError::InvalidAccountData);
     }
 
 
     //get market events account data
     let market_events_raw_data = &mut market_events_account.data.borrow_mut();
-    let market_events_data: &mut MarketEventsAccount = bytemuck::from_bytes_mut(market_events_raw_data);
+    let mut market_events_data = MarketEventsAccount::load(market_events_raw_data)?;
     
 
     //remove order
@@ -62,20 +71,8 @@ pub fn cancel_order(
     msg!("Removed Order");
 
     //emit event
-    let event = Event {
-        event_type: EventType::Out,
-        side: args.side,
-        maker: *owner_account.key,
-        taker: *owner_account.key,
-        coin_qty: removed_order.quantity - removed_order.filled_quantity,
-        pc_qty: (removed_order.quantity - removed_order.filled_quantity) * removed_order.price,
-        maker_order_id: removed_order.order_id
-    };
-    let result = market_events_data.enqueue(event)?;
-    if !result {
-        //TODO: handle this
-        msg!("Event Queue is Full");
-    }
+    let event = Event::out(&removed_order, removed_order.remaining_quantity()?, &market_data)?;
+    market_events_data.enqueue(event)?;
     msg!("Emitted Out Event");
 
     Ok(())
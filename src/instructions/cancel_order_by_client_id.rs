@@ -0,0 +1,147 @@
+use borsh::BorshDeserialize;
+use solana_program::{account_info::{next_account_info, AccountInfo}, entrypoint::ProgramResult, msg, program_error::ProgramError, pubkey::Pubkey};
+
+use crate::{error::OrderBookError, state::{CancelOrderByClientIdArgs, Event, MarketEventsAccount, MarketState, OpenOrderAccount, OrderBook, Side}};
+
+pub fn cancel_order_by_client_id(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    args: CancelOrderByClientIdArgs
+) -> ProgramResult {
+    let mut iter = accounts.iter();
+
+    let market_account = next_account_info(&mut iter)?;
+    let market_events_account = next_account_info(&mut iter)?;
+    let owner_account = next_account_info(&mut iter)?;
+    let open_order_account = next_account_info(&mut iter)?;
+    let coin_mint_account = next_account_info(&mut iter)?;
+    let pc_mint_account = next_account_info(&mut iter)?;
+    let bids_account = next_account_info(&mut iter)?;
+    let asks_account = next_account_info(&mut iter)?;
+
+
+    //verify owner
+    if !owner_account.is_signer {
+        msg!("Owner must sign to cancel orders");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    //verify market account
+    let market_seeds = &[b"market", pc_mint_account.key.as_ref(), coin_mint_account.key.as_ref()];
+
+    let market_pda = Pubkey::find_program_address(
+        market_seeds,
+        program_id
+    ).0;
+
+    if *market_account.key != market_pda {
+        msg!("Invalid market account provided, expected: {}", market_pda);
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let market_data = MarketState::try_from_slice(&market_account.data.borrow())?;
+
+
+    //verify market events account
+    if *market_events_account.owner != *program_id {
+        msg!("Invalid market events account provided, it has wrong owner");
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    //verify open order account
+    let open_order_seeds = [b"open_order", market_account.key.as_ref(), owner_account.key.as_ref()];
+
+    let open_order_pda = Pubkey::find_program_address(&open_order_seeds, program_id).0;
+
+    if open_order_pda != *open_order_account.key {
+        msg!("Invalid open order account provided, expected: {}", open_order_pda);
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if open_order_account.lamports() == 0 {
+        msg!("Open Order account has not been initialised");
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let mut open_order_raw_data = open_order_account.data.borrow_mut();
+    let open_order_data: &mut OpenOrderAccount = bytemuck::from_bytes_mut(&mut open_order_raw_data);
+
+    //verify bids and asks accounts
+    if *bids_account.owner != *program_id {
+        msg!("Invalid bids account provided, it has wrong owner");
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if *asks_account.owner != *program_id {
+        msg!("Invalid asks account provided, it has wrong owner");
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let mut bids_raw_data = bids_account.data.borrow_mut();
+    let mut bids_data = OrderBook::load(&mut bids_raw_data)?;
+
+    let mut asks_raw_data = asks_account.data.borrow_mut();
+    let mut asks_data = OrderBook::load(&mut asks_raw_data)?;
+
+    if bids_data.header.side != Side::Bid || bids_data.header.market != *market_account.key {
+        msg!("Invalid bids account provided, it is not the bids of this market");
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if asks_data.header.side != Side::Ask || asks_data.header.market != *market_account.key {
+        msg!("Invalid asks account provided, it is not the asks of this market");
+        return Err(ProgramError::InvalidAccountData);
+    }
+    msg!("Accounts verification success");
+
+
+    //find the open order with this client order id that is still resting in its book
+    let mut found = None;
+    for i in 0..open_order_data.next_array_index as usize {
+        if open_order_data.client_order_ids[i] != args.client_order_id {
+            continue;
+        }
+
+        let (order_id, side) = OpenOrderAccount::decode_side_encoded_order_id(open_order_data.order_ids[i])?;
+        let order_book_data = match side {
+            Side::Bid => &bids_data,
+            Side::Ask => &asks_data
+        };
+
+        if let Some(index) = order_book_data.find_order_index(order_id) {
+            found = Some((side, index, open_order_data.order_ids[i]));
+            break;
+        }
+    }
+
+    let (side, index, side_encoded_order_id) = found.ok_or_else(|| {
+        msg!("No resting order with client order id {}", args.client_order_id);
+        OrderBookError::OrderNotFound
+    })?;
+
+    let order_book_data = match side {
+        Side::Bid => &mut bids_data,
+        Side::Ask => &mut asks_data
+    };
+
+    let removed_order = *order_book_data.order(index);
+    if removed_order.owner != *owner_account.key {
+        msg!("Owner mismatch, you do not own the order");
+        return Err(ProgramError::IllegalOwner);
+    }
+    order_book_data.remove_order(index)?;
+    open_order_data.remove_order(side_encoded_order_id);
+    msg!("Removed Order");
+
+
+    //get market events account data
+    let market_events_raw_data = &mut market_events_account.data.borrow_mut();
+    let mut market_events_data = MarketEventsAccount::load(market_events_raw_data)?;
+
+    //emit event
+    let event = Event::out(&removed_order, removed_order.remaining_quantity()?, &market_data)?;
+    market_events_data.enqueue(event)?;
+    msg!("Emitted Out Event");
+
+    Ok(())
+}
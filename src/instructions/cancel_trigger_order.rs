@@ -0,0 +1,99 @@
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::{account_info::{next_account_info, AccountInfo}, entrypoint::ProgramResult, msg, program_error::ProgramError, pubkey::Pubkey};
+
+use crate::{error::OrderBookError, state::{CancelTriggerOrderArgs, MarketState, Side, TriggerOrderBook, UserMarketAccount}};
+
+pub fn cancel_trigger_order(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    args: CancelTriggerOrderArgs
+) -> ProgramResult {
+    let mut iter = accounts.iter();
+
+    let market_account = next_account_info(&mut iter)?;
+    let owner_account = next_account_info(&mut iter)?;
+    let user_market_account = next_account_info(&mut iter)?;
+    let coin_mint_account = next_account_info(&mut iter)?;
+    let pc_mint_account = next_account_info(&mut iter)?;
+    let trigger_orders_account = next_account_info(&mut iter)?;
+
+
+    //verify owner
+    if !owner_account.is_signer {
+        msg!("Owner must sign to cancel trigger orders");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    //verify market account
+    let market_seeds = &[b"market", pc_mint_account.key.as_ref(), coin_mint_account.key.as_ref()];
+
+    let market_pda = Pubkey::find_program_address(
+        market_seeds,
+        program_id
+    ).0;
+
+    if *market_account.key != market_pda {
+        msg!("Invalid market account provided, expected: {}", market_pda);
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let market_data = MarketState::try_from_slice(&market_account.data.borrow())?;
+
+
+    //verify trigger orders account
+    if *trigger_orders_account.key != market_data.trigger_orders {
+        msg!("Invalid trigger orders account provided, expected: {}", market_data.trigger_orders);
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+
+    //verify user market account
+    let user_market_seeds = [b"user_market_account", market_account.key.as_ref(), owner_account.key.as_ref()];
+
+    let user_market_pda = Pubkey::find_program_address(&user_market_seeds, program_id).0;
+
+    if user_market_pda != *user_market_account.key {
+        msg!("Invalid user market account provided, expected: {}", user_market_pda);
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let mut user_market_data = UserMarketAccount::try_from_slice(*user_market_account.data.borrow_mut())?;
+    msg!("Accounts verification success");
+
+
+    //remove the trigger order
+    let mut trigger_orders_raw_data = trigger_orders_account.data.borrow_mut();
+    let trigger_orders_data = TriggerOrderBook::load(&mut trigger_orders_raw_data)?;
+
+    let index = trigger_orders_data.find_order_index(args.trigger_id).ok_or_else(|| {
+        msg!("Trigger Id is not present");
+        OrderBookError::OrderNotFound
+    })?;
+    let trigger_order = trigger_orders_data.orders[index];
+
+    if trigger_order.owner != *owner_account.key {
+        msg!("Owner mismatch, you do not own the trigger order");
+        return Err(ProgramError::IllegalOwner);
+    }
+    trigger_orders_data.remove_order(index)?;
+    msg!("Removed Trigger Order");
+
+    let coin_qty = trigger_order.coin_qty;
+    let pc_qty = trigger_order.pc_qty;
+
+
+    //trigger orders never reached the book, so their funds are released directly
+    match trigger_order.side {
+        Side::Bid => {
+            user_market_data.unlock_pc(&pc_qty)?;
+        }
+        Side::Ask => {
+            user_market_data.unlock_coin(&market_data.coin_native(coin_qty)?)?;
+        }
+    };
+    msg!("Released funds of trigger order");
+
+    user_market_data.serialize(&mut *user_market_account.data.borrow_mut())?;
+
+    Ok(())
+}
@@ -0,0 +1,133 @@
+use borsh::BorshDeserialize;
+use solana_program::{account_info::{next_account_info, AccountInfo}, entrypoint::ProgramResult, msg, program_error::ProgramError, pubkey::Pubkey};
+
+use crate::{error::OrderBookError, state::{MarketEventsAccount, OpenOrderAccount, UserMarketAccount}};
+
+pub fn close_user_accounts(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    let mut iter = accounts.iter();
+
+    let market_account = next_account_info(&mut iter)?;
+    let market_events_account = next_account_info(&mut iter)?;
+    let owner_account = next_account_info(&mut iter)?;
+    let open_order_account = next_account_info(&mut iter)?;
+    let user_market_account = next_account_info(&mut iter)?;
+    let coin_mint_account = next_account_info(&mut iter)?;
+    let pc_mint_account = next_account_info(&mut iter)?;
+
+
+    //verify market account
+    let market_seeds = &[b"market", pc_mint_account.key.as_ref(), coin_mint_account.key.as_ref()];
+
+    let market_pda = Pubkey::find_program_address(
+        market_seeds,
+        program_id
+    ).0;
+
+    if *market_account.key != market_pda {
+        msg!("Invalid market account provided, expected: {}", market_pda);
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+
+    //verify owner
+    if !owner_account.is_signer {
+        msg!("Owner must sign to close their accounts");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+
+    //verify market events account
+    if *market_events_account.owner != *program_id {
+        msg!("Invalid market events account provided, it has wrong owner");
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+
+    //verify open order account
+    let open_order_seeds = [b"open_order", market_account.key.as_ref(), owner_account.key.as_ref()];
+
+    let open_order_pda = Pubkey::find_program_address(&open_order_seeds, program_id).0;
+
+    if open_order_pda != *open_order_account.key {
+        msg!("Invalid open order account provided, expected: {}", open_order_pda);
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if *open_order_account.owner != *program_id {
+        msg!("Open Order account has not been initialised");
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+
+    //verify user market account
+    let user_market_seeds = [b"user_market_account", market_account.key.as_ref(), owner_account.key.as_ref()];
+
+    let user_market_pda = Pubkey::find_program_address(&user_market_seeds, program_id).0;
+
+    if user_market_pda != *user_market_account.key {
+        msg!("Invalid user market account provided, expected: {}", user_market_pda);
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if *user_market_account.owner != *program_id {
+        msg!("User Market Account has not been initialised");
+        return Err(ProgramError::InvalidAccountData);
+    }
+    msg!("Accounts verification success");
+
+
+    //nothing may still rest in the book or wait to be settled
+    {
+        let open_order_raw_data = open_order_account.data.borrow();
+        let open_order_data: &OpenOrderAccount = bytemuck::from_bytes(&open_order_raw_data);
+        if open_order_data.next_array_index > 0 {
+            msg!("User still has {} open orders, cancel them first", { open_order_data.next_array_index });
+            return Err(OrderBookError::OpenOrdersRemaining.into());
+        }
+    }
+
+    let user_market_data = UserMarketAccount::try_from_slice(&user_market_account.data.borrow())?;
+    if user_market_data.locked_coin > 0 || user_market_data.locked_pc > 0 {
+        msg!("User still has locked coin {} and pc {}, consume events first", user_market_data.locked_coin, user_market_data.locked_pc);
+        return Err(OrderBookError::LockedBalanceRemaining.into());
+    }
+    if user_market_data.free_coin > 0 || user_market_data.free_pc > 0 {
+        msg!("User still has free coin {} and pc {}, settle funds first", user_market_data.free_coin, user_market_data.free_pc);
+        return Err(OrderBookError::FreeBalanceRemaining.into());
+    }
+
+    {
+        let mut market_events_raw_data = market_events_account.data.borrow_mut();
+        let market_events_data = MarketEventsAccount::load(&mut market_events_raw_data)?;
+        if market_events_data.header.market != *market_account.key {
+            msg!("Market events account does not belong to this market");
+            return Err(ProgramError::InvalidAccountData);
+        }
+        if market_events_data.has_pending_events_of(owner_account.key) {
+            msg!("User still has events waiting to be consumed");
+            return Err(OrderBookError::PendingEvents.into());
+        }
+    }
+
+
+    //return the rent of both accounts to the owner
+    let open_order_lamports = open_order_account.lamports();
+    let user_market_lamports = user_market_account.lamports();
+    close_account(open_order_account, owner_account)?;
+    close_account(user_market_account, owner_account)?;
+    msg!("Closed Open Order and User Market accounts, returned {} lamports", open_order_lamports + user_market_lamports);
+
+    Ok(())
+}
+
+//moves every lamport of a program account to `destination` and empties it, the runtime then drops the account
+fn close_account(account: &AccountInfo, destination: &AccountInfo) -> ProgramResult {
+    let destination_lamports = destination.lamports().checked_add(account.lamports()).ok_or(OrderBookError::ArithmeticOverflow)?;
+    **destination.try_borrow_mut_lamports()? = destination_lamports;
+    **account.try_borrow_mut_lamports()? = 0;
+    account.resize(0)?;
+    Ok(())
+}
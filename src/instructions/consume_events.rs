@@ -3,7 +3,7 @@ use std::{collections::HashMap, io::Cursor};
 use borsh::{BorshDeserialize, BorshSerialize};
 use solana_program::{account_info::{next_account_info, AccountInfo}, entrypoint::ProgramResult, msg, program_error::ProgramError, pubkey::Pubkey};
 
-use crate::state::{ConsumeEventsArgs, EventType, MarketEventsAccount, Side, UserMarketAccount};
+use crate::{error::OrderBookError, state::{ConsumeEventsArgs, EventType, MarketEventsAccount, MarketState, OpenOrderAccount, Side, UserMarketAccount}};
 
 pub fn consume_events(
     program_id: &Pubkey,
@@ -37,6 +37,8 @@ pub fn consume_events(
         return Err(ProgramError::InvalidAccountData);
     }
 
+    let mut market_data = MarketState::try_from_slice(&market_account.data.borrow())?;
+
     //verify market events account
     if *market_events_account.owner != *program_id {
         msg!("Invalid market events account provided, it has wrong owner");
@@ -48,7 +50,7 @@ pub fn consume_events(
 
 
     let mut events_acc_raw_data = market_events_account.data.borrow_mut();
-    let events_info: &mut MarketEventsAccount = bytemuck::from_bytes_mut(&mut events_acc_raw_data);
+    let mut events_info = MarketEventsAccount::load(&mut events_acc_raw_data)?;
     
     if events_info.is_empty() {
         msg!("Event Queue is Empty");
@@ -58,10 +60,17 @@ pub fn consume_events(
     
     let mut i = 0;
     while i < args.drain_count {
+        if events_info.is_empty() {
+            msg!("Event Queue is drained");
+            break;
+        }
+
         /////settle balance for the event
 
         //get the oldest added event
-        let event = events_info.events[events_info.tail as usize];
+        let event = events_info.events[events_info.header.tail as usize];
+        let coin_qty = event.coin_qty;
+        let pc_qty = event.pc_qty;
 
         match event.event_type {
             EventType::Fill => {
@@ -77,8 +86,8 @@ pub fn consume_events(
                         ProgramError::NotEnoughAccountKeys
                 })?;
                 let mut maker_uma_raw_data = maker_uma_info.data.borrow_mut();
-                let mut reader1 = &maker_uma_raw_data[..];
-                let mut maker_uma_data = UserMarketAccount::try_from_slice(&mut reader1)?;
+                let reader1 = &maker_uma_raw_data[..];
+                let mut maker_uma_data = UserMarketAccount::try_from_slice(reader1)?;
                 msg!("Retreived Maker's User Market Data");
 
                 //retrieve User Market Account for taker
@@ -93,20 +102,68 @@ pub fn consume_events(
                         ProgramError::NotEnoughAccountKeys
                 })?;
                 let mut taker_uma_raw_data = taker_uma_info.data.borrow_mut();
-                let mut reader2 = &taker_uma_raw_data[..];
-                let mut taker_uma_data = UserMarketAccount::try_from_slice(&mut reader2)?;
+                let reader2 = &taker_uma_raw_data[..];
+                let mut taker_uma_data = UserMarketAccount::try_from_slice(reader2)?;
                 msg!("Retreived Taker's User Market Data");
 
 
-                //settle free coin and pc balance 
+                //settle free and locked coin and pc balance, fees are in pc: whoever receives pc gets it net of their fee,
+                //whoever pays pc has the fill and their fee taken from the pc they locked for the order and rebates go to free pc
+                let (taker_fee, maker_fee) = market_data.fill_fees(pc_qty)?;
+                let maker_rebate = maker_fee.min(0).unsigned_abs();
                 if event.side == Side::Bid {
-                    maker_uma_data.free_coin += event.coin_qty;
-                    taker_uma_data.free_pc += event.pc_qty;
+                    //the bid maker locked at its own price, what it did not pay is price improvement
+                    let maker_pc_locked = event.maker_pc_locked;
+                    let maker_pc_refund = pc_qty.checked_add(maker_fee.max(0) as u64)
+                        .and_then(|maker_pc_paid| maker_pc_locked.checked_sub(maker_pc_paid))
+                        .and_then(|price_improvement| price_improvement.checked_add(maker_rebate))
+                        .ok_or(OrderBookError::ArithmeticOverflow)?;
+                    maker_uma_data.debit_locked_pc(&maker_pc_locked)?;
+                    maker_uma_data.credit_free_pc(&maker_pc_refund)?;
+                    maker_uma_data.credit_free_coin(&coin_qty)?;
+                    taker_uma_data.debit_locked_coin(&coin_qty)?;
+                    taker_uma_data.credit_free_pc(&pc_qty.checked_sub(taker_fee).ok_or(OrderBookError::ArithmeticOverflow)?)?;
                 } else if event.side == Side::Ask {
-                    maker_uma_data.free_pc += event.pc_qty;
-                    taker_uma_data.free_coin += event.coin_qty;
+                    let maker_pc_received = pc_qty.checked_sub(maker_fee.max(0) as u64)
+                        .and_then(|maker_pc_received| maker_pc_received.checked_add(maker_rebate))
+                        .ok_or(OrderBookError::ArithmeticOverflow)?;
+                    maker_uma_data.debit_locked_coin(&coin_qty)?;
+                    maker_uma_data.credit_free_pc(&maker_pc_received)?;
+                    taker_uma_data.debit_locked_pc(&pc_qty.checked_add(taker_fee).ok_or(OrderBookError::ArithmeticOverflow)?)?;
+                    taker_uma_data.credit_free_coin(&coin_qty)?;
+                }
+                msg!("Settled free coin and pc balance for maker and taker, fees: taker {} maker {}", taker_fee, maker_fee);
+
+                //the taker's referrer gets its share of the taker fee
+                let mut referrer_fee = 0;
+                if event.referrer != Pubkey::default() {
+                    referrer_fee = market_data.referrer_fee(taker_fee)?;
+                    if event.referrer == event.maker {
+                        maker_uma_data.credit_free_pc(&referrer_fee)?;
+                    } else {
+                        let referrer_uma_pda = Pubkey::find_program_address(
+                            &[b"user_market_account", market_account.key.as_ref(), event.referrer.as_ref()],
+                            program_id
+                        ).0;
+
+                        let referrer_uma_info = user_account_map
+                            .get(&referrer_uma_pda)
+                            .ok_or_else(|| {
+                                msg!("Referrer's User Market account is not provided: {}", referrer_uma_pda);
+                                ProgramError::NotEnoughAccountKeys
+                        })?;
+
+                        let mut referrer_uma_raw_data = referrer_uma_info.data.borrow_mut();
+                        let mut referrer_uma_data = UserMarketAccount::try_from_slice(&referrer_uma_raw_data[..])?;
+                        referrer_uma_data.credit_free_pc(&referrer_fee)?;
+                        let mut writer3 = Cursor::new(&mut referrer_uma_raw_data[..]);
+                        referrer_uma_data.serialize(&mut writer3)?;
+                    }
+                    msg!("Credited referrer with {} pc", referrer_fee);
                 }
-                msg!("Settled free coin and pc balance for maker and taker");
+                let fees_earned = u64::try_from(taker_fee as i128 + maker_fee as i128 - referrer_fee as i128)
+                    .map_err(|_| OrderBookError::ArithmeticOverflow)?;
+                market_data.fees_accrued = market_data.fees_accrued.checked_add(fees_earned).ok_or(OrderBookError::ArithmeticOverflow)?;
 
                 //write modified data back to the account
                 let mut writer1 = Cursor::new(&mut maker_uma_raw_data[..]);
@@ -129,17 +186,15 @@ pub fn consume_events(
                         ProgramError::NotEnoughAccountKeys
                 })?;
                 let mut maker_uma_raw_data = maker_uma_info.data.borrow_mut();
-                let mut reader1 = &maker_uma_raw_data[..];
-                let mut maker_uma_data = UserMarketAccount::try_from_slice(&mut reader1)?;
+                let reader1 = &maker_uma_raw_data[..];
+                let mut maker_uma_data = UserMarketAccount::try_from_slice(reader1)?;
                 msg!("Retreived User Market Data");
 
                 //settle free and locked balance                 
                 if event.side == Side::Bid {
-                    maker_uma_data.free_pc += event.pc_qty;
-                    maker_uma_data.locked_pc -= event.pc_qty;
+                    maker_uma_data.unlock_pc(&pc_qty)?;
                 } else if event.side == Side::Ask {
-                    maker_uma_data.free_coin += event.coin_qty;
-                    maker_uma_data.locked_coin -= event.coin_qty;
+                    maker_uma_data.unlock_coin(&coin_qty)?;
                 }
                 msg!("Settled free and locked balance for cancelled order");
 
@@ -150,13 +205,33 @@ pub fn consume_events(
             }
         }
 
+        //the maker order left the book, so its open order slot can take a new order
+        if event.maker_out == 1 {
+            let maker_open_order_pda = Pubkey::find_program_address(
+                &[b"open_order", market_account.key.as_ref(), event.maker.as_ref()],
+                program_id
+            ).0;
+            let maker_open_order_info = user_account_map
+                .get(&maker_open_order_pda)
+                .ok_or_else(|| {
+                    msg!("Maker's Open Order account is not provided: {}", maker_open_order_pda);
+                    ProgramError::NotEnoughAccountKeys
+            })?;
+            if *maker_open_order_info.owner != *program_id {
+                msg!("Invalid open order account provided, it has wrong owner");
+                return Err(ProgramError::InvalidAccountData);
+            }
+            let mut maker_open_order_raw_data = maker_open_order_info.data.borrow_mut();
+            let maker_open_order_data: &mut OpenOrderAccount = bytemuck::from_bytes_mut(&mut maker_open_order_raw_data);
+            maker_open_order_data.remove_order(OpenOrderAccount::create_side_encoded_order_id(event.maker_order_id, event.side));
+            msg!("Freed Maker's open order slot");
+        }
+
         /////remove that event from queue
-        let _ = events_info.dequeue()?.ok_or_else(|| {
+        events_info.dequeue()?.ok_or_else(|| {
             msg!("Queue is Empty");
-            ProgramError::Custom(1);
-        });
-        let coin_qty = event.coin_qty;
-        let pc_qty = event.pc_qty;
+            OrderBookError::EventQueueEmpty
+        })?;
         let maker_order_id = event.maker_order_id;
         msg!(
             "Drained Event -> type: {:?}, side: {:?}, maker: {}, taker: {}, coin_qty: {}, pc_qty: {}, maker_order_id: {}",
@@ -170,6 +245,7 @@ pub fn consume_events(
         ); 
         i += 1;
     }
+    market_data.serialize(&mut *market_account.data.borrow_mut())?;
     msg!("Consumed Events successfully");
     Ok(())
 } 
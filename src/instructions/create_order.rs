@@ -1,10 +1,10 @@
 use std::io::Cursor;
 
 use borsh::{BorshDeserialize, BorshSerialize};
-use solana_program::{account_info::{next_account_info, AccountInfo}, entrypoint::ProgramResult, msg, program::{invoke, invoke_signed}, program_error::ProgramError, program_pack::Pack, pubkey::Pubkey, system_instruction::create_account, sysvar::rent};
+use solana_program::{account_info::{next_account_info, AccountInfo}, clock::Clock, entrypoint::ProgramResult, msg, program::{invoke, invoke_signed}, program_error::ProgramError, program_pack::Pack, pubkey::Pubkey, system_instruction::create_account, sysvar::{rent, Sysvar}};
 use spl_token::{instruction::transfer, state::Account as TokenAccount};
 
-use crate::state::{CreateOrderArgs, Event, EventType, MarketEventsAccount, OpenOrderAccount, Order, OrderBook, Side, UserMarketAccount};
+use crate::{error::OrderBookError, state::{pegged_price, CreateOrderArgs, Event, EventType, MarketEventsAccount, MarketState, MatchLimitBehavior, OpenOrderAccount, OraclePrice, Order, OrderBook, OrderType, SelfTradeBehavior, Side, UserMarketAccount}};
 
 pub fn create_order(
     program_id: &Pubkey,
@@ -27,17 +27,21 @@ pub fn create_order(
     let asks_account = next_account_info(&mut iter)?;
     let system_program_account = next_account_info(&mut iter)?;
     let token_program_account = next_account_info(&mut iter)?;
+    let oracle_account = next_account_info(&mut iter)?;
+    let referrer_account = iter.next();
 
     let rent = rent::Rent::default();
+    let now = Clock::get()?.unix_timestamp;
 
-    let CreateOrderArgs {
-        side,
-        limit_price,
-        coin_qty,
-        pc_qty,
-    } = args;
+    let side = args.side;
 
 
+    //verify owner, placing an order spends their free balance
+    if !owner_account.is_signer {
+        msg!("Owner must sign to place orders");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
     //verify market account
     let market_seeds = &[b"market", pc_mint_account.key.as_ref(), coin_mint_account.key.as_ref()]; 
 
@@ -51,6 +55,13 @@ pub fn create_order(
         return Err(ProgramError::InvalidAccountData);
     }
 
+    let mut market_data = MarketState::try_from_slice(&market_account.data.borrow())?;
+
+    //verify referrer
+    if let Some(referrer) = args.referrer {
+        verify_referrer(program_id, market_account.key, owner_account.key, &referrer, referrer_account)?;
+    }
+
     //verify market events account
     if *market_events_account.owner != *program_id {
         msg!("Invalid market events account provided, it has wrong owner");
@@ -99,9 +110,10 @@ pub fn create_order(
         //initialize open order data
         let mut open_order_raw_data = open_order_account.data.borrow_mut();
         let open_order_data: &mut OpenOrderAccount = bytemuck::from_bytes_mut(&mut open_order_raw_data);
-        open_order_data.owner = owner_account.key.clone();
-        open_order_data.market = market_account.key.clone();
+        open_order_data.owner = *owner_account.key;
+        open_order_data.market = *market_account.key;
         open_order_data.order_ids = [0u64; 64];
+        open_order_data.client_order_ids = [0u64; 64];
         open_order_data.bump = open_order_bump;
         
         msg!("Open Order account created");
@@ -204,213 +216,526 @@ pub fn create_order(
     };
     msg!("Payer account verified");
 
-    //get vault ata and verify it
-    let vault = TokenAccount::unpack(*vault_account.data.borrow_mut())?;
-    match side {
-        Side::Bid => {
-            if vault.mint != *pc_mint_account.key {
-                msg!("Given vault account is of wrong mint, expected {}", pc_mint_account.key);
-                return Err(ProgramError::InvalidAccountData);
-            }
-        }
-        Side::Ask => {
-            if vault.mint != *coin_mint_account.key {
-                msg!("Given vault account is of wrong mint, expected {}", coin_mint_account.key);
-                return Err(ProgramError::InvalidAccountData);
-            }
-        }
+    //verify vault account, deposits must land in the market's own vault for the side
+    let expected_vault = match side {
+        Side::Bid => market_data.pc_vault,
+        Side::Ask => market_data.coin_vault
     };
+    if *vault_account.key != expected_vault {
+        msg!("Invalid vault account provided, expected: {}", expected_vault);
+        return Err(ProgramError::InvalidAccountData);
+    }
     msg!("Vault account verified");
 
 
     //get market events account data
     let market_events_raw_data = &mut market_events_account.data.borrow_mut();
-    let market_events_data: &mut MarketEventsAccount = bytemuck::from_bytes_mut(market_events_raw_data);
+    let mut market_events_data = MarketEventsAccount::load(market_events_raw_data)?;
 
 
     //get bids and asks accounts' data
     let mut bids_raw_data = bids_account.data.borrow_mut();
-    let mut bids_data: &mut OrderBook = bytemuck::from_bytes_mut(&mut bids_raw_data);
+    let mut bids_data = OrderBook::load(&mut bids_raw_data)?;
 
     let mut asks_raw_data = asks_account.data.borrow_mut();
-    let mut asks_data: &mut OrderBook = bytemuck::from_bytes_mut(&mut asks_raw_data);
+    let mut asks_data = OrderBook::load(&mut asks_raw_data)?;
+
+    let mut ctx = OrderContext {
+        owner: owner_account.key,
+        market: market_account.key,
+        now,
+        market_data: &market_data,
+        user_market_data: &mut user_market_data,
+        open_order_data,
+        bids_data: &mut bids_data,
+        asks_data: &mut asks_data,
+        market_events_data: &mut market_events_data,
+        last_traded_price: market_data.last_traded_price,
+        oracle_price: load_oracle_price(oracle_account, &market_data, now)?
+    };
+    let deposit_amount = place_order(&mut ctx, args)?;
+    market_data.last_traded_price = ctx.last_traded_price;
+
+    if payer.amount < deposit_amount {
+        match side {
+            Side::Bid => msg!("Owner's ATA does not have enough PC balance"),
+            Side::Ask => msg!("Owner's ATA does not have enough Coin balance")
+        };
+        return Err(OrderBookError::InsufficientFunds.into());
+    }
+
+
+    //transfer extra funds to vault if needed
+    if deposit_amount > 0 {
+        let mint_account = match side {
+            Side::Bid => {
+                pc_mint_account
+            }
+            Side::Ask => {
+                coin_mint_account
+            }
+        };
+
+        transfer_to_vault(
+            token_program_account,
+            payer_account,
+            vault_account,
+            owner_account,
+            accounts_authority,
+            mint_account,
+            deposit_amount
+        )?;
+    }
+    
+    user_market_data.serialize(&mut *user_market_account.data.borrow_mut())?;
+    market_data.serialize(&mut *market_account.data.borrow_mut())?;
+
+    Ok(())
+}
+
+
+//accounts data an order is matched and rested against
+pub struct OrderContext<'a, 'b> {
+    pub owner: &'a Pubkey,
+    pub market: &'a Pubkey,
+    pub now: i64,
+    pub market_data: &'a MarketState,
+    pub user_market_data: &'a mut UserMarketAccount,
+    pub open_order_data: &'a mut OpenOrderAccount,
+    pub bids_data: &'a mut OrderBook<'b>,
+    pub asks_data: &'a mut OrderBook<'b>,
+    pub market_events_data: &'a mut MarketEventsAccount<'b>,
+    pub last_traded_price: u64,
+    pub oracle_price: Option<u64>   //none while the oracle is unusable, pegged makers are skipped then
+}
+
+//locks funds for the order, matches it and rests the remainder
+//returns the amount that is not covered by free balance and has to be deposited into the vault
+pub fn place_order(ctx: &mut OrderContext, args: CreateOrderArgs) -> Result<u64, ProgramError> {
+    let CreateOrderArgs {
+        side,
+        limit_price,
+        coin_qty,
+        order_type,
+        pc_qty_limit,
+        self_trade_behavior,
+        client_order_id,
+        expiry_timestamp,
+        display_quantity,
+        peg_offset,
+        referrer,
+        match_limit,
+        match_limit_behavior,
+    } = args;
+
+    //orders must respect the market's trading parameters, market orders ignore their limit price
+    let market_data = ctx.market_data;
+    if coin_qty < market_data.min_order_size {
+        msg!("Order quantity {} is below the minimum order size of {} lots", coin_qty, market_data.min_order_size);
+        return Err(OrderBookError::BelowMinOrderSize.into());
+    }
+
+    if order_type != OrderType::Market && (limit_price == 0 || limit_price % market_data.tick_size != 0) {
+        msg!("Limit price {} is not a positive multiple of the tick size {}", limit_price, market_data.tick_size);
+        return Err(OrderBookError::InvalidTickSize.into());
+    }
+
+    if peg_offset.is_some_and(|peg_offset| peg_offset % market_data.tick_size as i64 != 0) {
+        msg!("Peg offset is not a multiple of the tick size {}", market_data.tick_size);
+        return Err(OrderBookError::InvalidTickSize.into());
+    }
+
+    if match_limit == Some(0) {
+        msg!("Match limit must allow at least one fill");
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    //good till time orders must expire in the future
+    let expiry_timestamp = expiry_timestamp.unwrap_or(0);
+    if expiry_timestamp != 0 && expiry_timestamp <= ctx.now {
+        msg!("Order expiry {} is not after current time {}", expiry_timestamp, ctx.now);
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    //pegged orders follow the oracle, their limit price caps a bid and floors an ask
+    let taker_price = match peg_offset {
+        Some(peg_offset) => {
+            if order_type == OrderType::Market {
+                msg!("Market orders can not be pegged");
+                return Err(ProgramError::InvalidArgument);
+            }
+            let oracle_price = ctx.oracle_price.ok_or_else(|| {
+                msg!("Oracle price is unavailable or stale, can not place a pegged order");
+                ProgramError::InvalidArgument
+            })?;
+            let pegged_price = pegged_price(oracle_price, peg_offset);
+            match side {
+                Side::Bid => pegged_price.min(limit_price),
+                Side::Ask => pegged_price.max(limit_price)
+            }
+        }
+        None => limit_price
+    };
 
     let (taker_book, maker_book) = match side {
         Side::Bid => {
-            (&mut bids_data, &mut asks_data)
+            (&mut *ctx.bids_data, &mut *ctx.asks_data)
         }
         Side::Ask => {
-            (&mut asks_data, &mut bids_data)
+            (&mut *ctx.asks_data, &mut *ctx.bids_data)
         }
     };
 
     //check if taker book is filled
-    if taker_book.slots_filled >= 1024 {
+    if taker_book.is_full() {
         if side == Side::Bid {
             msg!("Bids is full right now");  
         } else {
             msg!("Asks is full right now");
         }
-        return Err(ProgramError::Custom(1));
+        return Err(OrderBookError::BookFull.into());
     }
 
+    //post only orders must not take any liquidity, expired makers do not count
+    if order_type == OrderType::PostOnly {
+        let best_order = maker_book.best_order(ctx.oracle_price, |order| order.is_expired(ctx.now));
+        if let Some((_, best_price)) = best_order {
+            if side.is_crossed(taker_price, best_price) {
+                msg!("Post only order would cross the book at price {}", best_price);
+                return Err(OrderBookError::OrderWouldCross.into());
+            }
+        }
+    }
 
-    // //check if 
-    // if pc_qty < (limit_price) * (coin_qty) {
-    //     msg!("Not enough funds provided for instruction");
-    //     return Err(ProgramError::InsufficientFunds);
-    // }
 
-    //lock funds
-    let deposit_amount;
-    match side {
-        Side::Bid => {
-            let free_pc = user_market_data.free_pc;
-            let pc_qty_to_lock = pc_qty.min(free_pc);
-            deposit_amount = pc_qty - pc_qty_to_lock;
-            if payer.amount < deposit_amount {
-                msg!("Owner's ATA does not have enough PC balance");
-                return Err(ProgramError::InsufficientFunds);
-            }
-            user_market_data.lock_free_pc(&pc_qty_to_lock);
-            user_market_data.credit_locked_pc(&deposit_amount);
+    //asks lock their coin up front, a bid's pc is derived from its fills and remainder once matching is done
+    let mut deposit_amount = 0;
+    if side == Side::Ask {
+        let coin_amount = market_data.coin_native(coin_qty)?;
+        let free_coin = ctx.user_market_data.free_coin;
+        let coin_qty_to_lock = coin_amount.min(free_coin);
+        deposit_amount = coin_amount - coin_qty_to_lock;
+        ctx.user_market_data.lock_free_coin(&coin_qty_to_lock)?;
+        ctx.user_market_data.credit_locked_coin(&deposit_amount)?;
+        msg!("Funds Locked");
+    }
 
+    let mut coin_qty_remaining = coin_qty;
+    let mut pc_qty_traded = 0u64;
+    let mut taker_fees = 0u64;
+    let mut pc_qty_owed = 0u64;     //what a bid's fills take out of its locked pc when consumed, taker fees included
+    let mut taker_cancelled = false;
+    let mut fill_count = 0u16;
+    let mut match_limit_reached = false;
+
+    //every maker visited emits one event, matching never goes past the room left in the event queue
+    let mut event_capacity = ctx.market_events_data.free_space();
+    let mut event_queue_exhausted = false;
+
+    //makers are taken best effective price first, pegged orders are priced off the oracle at match time,
+    //makers that are done leave the book right away so the next best order is always at the top of its tree
+    loop {
+        if coin_qty_remaining == 0 {
+            break;
         }
-        Side::Ask => {
-            let free_coin = user_market_data.free_coin;
-            let coin_qty_to_lock = coin_qty.min(free_coin);
-            deposit_amount = coin_qty - coin_qty_to_lock;
-            if payer.amount < deposit_amount {
-                msg!("Owner's ATA does not have enough Coin balance");
-                return Err(ProgramError::InsufficientFunds);
-            }
-            user_market_data.lock_free_coin(&coin_qty_to_lock);
-            user_market_data.credit_locked_coin(&deposit_amount);
-        }
-    };
-    msg!("Funds Locked");
 
-    let mut coin_qty_remaining = coin_qty;
+        let best_order = maker_book.best_order(ctx.oracle_price, |_| false);
+        let Some((index, maker_price)) = best_order else {
+            break;
+        };
+        let maker_order = maker_book.order_mut(index);
 
-    let mut order_indexes_to_remove: Vec<usize> = Vec::new();
+        if order_type != OrderType::Market && !side.is_crossed(taker_price, maker_price) {
+            break;
+        }
 
-    for i in 0..maker_book.slots_filled {
-        let maker_order = &mut maker_book.orders[i as usize];
-        
-        if coin_qty_remaining == 0 {
+        if event_capacity == 0 {
+            msg!("Event queue has no room left, {} left unfilled", coin_qty_remaining);
+            event_queue_exhausted = true;
             break;
         }
 
-        let crossed = match side {
-            Side::Bid => {
-                limit_price >= maker_order.price
-            }
+        //expired makers are taken out of the book instead of being filled, whether or not the match limit is reached
+        if maker_order.is_expired(ctx.now) {
+            event_capacity -= 1;
+            let event = Event::out(maker_order, maker_order.remaining_quantity()?, market_data)?;
+            ctx.market_events_data.enqueue(event)?;
+            maker_book.remove_order(index)?;
+            msg!("Emitted Out Event for expired order");
+            continue;
+        }
 
-            Side::Ask => {
-                limit_price <= maker_order.price
-            }
-        };
-        if !crossed {
+        //bounds the compute spent on matching, the book still crosses here
+        if match_limit.is_some_and(|match_limit| fill_count >= match_limit) {
+            msg!("Match limit of {} fills reached, {} left unfilled", fill_count, coin_qty_remaining);
+            match_limit_reached = true;
             break;
         }
-        
-        let trade_qty = maker_order.quantity
-            .min(coin_qty_remaining);
-        coin_qty_remaining -= trade_qty;
-        maker_order.filled_quantity += trade_qty;
+        event_capacity -= 1;
+
+        //self trade prevention
+        if maker_order.owner == *ctx.owner {
+            let maker_order_id = maker_order.order_id;
+            let maker_qty_remaining = maker_order.remaining_quantity()?;
+            let cancel_qty = match self_trade_behavior {
+                SelfTradeBehavior::AbortTransaction => {
+                    msg!("Order would trade against own order {}", maker_order_id);
+                    return Err(OrderBookError::SelfTradeAborted.into());
+                }
+                SelfTradeBehavior::CancelTaker => {
+                    msg!("Order would trade against own order {}, cancelling remainder", maker_order_id);
+                    taker_cancelled = true;
+                    break;
+                }
+                SelfTradeBehavior::CancelMaker => {
+                    maker_qty_remaining
+                }
+                SelfTradeBehavior::DecrementAndCancel => {
+                    let decrement_qty = maker_qty_remaining.min(coin_qty_remaining);
+                    coin_qty_remaining -= decrement_qty;
+                    if side == Side::Ask {
+                        ctx.user_market_data.unlock_coin(&market_data.coin_native(decrement_qty)?)?;
+                    }
+                    decrement_qty
+                }
+            };
+
+            //emit out event so the cancelled part of the maker gets unlocked
+            let mut event = Event::out(maker_order, cancel_qty, market_data)?;
+            maker_order.reduce_quantity(cancel_qty)?;
+            event.maker_out = (maker_order.remaining_quantity()? == 0) as u8;
+            ctx.market_events_data.enqueue(event)?;
+            msg!("Emitted Out Event for self trade");
+        } else {
+            let trade_qty = maker_order.quantity.checked_sub(maker_order.filled_quantity)
+                .ok_or(OrderBookError::ArithmeticOverflow)?
+                .min(coin_qty_remaining);
+            let trade_pc_qty = market_data.pc_native(maker_price, trade_qty)?;
+            let taker_fee = market_data.fill_fees(trade_pc_qty)?.0;
+            coin_qty_remaining -= trade_qty;
+            pc_qty_traded = pc_qty_traded.checked_add(trade_pc_qty).ok_or(OrderBookError::ArithmeticOverflow)?;
+            taker_fees = taker_fees.checked_add(taker_fee).ok_or(OrderBookError::ArithmeticOverflow)?;
+            pc_qty_owed = trade_pc_qty.checked_add(taker_fee)
+                .and_then(|trade_pc_owed| pc_qty_owed.checked_add(trade_pc_owed))
+                .ok_or(OrderBookError::ArithmeticOverflow)?;
+
+            //bid makers locked at their own price, which is the peg limit for pegged orders, the event tells how much to release
+            let maker_pc_locked = match maker_order.side {
+                Side::Bid => market_data.bid_lock_between(maker_order.price, maker_order.filled_quantity, maker_order.filled_quantity + trade_qty)?,
+                Side::Ask => 0
+            };
+            maker_order.filled_quantity += trade_qty;
+            ctx.last_traded_price = maker_price;
+
+            //emit fill event for this order
+            let event = Event {
+                event_type: EventType::Fill,
+                side: maker_order.side,
+                maker: maker_order.owner,
+                taker: *ctx.owner,
+                coin_qty: market_data.coin_native(trade_qty)?,
+                pc_qty: trade_pc_qty,
+                maker_order_id: maker_order.order_id,
+                maker_client_order_id: maker_order.client_order_id,
+                referrer: referrer.unwrap_or_default(),
+                maker_pc_locked,
+                maker_out: (maker_order.remaining_quantity()? == 0) as u8,
+                taker_client_order_id: client_order_id
+            };
+            ctx.market_events_data.enqueue(event)?;
+            fill_count += 1;
+            msg!("Emitted Fill Event");
+        }
 
-        if maker_order.quantity == maker_order.filled_quantity {
-            order_indexes_to_remove.push(i as usize);
+        //done makers leave the book, icebergs show their next slice at the back of their price level
+        if maker_order.remaining_quantity()? == 0 {
+            maker_book.remove_order(index)?;
+        } else if maker_order.refill()? {
+            maker_book.move_to_back(index)?;
+            msg!("Refilled iceberg order");
         }
+    }
+    msg!("Matching complete");
 
-        //emit fill event for this order
-        let event = Event {
-            event_type: EventType::Fill,
-            side: maker_book.side,
-            maker: maker_order.owner,
-            taker: *owner_account.key,
-            coin_qty: trade_qty,
-            pc_qty: trade_qty * maker_order.price,
-            maker_order_id: maker_order.order_id
+    if order_type == OrderType::FillOrKill && coin_qty_remaining > 0 {
+        msg!("Fill or kill order could not be fully matched, {} left unfilled", coin_qty_remaining);
+        return Err(OrderBookError::FillOrKillNotFilled.into());
+    }
+
+    //the bound covers taker fees, a market bid's spend includes them and a market ask receives its fills net of them
+    if order_type == OrderType::Market {
+        let (pc_qty_net, within_limit) = match side {
+            Side::Bid => (pc_qty_owed, pc_qty_owed <= pc_qty_limit),
+            Side::Ask => {
+                let pc_qty_received = pc_qty_traded.checked_sub(taker_fees).ok_or(OrderBookError::ArithmeticOverflow)?;
+                (pc_qty_received, pc_qty_received >= pc_qty_limit)
+            }
         };
-        let result = market_events_data.enqueue(event)?;
-        if !result {
-            //TODO: handle this
-            msg!("Event Queue is Full");
+        if !within_limit {
+            msg!("Market order moved {} pc after taker fees, outside its limit of {}", pc_qty_net, pc_qty_limit);
+            return Err(OrderBookError::MarketOrderLimitExceeded.into());
         }
-        msg!("Emitted Fill Event");
     }
-    msg!("Matching complete");
 
-    
-    //remove filled orders from maker book
-    for i in 0..order_indexes_to_remove.len() {
-        maker_book.remove_order(order_indexes_to_remove[i])?;
+
+    //only limit and post only orders rest, release whatever the others did not spend
+    let mut rests = (order_type == OrderType::Limit || order_type == OrderType::PostOnly) && !taker_cancelled;
+
+    //a remainder left by the match limit or a full event queue still crosses the book, it is dropped or the order fails
+    if rests && (match_limit_reached || event_queue_exhausted) {
+        if match_limit_behavior == MatchLimitBehavior::DropRemainder {
+            rests = false;
+        } else if event_queue_exhausted {
+            msg!("Event queue can not hold the fills of this order");
+            return Err(OrderBookError::EventQueueFull.into());
+        } else {
+            msg!("Match limit reached while the remainder still crosses the book");
+            return Err(OrderBookError::MatchLimitReached.into());
+        }
     }
-    if order_indexes_to_remove.len() != 0 {
-        msg!("Remove filled orders from maker book")
+    if !rests {
+        if side == Side::Ask {
+            ctx.user_market_data.unlock_coin(&market_data.coin_native(coin_qty_remaining)?)?;
+        }
+        msg!("Released funds not spent by the order");
+        coin_qty_remaining = 0;
+    }
+
+    //a bid locks what its fills cost plus the remainder at its resting price
+    if side == Side::Bid {
+        let pc_qty_needed = pc_qty_owed.checked_add(market_data.bid_lock(limit_price, coin_qty_remaining)?)
+            .ok_or(OrderBookError::ArithmeticOverflow)?;
+        let free_pc = ctx.user_market_data.free_pc;
+        let pc_qty_to_lock = pc_qty_needed.min(free_pc);
+        deposit_amount = pc_qty_needed - pc_qty_to_lock;
+        ctx.user_market_data.lock_free_pc(&pc_qty_to_lock)?;
+        ctx.user_market_data.credit_locked_pc(&deposit_amount)?;
+        msg!("Locked {} pc for the order", pc_qty_needed);
     }
-    
 
     //add unfilled orders in taker book
     if coin_qty_remaining > 0 {
-        let order_id = taker_book.next_order_id;
+        let order_id = taker_book.header.next_order_id;
         let side_encoded_order_id = OpenOrderAccount::create_side_encoded_order_id(order_id, side);
-        let index = open_order_data.next_array_index;
-        open_order_data.order_ids[index as usize] = side_encoded_order_id;
-        open_order_data.next_array_index += 1;
+        ctx.open_order_data.add_order(side_encoded_order_id, client_order_id)?;
+
+        //iceberg orders only show display_quantity, the rest is kept in reserve
+        let visible_qty = if display_quantity > 0 {
+            display_quantity.min(coin_qty_remaining)
+        } else {
+            coin_qty_remaining
+        };
 
         let remaining_order = Order {
-            order_id: order_id,
-            owner: *owner_account.key,
-            market: *market_account.key,
+            order_id,
+            owner: *ctx.owner,
+            market: *ctx.market,
             price: limit_price,
-            quantity: coin_qty_remaining,
+            quantity: visible_qty,
             filled_quantity: 0,
-            side: side
+            side,
+            client_order_id,
+            expiry_timestamp,
+            display_quantity,
+            reserve_quantity: coin_qty_remaining - visible_qty,
+            pegged: peg_offset.is_some() as u8,
+            peg_offset: peg_offset.unwrap_or(0)
         };
         taker_book.add_order(remaining_order)?;
-        taker_book.next_order_id += 1;
+        taker_book.header.next_order_id += 1;
         msg!("Added unfilled order in maker book");
     }
-    
 
-    //transfer extra funds to vault if needed
-    if deposit_amount > 0 {
-        let mint_account = match side {
-            Side::Bid => {
-                pc_mint_account
-            }
-            Side::Ask => {
-                coin_mint_account
-            }
-        };
+    Ok(deposit_amount)
+}
 
-        let transfer_ix = transfer(
-            token_program_account.key, 
-            payer_account.key, 
-            vault_account.key, 
-            owner_account.key, 
-            &[owner_account.key, accounts_authority.key], 
-            deposit_amount
-        )?;
 
-        invoke(
-            &transfer_ix, 
-            &[
-                mint_account.clone(),
-                payer_account.clone(),
-                vault_account.clone(),
-                owner_account.clone(),
-                accounts_authority.clone(),
-                token_program_account.clone(),
-            ]
-        )?;
+//referrers are credited in their user market account when fills are consumed, so it must already exist
+pub fn verify_referrer(
+    program_id: &Pubkey,
+    market: &Pubkey,
+    owner: &Pubkey,
+    referrer: &Pubkey,
+    referrer_market_account: Option<&AccountInfo>
+) -> ProgramResult {
+    if referrer == owner {
+        msg!("Orders can not name their own owner as referrer");
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let referrer_market_account = referrer_market_account.ok_or_else(|| {
+        msg!("Referrer's User Market account is not provided");
+        ProgramError::NotEnoughAccountKeys
+    })?;
 
-        msg!("Transferred extra funds to vault account");
+    let referrer_market_pda = Pubkey::find_program_address(
+        &[b"user_market_account", market.as_ref(), referrer.as_ref()],
+        program_id
+    ).0;
+
+    if *referrer_market_account.key != referrer_market_pda {
+        msg!("Invalid referrer user market account provided, expected: {}", referrer_market_pda);
+        return Err(ProgramError::InvalidAccountData);
     }
-    
-    user_market_data.serialize(&mut *user_market_account.data.borrow_mut())?;
 
+    if referrer_market_account.lamports() == 0 || *referrer_market_account.owner != *program_id {
+        msg!("Referrer's User Market Account has not been initialised");
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    Ok(())
+}
+
+
+//reads the price of the oracle the market was set up with, none when it is stale
+pub fn load_oracle_price(oracle_account: &AccountInfo, market_data: &MarketState, now: i64) -> Result<Option<u64>, ProgramError> {
+    if *oracle_account.key != market_data.oracle {
+        msg!("Invalid oracle account provided, expected: {}", market_data.oracle);
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let oracle_raw_data = oracle_account.data.borrow();
+    if oracle_raw_data.len() < OraclePrice::LEN {
+        msg!("Invalid oracle account provided, it is too small");
+        return Err(ProgramError::InvalidAccountData);
+    }
+    let oracle_data: &OraclePrice = bytemuck::from_bytes(&oracle_raw_data[..OraclePrice::LEN]);
+
+    Ok(oracle_data.current_price(now))
+}
+
+
+//moves funds from the owner's token account into the market vault
+pub fn transfer_to_vault<'a>(
+    token_program_account: &AccountInfo<'a>,
+    payer_account: &AccountInfo<'a>,
+    vault_account: &AccountInfo<'a>,
+    owner_account: &AccountInfo<'a>,
+    accounts_authority: &AccountInfo<'a>,
+    mint_account: &AccountInfo<'a>,
+    amount: u64
+) -> ProgramResult {
+    let transfer_ix = transfer(
+        token_program_account.key, 
+        payer_account.key, 
+        vault_account.key, 
+        owner_account.key, 
+        &[owner_account.key, accounts_authority.key], 
+        amount
+    )?;
+
+    invoke(
+        &transfer_ix, 
+        &[
+            mint_account.clone(),
+            payer_account.clone(),
+            vault_account.clone(),
+            owner_account.clone(),
+            accounts_authority.clone(),
+            token_program_account.clone(),
+        ]
+    )?;
+
+    msg!("Transferred extra funds to vault account");
     Ok(())
 }
\ No newline at end of file
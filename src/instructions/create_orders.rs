@@ -0,0 +1,21 @@
+use solana_program::{account_info::AccountInfo, entrypoint::ProgramResult, msg, pubkey::Pubkey};
+
+use crate::{instructions::cancel_and_replace::cancel_and_replace, state::{CancelAndReplaceArgs, CreateOrderArgs}};
+
+//places a batch of orders, it takes the same accounts as cancel and replace with nothing to cancel
+pub fn create_orders(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    args: Vec<CreateOrderArgs>
+) -> ProgramResult {
+    msg!("Placing {} orders", args.len());
+
+    cancel_and_replace(
+        program_id,
+        accounts,
+        CancelAndReplaceArgs {
+            cancel_orders: Vec::new(),
+            new_orders: args
+        }
+    )
+}
@@ -0,0 +1,209 @@
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::{account_info::{next_account_info, AccountInfo}, entrypoint::ProgramResult, msg, program_error::ProgramError, program_pack::Pack, pubkey::Pubkey};
+use spl_token::state::Account as TokenAccount;
+
+use crate::{error::OrderBookError, instructions::create_order::transfer_to_vault, state::{CreateTriggerOrderArgs, MarketState, OrderType, SelfTradeBehavior, Side, TriggerOrder, TriggerOrderBook, UserMarketAccount}};
+
+pub fn create_trigger_order(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    args: CreateTriggerOrderArgs
+) -> ProgramResult {
+    let mut iter = accounts.iter();
+
+    let accounts_authority = next_account_info(&mut iter)?;
+    let market_account = next_account_info(&mut iter)?;
+    let owner_account = next_account_info(&mut iter)?;
+    let user_market_account = next_account_info(&mut iter)?;
+    let payer_account = next_account_info(&mut iter)?;
+    let vault_account = next_account_info(&mut iter)?;
+    let coin_mint_account = next_account_info(&mut iter)?;
+    let pc_mint_account = next_account_info(&mut iter)?;
+    let trigger_orders_account = next_account_info(&mut iter)?;
+    let token_program_account = next_account_info(&mut iter)?;
+
+
+    //triggered orders are placed by a crank, so they must not be able to fail the crank
+    if args.order_type != OrderType::Limit && args.order_type != OrderType::ImmediateOrCancel {
+        msg!("Trigger orders can only place Limit or ImmediateOrCancel orders");
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    if args.self_trade_behavior == SelfTradeBehavior::AbortTransaction {
+        msg!("Trigger orders can not abort on self trade");
+        return Err(ProgramError::InvalidArgument);
+    }
+
+
+    //verify owner
+    if !owner_account.is_signer {
+        msg!("Owner must sign to place trigger orders");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    //verify market account
+    let market_seeds = &[b"market", pc_mint_account.key.as_ref(), coin_mint_account.key.as_ref()];
+
+    let market_pda = Pubkey::find_program_address(
+        market_seeds,
+        program_id
+    ).0;
+
+    if *market_account.key != market_pda {
+        msg!("Invalid market account provided, expected: {}", market_pda);
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let market_data = MarketState::try_from_slice(&market_account.data.borrow())?;
+
+    //trigger orders must respect the market's trading parameters once placed
+    if args.coin_qty < market_data.min_order_size {
+        msg!("Trigger order quantity {} is below the minimum order size of {} lots", args.coin_qty, market_data.min_order_size);
+        return Err(OrderBookError::BelowMinOrderSize.into());
+    }
+
+    for price in [args.trigger_price, args.limit_price] {
+        if price == 0 || price % market_data.tick_size != 0 {
+            msg!("Price {} is not a positive multiple of the tick size {}", price, market_data.tick_size);
+            return Err(OrderBookError::InvalidTickSize.into());
+        }
+    }
+
+
+    //verify trigger orders account
+    if *trigger_orders_account.key != market_data.trigger_orders {
+        msg!("Invalid trigger orders account provided, expected: {}", market_data.trigger_orders);
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+
+    //verify user market account
+    let user_market_seeds = [b"user_market_account", market_account.key.as_ref(), owner_account.key.as_ref()];
+
+    let user_market_pda = Pubkey::find_program_address(&user_market_seeds, program_id).0;
+
+    if user_market_pda != *user_market_account.key {
+        msg!("Invalid user market account provided, expected: {}", user_market_pda);
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if user_market_account.lamports() == 0 {
+        msg!("User Market Account has not been initialised");
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let mut user_market_data = UserMarketAccount::try_from_slice(*user_market_account.data.borrow_mut())?;
+
+    if user_market_data.owner != *owner_account.key {
+        msg!("Invalid user market account, does not belongs to provided owner");
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if user_market_data.market != *market_account.key {
+        msg!("Invalid user market account, does not belongs to provided market");
+        return Err(ProgramError::InvalidAccountData);
+    }
+    msg!("User Market account verified");
+
+
+    //get payer and vault atas and verify them
+    let mint_account = match args.side {
+        Side::Bid => pc_mint_account,
+        Side::Ask => coin_mint_account
+    };
+
+    let payer = TokenAccount::unpack(*payer_account.data.borrow_mut())?;
+    if payer.mint != *mint_account.key {
+        msg!("Given payer account is of wrong mint, expected {}", mint_account.key);
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let expected_vault = match args.side {
+        Side::Bid => market_data.pc_vault,
+        Side::Ask => market_data.coin_vault
+    };
+    if *vault_account.key != expected_vault {
+        msg!("Invalid vault account provided, expected: {}", expected_vault);
+        return Err(ProgramError::InvalidAccountData);
+    }
+    msg!("Accounts verification success");
+
+
+    let mut trigger_orders_raw_data = trigger_orders_account.data.borrow_mut();
+    let trigger_orders_data = TriggerOrderBook::load(&mut trigger_orders_raw_data)?;
+
+    //bids lock enough to fill the whole order at their limit price, taker fee included
+    let pc_qty = match args.side {
+        Side::Bid => market_data.bid_max_lock(args.limit_price, args.coin_qty)?,
+        Side::Ask => 0
+    };
+
+    let trigger_order = TriggerOrder {
+        trigger_id: trigger_orders_data.next_trigger_id,
+        owner: *owner_account.key,
+        side: args.side,
+        order_type: args.order_type,
+        self_trade_behavior: args.self_trade_behavior,
+        trigger_price: args.trigger_price,
+        limit_price: args.limit_price,
+        coin_qty: args.coin_qty,
+        pc_qty,
+        client_order_id: args.client_order_id
+    };
+
+    if trigger_order.is_triggered(market_data.last_traded_price) {
+        msg!("Trigger price {} is already reached at last traded price {}", args.trigger_price, market_data.last_traded_price);
+        return Err(ProgramError::InvalidArgument);
+    }
+
+
+    //lock funds now so the order can be placed once it triggers
+    let lock_amount = match args.side {
+        Side::Bid => pc_qty,
+        Side::Ask => market_data.coin_native(args.coin_qty)?
+    };
+    let deposit_amount;
+    match args.side {
+        Side::Bid => {
+            let pc_qty_to_lock = lock_amount.min(user_market_data.free_pc);
+            deposit_amount = lock_amount - pc_qty_to_lock;
+            user_market_data.lock_free_pc(&pc_qty_to_lock)?;
+            user_market_data.credit_locked_pc(&deposit_amount)?;
+        }
+        Side::Ask => {
+            let coin_qty_to_lock = lock_amount.min(user_market_data.free_coin);
+            deposit_amount = lock_amount - coin_qty_to_lock;
+            user_market_data.lock_free_coin(&coin_qty_to_lock)?;
+            user_market_data.credit_locked_coin(&deposit_amount)?;
+        }
+    };
+
+    if payer.amount < deposit_amount {
+        msg!("Owner's ATA does not have enough balance");
+        return Err(OrderBookError::InsufficientFunds.into());
+    }
+    msg!("Funds Locked");
+
+    let trigger_id = trigger_orders_data.next_trigger_id;
+    trigger_orders_data.add_order(trigger_order)?;
+    trigger_orders_data.next_trigger_id += 1;
+    msg!("Added trigger order {}", trigger_id);
+
+
+    //transfer extra funds to vault if needed
+    if deposit_amount > 0 {
+        transfer_to_vault(
+            token_program_account,
+            payer_account,
+            vault_account,
+            owner_account,
+            accounts_authority,
+            mint_account,
+            deposit_amount
+        )?;
+    }
+
+    user_market_data.serialize(&mut *user_market_account.data.borrow_mut())?;
+
+    Ok(())
+}
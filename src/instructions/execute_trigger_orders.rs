@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::{account_info::{next_account_info, AccountInfo}, clock::Clock, entrypoint::ProgramResult, msg, program_error::ProgramError, pubkey::Pubkey, sysvar::Sysvar};
+
+use crate::{error::OrderBookError, instructions::create_order::{load_oracle_price, place_order, OrderContext}, state::{ExecuteTriggerOrdersArgs, MarketEventsAccount, MarketState, OpenOrderAccount, OrderBook, OrderType, Side, TriggerOrderBook, UserMarketAccount, MAX_OPEN_ORDERS}};
+
+pub fn execute_trigger_orders(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    args: ExecuteTriggerOrdersArgs
+) -> ProgramResult {
+    let mut iter = accounts.iter();
+
+    let market_account = next_account_info(&mut iter)?;
+    let market_events_account = next_account_info(&mut iter)?;
+    let coin_mint_account = next_account_info(&mut iter)?;
+    let pc_mint_account = next_account_info(&mut iter)?;
+    let bids_account = next_account_info(&mut iter)?;
+    let asks_account = next_account_info(&mut iter)?;
+    let trigger_orders_account = next_account_info(&mut iter)?;
+    let oracle_account = next_account_info(&mut iter)?;
+    let user_accounts: Vec<&AccountInfo> = iter.collect();
+
+    let mut user_account_map: HashMap<Pubkey, &AccountInfo> = HashMap::new();
+    for account in user_accounts {
+        user_account_map.insert(*account.key, account);
+    }
+
+    let now = Clock::get()?.unix_timestamp;
+
+
+    //verify market account
+    let market_seeds = &[b"market", pc_mint_account.key.as_ref(), coin_mint_account.key.as_ref()];
+
+    let market_pda = Pubkey::find_program_address(
+        market_seeds,
+        program_id
+    ).0;
+
+    if *market_account.key != market_pda {
+        msg!("Invalid market account provided, expected: {}", market_pda);
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let mut market_data = MarketState::try_from_slice(&market_account.data.borrow())?;
+
+
+    //verify market events account
+    if *market_events_account.owner != *program_id {
+        msg!("Invalid market events account provided, it has wrong owner");
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    //verify bids, asks and trigger orders accounts
+    if *bids_account.key != market_data.bids {
+        msg!("Invalid bids account provided, expected: {}", market_data.bids);
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if *asks_account.key != market_data.asks {
+        msg!("Invalid asks account provided, expected: {}", market_data.asks);
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if *trigger_orders_account.key != market_data.trigger_orders {
+        msg!("Invalid trigger orders account provided, expected: {}", market_data.trigger_orders);
+        return Err(ProgramError::InvalidAccountData);
+    }
+    msg!("Accounts verification success");
+
+
+    let market_events_raw_data = &mut market_events_account.data.borrow_mut();
+    let mut market_events_data = MarketEventsAccount::load(market_events_raw_data)?;
+
+    let mut bids_raw_data = bids_account.data.borrow_mut();
+    let mut bids_data = OrderBook::load(&mut bids_raw_data)?;
+
+    let mut asks_raw_data = asks_account.data.borrow_mut();
+    let mut asks_data = OrderBook::load(&mut asks_raw_data)?;
+
+    let mut trigger_orders_raw_data = trigger_orders_account.data.borrow_mut();
+    let trigger_orders_data = TriggerOrderBook::load(&mut trigger_orders_raw_data)?;
+
+
+    //place triggered orders one at a time, each fill can trigger further orders
+    let mut last_traded_price = market_data.last_traded_price;
+    let oracle_price = load_oracle_price(oracle_account, &market_data, now)?;
+    let mut processed_count = 0;
+    while processed_count < args.limit {
+        let triggered_index = (0..trigger_orders_data.slots_filled as usize)
+            .find(|&i| trigger_orders_data.orders[i].is_triggered(last_traded_price));
+        let Some(index) = triggered_index else {
+            break;
+        };
+
+        let trigger_order = trigger_orders_data.orders[index];
+        trigger_orders_data.remove_order(index)?;
+        let owner = trigger_order.owner;
+        let trigger_id = trigger_order.trigger_id;
+        let coin_qty = trigger_order.coin_qty;
+        let pc_qty = trigger_order.pc_qty;
+
+        //retrieve User Market and Open Order accounts of the owner
+        let uma_pda = Pubkey::find_program_address(
+            &[b"user_market_account", market_account.key.as_ref(), owner.as_ref()],
+            program_id
+        ).0;
+        let uma_info = user_account_map
+            .get(&uma_pda)
+            .ok_or_else(|| {
+                msg!("Owner's User Market account is not provided: {}", uma_pda);
+                ProgramError::NotEnoughAccountKeys
+        })?;
+
+        let open_order_pda = Pubkey::find_program_address(
+            &[b"open_order", market_account.key.as_ref(), owner.as_ref()],
+            program_id
+        ).0;
+        let open_order_info = user_account_map
+            .get(&open_order_pda)
+            .ok_or_else(|| {
+                msg!("Owner's Open Order account is not provided: {}", open_order_pda);
+                ProgramError::NotEnoughAccountKeys
+        })?;
+
+        if open_order_info.lamports() == 0 {
+            msg!("Open Order account has not been initialised");
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let mut user_market_data = UserMarketAccount::try_from_slice(*uma_info.data.borrow())?;
+        let mut open_order_raw_data = open_order_info.data.borrow_mut();
+        let open_order_data: &mut OpenOrderAccount = bytemuck::from_bytes_mut(&mut open_order_raw_data);
+
+        //hand the locked funds back to free balance, placing the order locks them again
+        match trigger_order.side {
+            Side::Bid => {
+                user_market_data.unlock_pc(&pc_qty)?;
+            }
+            Side::Ask => {
+                user_market_data.unlock_coin(&market_data.coin_native(coin_qty)?)?;
+            }
+        };
+
+        //an order that can no longer be placed is cancelled with its funds released, it must not block the orders behind it
+        let taker_book_full = match trigger_order.side {
+            Side::Bid => bids_data.is_full(),
+            Side::Ask => asks_data.is_full()
+        };
+        let open_orders_full = trigger_order.order_type == OrderType::Limit && open_order_data.next_array_index as usize >= MAX_OPEN_ORDERS;
+        if taker_book_full || open_orders_full {
+            msg!("Trigger order {} has no room to rest, cancelled it", trigger_id);
+            user_market_data.serialize(&mut *uma_info.data.borrow_mut())?;
+            processed_count += 1;
+            continue;
+        }
+
+        let mut ctx = OrderContext {
+            owner: &owner,
+            market: market_account.key,
+            now,
+            market_data: &market_data,
+            user_market_data: &mut user_market_data,
+            open_order_data,
+            bids_data: &mut bids_data,
+            asks_data: &mut asks_data,
+            market_events_data: &mut market_events_data,
+            last_traded_price,
+            oracle_price
+        };
+        let deposit_amount = place_order(&mut ctx, trigger_order.to_create_order_args())?;
+        last_traded_price = ctx.last_traded_price;
+
+        if deposit_amount > 0 {
+            msg!("Trigger order {} is missing {} of locked funds", trigger_id, deposit_amount);
+            return Err(OrderBookError::InsufficientFunds.into());
+        }
+
+        user_market_data.serialize(&mut *uma_info.data.borrow_mut())?;
+        processed_count += 1;
+    }
+    msg!("Processed {} trigger orders", processed_count);
+
+    market_data.last_traded_price = last_traded_price;
+    market_data.serialize(&mut *market_account.data.borrow_mut())?;
+
+    Ok(())
+}
@@ -2,11 +2,12 @@ use borsh::BorshSerialize;
 use bytemuck::Zeroable;
 use solana_program::{account_info::{next_account_info, AccountInfo}, entrypoint::ProgramResult, msg, program::{invoke, invoke_signed}, program_error::ProgramError, program_pack::Pack, pubkey::Pubkey, system_instruction::create_account, sysvar::rent};
 use spl_token::{instruction::initialize_account, state::Account};
-use crate::{state::{Event, MarketEventsAccount, MarketState, Order, OrderBook, Side, MAX_EVENT}};
+use crate::{state::{InitializeMarketArgs, MarketEventsAccount, MarketState, OraclePrice, OrderBook, Side, TriggerOrder, TriggerOrderBook, MAX_TRIGGER_ORDERS}};
 
 pub fn initialize_market_instruction(
     program_id: &Pubkey,
     accounts: &[AccountInfo],
+    args: InitializeMarketArgs
 ) -> ProgramResult {
     let mut iter = accounts.iter();
 
@@ -19,6 +20,9 @@ pub fn initialize_market_instruction(
     let pc_vault_account = next_account_info(&mut iter)?;
     let bids_account = next_account_info(&mut iter)?;
     let asks_account = next_account_info(&mut iter)?;
+    let trigger_orders_account = next_account_info(&mut iter)?;
+    let oracle_account = next_account_info(&mut iter)?;
+    let fee_destination_account = next_account_info(&mut iter)?;
     let system_program_account = next_account_info(&mut iter)?;
     let token_program_account = next_account_info(&mut iter)?;
     let rent_sysvar_account = next_account_info(&mut iter)?;
@@ -85,6 +89,45 @@ pub fn initialize_market_instruction(
         return Err(ProgramError::InvalidAccountData);
     }
 
+    //verify trigger orders account
+    if *trigger_orders_account.owner != *program_id {
+        msg!("Invalid trigger orders account provided, it has wrong owner");
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    //verify trading parameters
+    if args.tick_size == 0 || args.base_lot_size == 0 || args.quote_lot_size == 0 || args.min_order_size == 0 {
+        msg!("Tick size, lot sizes and minimum order size must all be positive");
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    //verify fee schedule, a maker rebate is paid out of the taker fee
+    if args.taker_fee_bps > 10_000 || args.maker_fee_bps.unsigned_abs() > 10_000 || (args.taker_fee_bps as i32) + (args.maker_fee_bps as i32) < 0 {
+        msg!("Invalid fee schedule, maker rebate must not exceed the taker fee");
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    //rebates and referrer shares are both paid out of the taker fee
+    let taker_fee_kept = args.taker_fee_bps as u64 * (10_000u64.saturating_sub(args.referrer_fee_share_bps as u64));
+    let maker_rebate = if args.maker_fee_bps < 0 { args.maker_fee_bps.unsigned_abs() as u64 * 10_000 } else { 0 };
+    if args.referrer_fee_share_bps > 10_000 || taker_fee_kept < maker_rebate {
+        msg!("Invalid referrer fee share, the taker fee must cover it and the maker rebate");
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    //verify fee destination, fees are swept out of the pc vault
+    let fee_destination = Account::unpack(*fee_destination_account.data.borrow())?;
+    if fee_destination.mint != *pc_mint_account.key {
+        msg!("Given fee destination account is of wrong mint, expected {}", pc_mint_account.key);
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    //verify oracle account, pegged orders read their price from it
+    if oracle_account.data_len() < OraclePrice::LEN {
+        msg!("Invalid oracle account provided, it is too small for a price");
+        return Err(ProgramError::InvalidAccountData);
+    }
+
     msg!("Accounts verification success");
 
     //create market account
@@ -115,15 +158,11 @@ pub fn initialize_market_instruction(
 
 
     //initialize data inside market_events_account
+    //its capacity follows from the size the account was created with
     let mut events_acc_raw_data = market_events_account.data.borrow_mut();
-    let events_acc_data: &mut MarketEventsAccount = bytemuck::from_bytes_mut(&mut events_acc_raw_data);
+    let events_acc_data = MarketEventsAccount::init(&mut events_acc_raw_data, *market_account.key)?;
 
-    events_acc_data.market = *market_account.key;
-    events_acc_data.head = 0;
-    events_acc_data.tail = 0;
-    events_acc_data.events = [Event::zeroed(); MAX_EVENT as usize];
-
-    msg!("Initialised data inside market events account");
+    msg!("Initialised data inside market events account, capacity {} events", { events_acc_data.header.capacity });
 
 
     //create and initialize coin_vault token account
@@ -215,39 +254,51 @@ pub fn initialize_market_instruction(
 
     msg!("Initialised pc vault account as ata");
 
-    //initialise data inside bids account
+    //initialise data inside bids account, sized by the account like the asks
     let mut bids_raw_data = bids_account.data.borrow_mut();
-    let bids_data: &mut OrderBook = bytemuck::from_bytes_mut(&mut bids_raw_data);
-    
-    bids_data.side = Side::Bid;
-    bids_data.market = market_account.key.clone();
-    bids_data.next_order_id = 0;
-    bids_data.orders = [Order::zeroed(); 1024];
-    bids_data.slots_filled = 0;
+    let bids_data = OrderBook::init(&mut bids_raw_data, Side::Bid, *market_account.key)?;
     
-    msg!("Initialised data inside bids account");
+    msg!("Initialised data inside bids account, capacity {} orders", { bids_data.header.capacity });
 
     //initialise data in asks account 
     let mut asks_raw_data = asks_account.data.borrow_mut();
-    let asks_data: &mut OrderBook = bytemuck::from_bytes_mut(&mut asks_raw_data);
+    let asks_data = OrderBook::init(&mut asks_raw_data, Side::Ask, *market_account.key)?;
+
+    msg!("Initialised data inside asks account, capacity {} orders", { asks_data.header.capacity });
+
+    //initialise data in trigger orders account
+    let mut trigger_orders_raw_data = trigger_orders_account.data.borrow_mut();
+    let trigger_orders_data = TriggerOrderBook::load(&mut trigger_orders_raw_data)?;
 
-    asks_data.side = Side::Ask;
-    asks_data.market = market_account.key.clone();
-    asks_data.orders = [Order::zeroed(); 1024];    
-    asks_data.next_order_id = 0;
-    asks_data.slots_filled = 0;
+    trigger_orders_data.market = *market_account.key;
+    trigger_orders_data.next_trigger_id = 0;
+    trigger_orders_data.orders = [TriggerOrder::zeroed(); MAX_TRIGGER_ORDERS as usize];
+    trigger_orders_data.slots_filled = 0;
 
-    msg!("Initialised data inside asks account");
+    msg!("Initialised data inside trigger orders account");
 
     //initialise data in market account
     let market_state = MarketState {
-        coin_vault: coin_vault_account.key.clone(),
-        pc_vault: pc_vault_account.key.clone(),
-        coin_mint: coin_mint_account.key.clone(),
-        pc_mint: pc_mint_account.key.clone(),
-        bids: bids_account.key.clone(),
-        asks: asks_account.key.clone(),
+        coin_vault: *coin_vault_account.key,
+        pc_vault: *pc_vault_account.key,
+        coin_mint: *coin_mint_account.key,
+        pc_mint: *pc_mint_account.key,
+        bids: *bids_account.key,
+        asks: *asks_account.key,
+        trigger_orders: *trigger_orders_account.key,
+        oracle: *oracle_account.key,
+        authority: *accounts_authority.key,
+        fee_destination: *fee_destination_account.key,
         next_order_id: 1,
+        last_traded_price: 0,
+        tick_size: args.tick_size,
+        base_lot_size: args.base_lot_size,
+        quote_lot_size: args.quote_lot_size,
+        min_order_size: args.min_order_size,
+        maker_fee_bps: args.maker_fee_bps,
+        taker_fee_bps: args.taker_fee_bps,
+        referrer_fee_share_bps: args.referrer_fee_share_bps,
+        fees_accrued: 0,
         bump: market_bump
     };
 
@@ -2,4 +2,16 @@ pub mod initialize_market;
 pub mod create_order;
 pub mod consume_events;
 pub mod settle_funds;
-pub mod cancel_order;
\ No newline at end of file
+pub mod cancel_order;
+pub mod cancel_order_by_client_id;
+pub mod prune_expired_orders;
+pub mod amend_order;
+pub mod cancel_all_orders;
+pub mod cancel_and_replace;
+pub mod create_orders;
+pub mod create_trigger_order;
+pub mod cancel_trigger_order;
+pub mod execute_trigger_orders;
+pub mod sweep_fees;
+pub mod realloc_market_account;
+pub mod close_user_accounts;
\ No newline at end of file
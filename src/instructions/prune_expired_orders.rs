@@ -0,0 +1,92 @@
+use borsh::BorshDeserialize;
+use solana_program::{account_info::{next_account_info, AccountInfo}, clock::Clock, entrypoint::ProgramResult, msg, program_error::ProgramError, pubkey::Pubkey, sysvar::Sysvar};
+
+use crate::state::{Event, MarketEventsAccount, MarketState, OrderBook, PruneExpiredOrdersArgs};
+
+pub fn prune_expired_orders(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    args: PruneExpiredOrdersArgs
+) -> ProgramResult {
+    let mut iter = accounts.iter();
+
+    let market_account = next_account_info(&mut iter)?;
+    let market_events_account = next_account_info(&mut iter)?;
+    let coin_mint_account = next_account_info(&mut iter)?;
+    let pc_mint_account = next_account_info(&mut iter)?;
+    let order_side_account = next_account_info(&mut iter)?;
+
+    let now = Clock::get()?.unix_timestamp;
+
+
+    //verify market account
+    let market_seeds = &[b"market", pc_mint_account.key.as_ref(), coin_mint_account.key.as_ref()];
+
+    let market_pda = Pubkey::find_program_address(
+        market_seeds,
+        program_id
+    ).0;
+
+    if *market_account.key != market_pda {
+        msg!("Invalid market account provided, expected: {}", market_pda);
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let market_data = MarketState::try_from_slice(&market_account.data.borrow())?;
+
+
+    //verify market events account
+    if *market_events_account.owner != *program_id {
+        msg!("Invalid market events account provided, it has wrong owner");
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    //verify Order Side account
+    if *order_side_account.owner != *program_id {
+        msg!("Invalid order side account provided, it has wrong owner");
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let mut order_side_raw_data = order_side_account.data.borrow_mut();
+    let mut order_book_data = OrderBook::load(&mut order_side_raw_data)?;
+
+    if order_book_data.header.side != args.side || order_book_data.header.market != *market_account.key {
+        msg!("Invalid order side account provided, expected: {:?}", args.side);
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+
+    //get market events account data
+    let market_events_raw_data = &mut market_events_account.data.borrow_mut();
+    let mut market_events_data = MarketEventsAccount::load(market_events_raw_data)?;
+
+
+    //emit out events for expired orders, up to the prune limit and the room left in the event queue
+    let mut order_indexes_to_remove: Vec<usize> = Vec::new();
+    for i in order_book_data.order_indexes() {
+        if order_indexes_to_remove.len() >= args.prune_limit as usize {
+            break;
+        }
+
+        if market_events_data.free_space() == 0 {
+            msg!("Event Queue is Full, stopping early");
+            break;
+        }
+
+        let order = *order_book_data.order(i);
+        if !order.is_expired(now) {
+            continue;
+        }
+
+        let event = Event::out(&order, order.remaining_quantity()?, &market_data)?;
+        market_events_data.enqueue(event)?;
+        order_indexes_to_remove.push(i);
+    }
+
+    for index in order_indexes_to_remove.iter() {
+        order_book_data.remove_order(*index)?;
+    }
+    msg!("Pruned {} expired orders", order_indexes_to_remove.len());
+
+    Ok(())
+}
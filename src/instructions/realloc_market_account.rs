@@ -0,0 +1,135 @@
+use borsh::BorshDeserialize;
+use solana_program::{account_info::{next_account_info, AccountInfo}, entrypoint::{ProgramResult, MAX_PERMITTED_DATA_INCREASE}, msg, program::invoke, program_error::ProgramError, pubkey::Pubkey, system_instruction::transfer, sysvar::rent};
+
+use crate::state::{MarketAccountKind, MarketEventsAccount, MarketState, OrderBook, ReallocMarketAccountArgs, Side};
+
+pub fn realloc_market_account(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    args: ReallocMarketAccountArgs
+) -> ProgramResult {
+    let mut iter = accounts.iter();
+
+    let authority_account = next_account_info(&mut iter)?;
+    let market_account = next_account_info(&mut iter)?;
+    let coin_mint_account = next_account_info(&mut iter)?;
+    let pc_mint_account = next_account_info(&mut iter)?;
+    let target_account = next_account_info(&mut iter)?;
+    let payer_account = next_account_info(&mut iter)?;
+    let system_program_account = next_account_info(&mut iter)?;
+
+
+    //verify market account
+    let market_seeds = &[b"market", pc_mint_account.key.as_ref(), coin_mint_account.key.as_ref()];
+
+    let market_pda = Pubkey::find_program_address(
+        market_seeds,
+        program_id
+    ).0;
+
+    if *market_account.key != market_pda {
+        msg!("Invalid market account provided, expected: {}", market_pda);
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let market_data = MarketState::try_from_slice(&market_account.data.borrow())?;
+
+
+    //verify authority
+    if !authority_account.is_signer {
+        msg!("Market authority must sign to realloc market accounts");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    if *authority_account.key != market_data.authority {
+        msg!("Only the market authority can realloc market accounts, expected: {}", market_data.authority);
+        return Err(ProgramError::IllegalOwner);
+    }
+
+
+    //verify the account being resized
+    if *target_account.owner != *program_id {
+        msg!("Invalid account provided to realloc, it has wrong owner");
+        return Err(ProgramError::IllegalOwner);
+    }
+
+    let new_len = {
+        let mut target_raw_data = target_account.data.borrow_mut();
+        match args.account {
+            MarketAccountKind::Bids | MarketAccountKind::Asks => {
+                let (expected_key, expected_side) = match args.account {
+                    MarketAccountKind::Bids => (market_data.bids, Side::Bid),
+                    _ => (market_data.asks, Side::Ask)
+                };
+                if *target_account.key != expected_key {
+                    msg!("Invalid order book account provided, expected: {}", expected_key);
+                    return Err(ProgramError::InvalidAccountData);
+                }
+                let book = OrderBook::load(&mut target_raw_data)?;
+                if book.header.side != expected_side || book.header.market != *market_account.key {
+                    msg!("Order book account does not belong to this side of the market");
+                    return Err(ProgramError::InvalidAccountData);
+                }
+                if args.new_capacity <= book.header.capacity {
+                    msg!("New capacity must be larger than the current capacity of {} orders", { book.header.capacity });
+                    return Err(ProgramError::InvalidArgument);
+                }
+                OrderBook::account_len(args.new_capacity)
+            },
+            MarketAccountKind::Events => {
+                let events = MarketEventsAccount::load(&mut target_raw_data)?;
+                if events.header.market != *market_account.key {
+                    msg!("Market events account does not belong to this market");
+                    return Err(ProgramError::InvalidAccountData);
+                }
+                if args.new_capacity <= events.header.capacity {
+                    msg!("New capacity must be larger than the current capacity of {} events", { events.header.capacity });
+                    return Err(ProgramError::InvalidArgument);
+                }
+                MarketEventsAccount::account_len(args.new_capacity)
+            }
+        }
+    };
+
+    if new_len.saturating_sub(target_account.data_len()) > MAX_PERMITTED_DATA_INCREASE {
+        msg!("Account can grow by at most {} bytes per instruction, realloc in smaller steps", MAX_PERMITTED_DATA_INCREASE);
+        return Err(ProgramError::InvalidArgument);
+    }
+    msg!("Accounts verification success");
+
+
+    //top up rent for the larger account
+    let rent = rent::Rent::default();
+    let lamports_needed = rent.minimum_balance(new_len).saturating_sub(target_account.lamports());
+    if lamports_needed > 0 {
+        if !payer_account.is_signer {
+            msg!("Payer must sign to top up rent");
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        invoke(
+            &transfer(payer_account.key, target_account.key, lamports_needed),
+            &[
+                payer_account.clone(),
+                target_account.clone(),
+                system_program_account.clone()
+            ]
+        )?;
+        msg!("Topped up {} lamports of rent", lamports_needed);
+    }
+
+    target_account.resize(new_len)?;
+
+    let mut target_raw_data = target_account.data.borrow_mut();
+    match args.account {
+        MarketAccountKind::Bids | MarketAccountKind::Asks => {
+            let book = OrderBook::grow(&mut target_raw_data)?;
+            msg!("Order book now has room for {} orders", { book.header.capacity });
+        },
+        MarketAccountKind::Events => {
+            let events = MarketEventsAccount::grow(&mut target_raw_data)?;
+            msg!("Market events account now has room for {} events", { events.header.capacity });
+        }
+    }
+
+    Ok(())
+}
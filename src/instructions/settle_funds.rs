@@ -62,8 +62,8 @@ pub fn settle_funds(
     }    
     
     let mut user_market_raw_data = user_market_account.data.borrow_mut();
-    let mut reader = &user_market_raw_data[..];
-    let mut user_market_data = UserMarketAccount::try_from_slice(&mut reader)?;
+    let reader = &user_market_raw_data[..];
+    let mut user_market_data = UserMarketAccount::try_from_slice(reader)?;
 
     if user_market_data.owner != *owner_account.key {
         msg!("Invalid user market account, does not belongs to provided owner");
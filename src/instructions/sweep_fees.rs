@@ -0,0 +1,99 @@
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::{account_info::{next_account_info, AccountInfo}, entrypoint::ProgramResult, msg, program::invoke_signed, program_error::ProgramError, pubkey::Pubkey};
+use spl_token::instruction::transfer;
+
+use crate::state::MarketState;
+
+pub fn sweep_fees(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+) -> ProgramResult {
+    let mut iter = accounts.iter();
+
+    let authority_account = next_account_info(&mut iter)?;
+    let market_account = next_account_info(&mut iter)?;
+    let coin_mint_account = next_account_info(&mut iter)?;
+    let pc_mint_account = next_account_info(&mut iter)?;
+    let pc_vault_account = next_account_info(&mut iter)?;
+    let fee_destination_account = next_account_info(&mut iter)?;
+    let token_program_account = next_account_info(&mut iter)?;
+
+
+    //verify market account
+    let market_seeds = &[b"market", pc_mint_account.key.as_ref(), coin_mint_account.key.as_ref()];
+
+    let market_pda = Pubkey::find_program_address(
+        market_seeds,
+        program_id
+    ).0;
+
+    if *market_account.key != market_pda {
+        msg!("Invalid market account provided, expected: {}", market_pda);
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let mut market_data = MarketState::try_from_slice(&market_account.data.borrow())?;
+
+
+    //verify authority
+    if !authority_account.is_signer {
+        msg!("Market authority must sign to sweep fees");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    if *authority_account.key != market_data.authority {
+        msg!("Only the market authority can sweep fees, expected: {}", market_data.authority);
+        return Err(ProgramError::IllegalOwner);
+    }
+
+
+    //verify pc vault and fee destination accounts
+    if *pc_vault_account.key != market_data.pc_vault {
+        msg!("Invalid pc vault account provided, expected: {}", market_data.pc_vault);
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    if *fee_destination_account.key != market_data.fee_destination {
+        msg!("Invalid fee destination account provided, expected: {}", market_data.fee_destination);
+        return Err(ProgramError::InvalidAccountData);
+    }
+    msg!("Accounts verification success");
+
+
+    let fees_accrued = market_data.fees_accrued;
+    if fees_accrued == 0 {
+        msg!("No fees to sweep");
+        return Ok(());
+    }
+
+    let transfer_ix = transfer(
+        token_program_account.key,
+        pc_vault_account.key,
+        fee_destination_account.key,
+        market_account.key,
+        &[],
+        fees_accrued
+    )?;
+
+    invoke_signed(
+        &transfer_ix,
+        &[
+            pc_vault_account.clone(),
+            fee_destination_account.clone(),
+            market_account.clone(),
+            token_program_account.clone(),
+        ],
+        &[&[
+            b"market",
+            pc_mint_account.key.as_ref(),
+            coin_mint_account.key.as_ref(),
+            &[market_data.bump]
+        ]]
+    )?;
+    msg!("Swept {} pc of fees", fees_accrued);
+
+    market_data.fees_accrued = 0;
+    market_data.serialize(&mut *market_account.data.borrow_mut())?;
+
+    Ok(())
+}
@@ -1,7 +1,8 @@
 use solana_program::{entrypoint::ProgramResult, entrypoint, pubkey::Pubkey, account_info::AccountInfo};
 
 mod processor;
-mod state;
+pub mod state;
+pub mod error;
 mod instructions;
 
 entrypoint!(process_instruction);
@@ -1,16 +1,28 @@
 use borsh::{BorshDeserialize, BorshSerialize};
 use solana_program::{entrypoint::ProgramResult, account_info::AccountInfo, pubkey::Pubkey};
 
-use crate::{instructions::{cancel_order::cancel_order, consume_events::consume_events, create_order::create_order, initialize_market::initialize_market_instruction, settle_funds::settle_funds}, state::{CancelOrderArgs, ConsumeEventsArgs, CreateOrderArgs}};
+use crate::{instructions::{amend_order::amend_order, cancel_all_orders::cancel_all_orders, cancel_and_replace::cancel_and_replace, cancel_order::cancel_order, cancel_order_by_client_id::cancel_order_by_client_id, cancel_trigger_order::cancel_trigger_order, close_user_accounts::close_user_accounts, consume_events::consume_events, create_order::create_order, create_orders::create_orders, create_trigger_order::create_trigger_order, execute_trigger_orders::execute_trigger_orders, initialize_market::initialize_market_instruction, prune_expired_orders::prune_expired_orders, realloc_market_account::realloc_market_account, settle_funds::settle_funds, sweep_fees::sweep_fees}, state::{AmendOrderArgs, CancelAllOrdersArgs, CancelAndReplaceArgs, CancelOrderArgs, CancelOrderByClientIdArgs, CancelTriggerOrderArgs, ConsumeEventsArgs, CreateOrderArgs, CreateTriggerOrderArgs, ExecuteTriggerOrdersArgs, InitializeMarketArgs, PruneExpiredOrdersArgs, ReallocMarketAccountArgs}};
 
 
 #[derive(BorshSerialize, BorshDeserialize)]
 pub enum OrderBookInstruction {
-    InitializeMarket,
+    InitializeMarket(InitializeMarketArgs),
     CreateOrder(CreateOrderArgs),
     ConsumeEvents(ConsumeEventsArgs),
     SettleFunds,
-    CancelOrder(CancelOrderArgs)
+    CancelOrder(CancelOrderArgs),
+    CancelOrderByClientId(CancelOrderByClientIdArgs),
+    PruneExpiredOrders(PruneExpiredOrdersArgs),
+    AmendOrder(AmendOrderArgs),
+    CancelAllOrders(CancelAllOrdersArgs),
+    CancelAndReplace(CancelAndReplaceArgs),
+    CreateOrders(Vec<CreateOrderArgs>),
+    CreateTriggerOrder(CreateTriggerOrderArgs),
+    CancelTriggerOrder(CancelTriggerOrderArgs),
+    ExecuteTriggerOrders(ExecuteTriggerOrdersArgs),
+    SweepFees,
+    ReallocMarketAccount(ReallocMarketAccountArgs),
+    CloseUserAccounts
 }
 
 pub fn process(
@@ -21,11 +33,23 @@ pub fn process(
     
     let instruction = OrderBookInstruction::try_from_slice(instruction_data)?;
     match instruction{
-        OrderBookInstruction::InitializeMarket => initialize_market_instruction(program_id, accounts)?,
+        OrderBookInstruction::InitializeMarket(data) => initialize_market_instruction(program_id, accounts, data)?,
         OrderBookInstruction::CreateOrder(data) => create_order(program_id, accounts, data)?,
         OrderBookInstruction::ConsumeEvents(data) => consume_events(program_id, accounts, data)?,
         OrderBookInstruction::SettleFunds => settle_funds(program_id, accounts)?,
-        OrderBookInstruction::CancelOrder(data) => cancel_order(program_id, accounts, data)?
+        OrderBookInstruction::CancelOrder(data) => cancel_order(program_id, accounts, data)?,
+        OrderBookInstruction::CancelOrderByClientId(data) => cancel_order_by_client_id(program_id, accounts, data)?,
+        OrderBookInstruction::PruneExpiredOrders(data) => prune_expired_orders(program_id, accounts, data)?,
+        OrderBookInstruction::AmendOrder(data) => amend_order(program_id, accounts, data)?,
+        OrderBookInstruction::CancelAllOrders(data) => cancel_all_orders(program_id, accounts, data)?,
+        OrderBookInstruction::CancelAndReplace(data) => cancel_and_replace(program_id, accounts, data)?,
+        OrderBookInstruction::CreateOrders(data) => create_orders(program_id, accounts, data)?,
+        OrderBookInstruction::CreateTriggerOrder(data) => create_trigger_order(program_id, accounts, data)?,
+        OrderBookInstruction::CancelTriggerOrder(data) => cancel_trigger_order(program_id, accounts, data)?,
+        OrderBookInstruction::ExecuteTriggerOrders(data) => execute_trigger_orders(program_id, accounts, data)?,
+        OrderBookInstruction::SweepFees => sweep_fees(program_id, accounts)?,
+        OrderBookInstruction::ReallocMarketAccount(data) => realloc_market_account(program_id, accounts, data)?,
+        OrderBookInstruction::CloseUserAccounts => close_user_accounts(program_id, accounts)?
     };
     Ok(())   
 }
\ No newline at end of file
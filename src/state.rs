@@ -2,8 +2,12 @@ use borsh::{BorshDeserialize, BorshSerialize};
 use solana_program::{entrypoint::ProgramResult, msg, program_error::ProgramError, pubkey::Pubkey};
 use bytemuck::{Pod, Zeroable};
 
+use crate::error::OrderBookError;
 
-pub const MAX_EVENT: u16 = 512; 
+
+pub const MAX_TRIGGER_ORDERS: u16 = 256;
+pub const MAX_OPEN_ORDERS: usize = 64;    //resting orders one user can have on a market
+pub const MAX_ORACLE_STALENESS: i64 = 60;   //seconds after which an oracle price is no longer used
 
 #[derive(BorshSerialize, BorshDeserialize)]
 pub struct MarketState {
@@ -13,12 +17,103 @@ pub struct MarketState {
     pub pc_mint: Pubkey,
     pub bids: Pubkey,
     pub asks: Pubkey,
+    pub trigger_orders: Pubkey,
+    pub oracle: Pubkey,             //price account pegged orders follow
+    pub authority: Pubkey,          //may sweep accrued fees
+    pub fee_destination: Pubkey,    //pc token account swept fees are sent to
     pub next_order_id: u64,
+    pub last_traded_price: u64,     //price of the most recent fill, 0 before the first trade
+    pub tick_size: u64,             //book prices must be a multiple of this, in quote lots per base lot
+    pub base_lot_size: u64,         //native coin amount of one base lot
+    pub quote_lot_size: u64,        //native pc amount of one quote lot
+    pub min_order_size: u64,        //smallest order quantity accepted, in base lots
+    pub maker_fee_bps: i16,         //negative values pay makers a rebate
+    pub taker_fee_bps: u16,
+    pub referrer_fee_share_bps: u16,    //part of the taker fee credited to the taker's referrer
+    pub fees_accrued: u64,          //native pc held in the pc vault for the market, not owed to users
     pub bump: u8
 }
 
 impl MarketState {
-    pub const LEN: usize = 6 * 32 + 8 + 1;
+    pub const LEN: usize = 10 * 32 + 8 + 8 + (4 * 8) + 2 + 2 + 2 + 8 + 1;
+
+    //native coin amount of `qty` base lots
+    pub fn coin_native(&self, qty: u64) -> Result<u64, ProgramError> {
+        Ok(qty.checked_mul(self.base_lot_size).ok_or(OrderBookError::ArithmeticOverflow)?)
+    }
+
+    //native pc amount of `qty` base lots at `price` quote lots per base lot
+    pub fn pc_native(&self, price: u64, qty: u64) -> Result<u64, ProgramError> {
+        let pc_qty = (price as u128).checked_mul(qty as u128)
+            .and_then(|pc_qty| pc_qty.checked_mul(self.quote_lot_size as u128))
+            .and_then(|pc_qty| u64::try_from(pc_qty).ok());
+        Ok(pc_qty.ok_or(OrderBookError::ArithmeticOverflow)?)
+    }
+
+    //native pc a bid locks for `qty` base lots resting at `price`, including the maker fee it may owe
+    pub fn bid_lock(&self, price: u64, qty: u64) -> Result<u64, ProgramError> {
+        let pc_qty = self.pc_native(price, qty)?;
+        let maker_fee = self.fill_fees(pc_qty)?.1.max(0) as u64;
+        Ok(pc_qty.checked_add(maker_fee).ok_or(OrderBookError::ArithmeticOverflow)?)
+    }
+
+    //native pc a bid resting at `price` locks for base lots `from..to` of its size, the fee is rounded on the running size
+    //so the pc its fills and cancels release adds up to exactly what the order locked
+    pub fn bid_lock_between(&self, price: u64, from: u64, to: u64) -> Result<u64, ProgramError> {
+        let locked_to = self.bid_lock(price, to)?;
+        Ok(locked_to.checked_sub(self.bid_lock(price, from)?).ok_or(OrderBookError::ArithmeticOverflow)?)
+    }
+
+    //native pc a bid for `qty` base lots up to `price` needs at most, whether it fills as a taker or rests
+    //fees round up on every fill and a fill takes at least one lot, so the fee is bounded per lot
+    pub fn bid_max_lock(&self, price: u64, qty: u64) -> Result<u64, ProgramError> {
+        let pc_qty = self.pc_native(price, qty)?;
+        let fee_bps = self.taker_fee_bps.max(self.maker_fee_bps.max(0) as u16);
+        let lot_fee = u64::try_from((self.pc_native(price, 1)? as u128 * fee_bps as u128).div_ceil(10_000))
+            .map_err(|_| OrderBookError::ArithmeticOverflow)?;
+        let fee = lot_fee.checked_mul(qty).ok_or(OrderBookError::ArithmeticOverflow)?;
+        Ok(pc_qty.checked_add(fee).ok_or(OrderBookError::ArithmeticOverflow)?)
+    }
+
+    //taker fee and signed maker fee on a fill of `pc_qty` native pc, taker fees round up, maker fees and rebates round down
+    //so that the maker fees of partial fills never add up to more than a resting bid locked for them
+    pub fn fill_fees(&self, pc_qty: u64) -> Result<(u64, i64), ProgramError> {
+        let taker_fee = u64::try_from((pc_qty as u128 * self.taker_fee_bps as u128).div_ceil(10_000))
+            .map_err(|_| OrderBookError::ArithmeticOverflow)?;
+        let maker_fee = i64::try_from(pc_qty as u128 * self.maker_fee_bps.unsigned_abs() as u128 / 10_000)
+            .map_err(|_| OrderBookError::ArithmeticOverflow)?;
+        if self.maker_fee_bps >= 0 {
+            Ok((taker_fee, maker_fee))
+        } else {
+            Ok((taker_fee, -maker_fee))
+        }
+    }
+
+    //referrer's share of a taker fee, rounded down
+    pub fn referrer_fee(&self, taker_fee: u64) -> Result<u64, ProgramError> {
+        Ok(u64::try_from(taker_fee as u128 * self.referrer_fee_share_bps as u128 / 10_000).map_err(|_| OrderBookError::ArithmeticOverflow)?)
+    }
+}
+
+
+//layout of the oracle price account, kept up to date by an external oracle program
+#[repr(C, packed)]
+#[derive(Copy, Clone, Zeroable, Pod)]
+pub struct OraclePrice {
+    pub price: u64,
+    pub last_update_timestamp: i64
+}
+
+impl OraclePrice {
+    pub const LEN: usize = 8 + 8;
+
+    //none while the oracle has no price or has not updated recently
+    pub fn current_price(&self, now: i64) -> Option<u64> {
+        if self.price == 0 || now.saturating_sub(self.last_update_timestamp) > MAX_ORACLE_STALENESS {
+            return None;
+        }
+        Some(self.price)
+    }
 }
 
 
@@ -33,6 +128,53 @@ pub enum Side {
 unsafe impl Zeroable for Side {}
 unsafe impl Pod for Side {}
 
+impl Side {
+    //whether an order on this side at limit_price trades with a resting order at maker_price
+    pub fn is_crossed(&self, limit_price: u64, maker_price: u64) -> bool {
+        match self {
+            Side::Bid => limit_price >= maker_price,
+            Side::Ask => limit_price <= maker_price
+        }
+    }
+}
+
+
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Debug, BorshSerialize, BorshDeserialize)]
+#[borsh(use_discriminant=true)]
+pub enum OrderType {
+    Limit = 0,              //match what crosses, rest the remainder
+    ImmediateOrCancel = 1,  //match what crosses, drop the remainder
+    FillOrKill = 2,         //fail unless the whole quantity matches
+    PostOnly = 3,           //fail if any part would cross the book
+    Market = 4              //ignore limit price, drop the remainder, bounded by pc_qty_limit
+}
+
+unsafe impl Zeroable for OrderType {}
+unsafe impl Pod for OrderType {}
+
+
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Debug, BorshSerialize, BorshDeserialize)]
+#[borsh(use_discriminant=true)]
+pub enum SelfTradeBehavior {
+    CancelMaker = 0,          //cancel the resting order and keep matching
+    CancelTaker = 1,          //stop matching and drop the taker's remainder
+    DecrementAndCancel = 2,   //reduce both sides by the smaller size without trading
+    AbortTransaction = 3      //fail the whole instruction
+}
+
+unsafe impl Zeroable for SelfTradeBehavior {}
+unsafe impl Pod for SelfTradeBehavior {}
+
+#[repr(u8)]
+#[derive(Clone, Copy, PartialEq, Debug, BorshSerialize, BorshDeserialize)]
+#[borsh(use_discriminant=true)]
+pub enum MatchLimitBehavior {
+    FailOrder = 0,        //the instruction fails, a remainder cut short still crosses the book so it can never rest
+    DropRemainder = 1     //the remainder is released like an immediate or cancel order
+}
+
 #[repr(C, packed)]
 #[derive(Copy, Clone, Zeroable, Pod)]
 pub struct Order {
@@ -42,51 +184,726 @@ pub struct Order {
     pub price: u64,
     pub quantity: u64,
     pub filled_quantity: u64,
-    pub side: Side
+    pub side: Side,
+    pub client_order_id: u64,
+    pub expiry_timestamp: i64,    //unix timestamp after which the order is dead, 0 if it never expires
+    pub display_quantity: u64,    //iceberg orders only: size of each visible slice, 0 if fully visible
+    pub reserve_quantity: u64,    //iceberg orders only: hidden quantity not yet shown in the book
+    pub pegged: u8,               //1 if the price follows the oracle, `price` is then the peg limit
+    pub peg_offset: i64           //pegged orders only: offset added to the oracle price
 }
 
+impl Order {
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.expiry_timestamp != 0 && self.expiry_timestamp <= now
+    }
+
+    //unfilled quantity of the order, visible and hidden
+    pub fn remaining_quantity(&self) -> Result<u64, ProgramError> {
+        let remaining_qty = self.quantity.checked_sub(self.filled_quantity)
+            .and_then(|visible_qty| visible_qty.checked_add(self.reserve_quantity));
+        Ok(remaining_qty.ok_or(OrderBookError::ArithmeticOverflow)?)
+    }
+
+    //size of the order, filled or not, its unfilled part runs from `filled_quantity` up to it
+    pub fn total_quantity(&self) -> Result<u64, ProgramError> {
+        Ok(self.quantity.checked_add(self.reserve_quantity).ok_or(OrderBookError::ArithmeticOverflow)?)
+    }
+
+    //takes `qty` out of the unfilled quantity, visible part first then the reserve
+    pub fn reduce_quantity(&mut self, qty: u64) -> ProgramResult {
+        let visible_qty = qty.min(self.quantity.checked_sub(self.filled_quantity).ok_or(OrderBookError::ArithmeticOverflow)?);
+        self.quantity -= visible_qty;
+        self.reserve_quantity = self.reserve_quantity.checked_sub(qty - visible_qty).ok_or(OrderBookError::ArithmeticOverflow)?;
+        Ok(())
+    }
+
+    //shows the next slice of an iceberg order once its visible part is fully filled
+    pub fn refill(&mut self) -> Result<bool, ProgramError> {
+        if self.quantity != self.filled_quantity || self.reserve_quantity == 0 {
+            return Ok(false);
+        }
+        let refill_qty = self.display_quantity.min(self.reserve_quantity);
+        self.reserve_quantity -= refill_qty;
+        self.quantity = self.quantity.checked_add(refill_qty).ok_or(OrderBookError::ArithmeticOverflow)?;
+        Ok(true)
+    }
+
+    pub fn is_pegged(&self) -> bool {
+        self.pegged == 1
+    }
+
+    //price the order trades at right now, none for a pegged order without a usable oracle price or past its peg limit
+    pub fn effective_price(&self, oracle_price: Option<u64>) -> Option<u64> {
+        if !self.is_pegged() {
+            return Some(self.price);
+        }
+
+        let price = pegged_price(oracle_price?, self.peg_offset);
+        let within_limit = match self.side {
+            Side::Bid => price <= self.price,
+            Side::Ask => price >= self.price
+        };
+        if within_limit { Some(price) } else { None }
+    }
+}
+
+//oracle price moved by a peg offset, never below 1
+pub fn pegged_price(oracle_price: u64, peg_offset: i64) -> u64 {
+    (oracle_price as i128 + peg_offset as i128).clamp(1, u64::MAX as i128) as u64
+}
+
+
+//a book node handle with this bit set points at a slot's leaf, otherwise at its inner node
+const LEAF_TAG: u32 = 1 << 31;
+//empty tree or end of a free list
+const NIL: u32 = u32::MAX;
+//prefixes grow along every path, so a tree is at most 128 inner nodes deep and a walk never holds more than this many nodes
+const WALK_STACK_LEN: usize = 129;
+
+#[repr(C, packed)]
+#[derive(Copy, Clone, Zeroable, Pod)]
+pub struct LeafNode {
+    pub key: u128,      //price level in the high half and time priority in the low half, next free leaf while unused
+    pub order: Order
+}
 
 #[repr(C, packed)]
 #[derive(Copy, Clone, Zeroable, Pod)]
-pub struct OrderBook {
+pub struct InnerNode {
+    pub prefix_len: u32,      //leading key bits shared by every leaf below, the next bit picks the child
+    pub key: u128,            //key of a leaf below
+    pub children: [u32; 2],   //lower keys left, higher keys right, children[0] is the next free node while unused
+    pub peg_reach: i128       //pegged tree only: the best peg reach of the orders below, highest for bids and lowest for asks
+}
+
+//trees of n leaves never need more than n inner nodes, so every slot has room for one of each
+//in the fixed or pegged tree and one in the order id tree
+#[repr(C, packed)]
+#[derive(Copy, Clone, Zeroable, Pod)]
+pub struct BookSlot {
+    pub leaf: LeafNode,
+    pub inner_node: InnerNode,
+    pub order_id_node: InnerNode
+}
+
+//the trees sharing a book's slab, fixed and pegged orders are keyed by priority and every order is also keyed by its id
+#[derive(Clone, Copy, PartialEq)]
+enum BookTree {
+    Fixed,
+    Pegged,
+    OrderIds
+}
+
+//a pegged order has a price while the oracle price is at most its reach for a bid and at least its reach for an ask,
+//pegged prices never go below 1 so an ask limited at 1 always has one
+fn peg_reach(side: Side, order: &Order) -> i128 {
+    match side {
+        Side::Ask if order.price <= 1 => i128::MIN,
+        _ => order.price as i128 - order.peg_offset as i128
+    }
+}
+
+fn best_peg_reach(side: Side, reach: i128, other_reach: i128) -> i128 {
+    match side {
+        Side::Bid => reach.max(other_reach),
+        Side::Ask => reach.min(other_reach)
+    }
+}
+
+fn is_within_peg_reach(side: Side, reach: i128, oracle_price: u64) -> bool {
+    match side {
+        Side::Bid => oracle_price as i128 <= reach,
+        Side::Ask => oracle_price as i128 >= reach
+    }
+}
+
+
+//start of a bids or asks account, followed by `capacity` slots
+#[repr(C, packed)]
+#[derive(Copy, Clone, Zeroable, Pod)]
+pub struct OrderBookHeader {
     pub side: Side,
     pub market: Pubkey,
     pub next_order_id: u64,
-    pub orders: [Order; 1024],
-    pub slots_filled: u16
+    pub next_seq_num: u64,    //time priority given to the next order joining the book
+    pub fixed_root: u32,
+    pub pegged_root: u32,
+    pub order_id_root: u32,
+    pub free_leaf: u32,
+    pub free_inner_node: u32,
+    pub free_order_id_node: u32,
+    pub slots_filled: u32,
+    pub capacity: u32         //resting orders the account has room for, derived from its size
+}
+
+//critbit trees sharing one slab of nodes, one for fixed price orders and one for pegged orders, plus one finding any
+//order by its id, leaf indexes stay valid until that order is removed
+pub struct OrderBook<'a> {
+    pub header: &'a mut OrderBookHeader,
+    pub slots: &'a mut [BookSlot]
 }
 
-impl OrderBook {
-    pub const LEN: usize = 1 + 32 + 8 + (97 * 1024) + 2;  //99371 bytes
+impl<'a> OrderBook<'a> {
+    pub const HEADER_LEN: usize = 1 + 32 + 8 + 8 + 32;   //81 bytes
+    pub const SLOT_LEN: usize = 154 + 44 + 44;          //242 bytes
+
+    //account size needed for a book of `capacity` orders
+    pub fn account_len(capacity: u32) -> usize {
+        Self::HEADER_LEN + Self::SLOT_LEN * capacity as usize
+    }
+
+    //views the data of an initialized bids or asks account
+    pub fn load(data: &'a mut [u8]) -> Result<Self, ProgramError> {
+        if data.len() < Self::HEADER_LEN {
+            msg!("Order book account is too small");
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let (header_data, slots_data) = data.split_at_mut(Self::HEADER_LEN);
+        let header: &mut OrderBookHeader = bytemuck::from_bytes_mut(header_data);
+
+        let slots_len = Self::SLOT_LEN * header.capacity as usize;
+        if slots_data.len() < slots_len {
+            msg!("Order book account is smaller than its capacity of {} orders", { header.capacity });
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let slots: &mut [BookSlot] = bytemuck::cast_slice_mut(&mut slots_data[..slots_len]);
+        Ok(Self { header, slots })
+    }
+
+    //sizes a new book by its account data length and empties it
+    pub fn init(data: &'a mut [u8], side: Side, market: Pubkey) -> Result<Self, ProgramError> {
+        let capacity = data.len().saturating_sub(Self::HEADER_LEN) / Self::SLOT_LEN;
+        if capacity == 0 || capacity >= LEAF_TAG as usize {
+            msg!("Order book account of {} bytes can not hold a valid number of orders", data.len());
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let header: &mut OrderBookHeader = bytemuck::from_bytes_mut(&mut data[..Self::HEADER_LEN]);
+        header.side = side;
+        header.market = market;
+        header.next_order_id = 0;
+        header.capacity = capacity as u32;
+
+        let mut book = Self::load(data)?;
+        book.init_nodes();
+        Ok(book)
+    }
+
+    //takes in the slots added to a resized book, chaining them onto the front of the free lists
+    pub fn grow(data: &'a mut [u8]) -> Result<Self, ProgramError> {
+        let capacity = data.len().saturating_sub(Self::HEADER_LEN) / Self::SLOT_LEN;
+        let header: &mut OrderBookHeader = bytemuck::from_bytes_mut(&mut data[..Self::HEADER_LEN]);
+        let old_capacity = header.capacity;
+        if capacity <= old_capacity as usize || capacity >= LEAF_TAG as usize {
+            msg!("Order book account of {} bytes can not grow past its capacity of {} orders", data.len(), old_capacity);
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let (free_leaf, free_inner_node, free_order_id_node) = (header.free_leaf, header.free_inner_node, header.free_order_id_node);
+        header.capacity = capacity as u32;
+
+        let book = Self::load(data)?;
+        let capacity = capacity as u32;
+        for i in old_capacity..capacity {
+            let next = |free: u32| if i + 1 < capacity { i + 1 } else { free };
+            book.slots[i as usize] = BookSlot {
+                leaf: LeafNode { key: next(free_leaf) as u128, order: Order::zeroed() },
+                inner_node: InnerNode { prefix_len: 0, key: 0, children: [next(free_inner_node), NIL], peg_reach: 0 },
+                order_id_node: InnerNode { prefix_len: 0, key: 0, children: [next(free_order_id_node), NIL], peg_reach: 0 }
+            };
+        }
+        book.header.free_leaf = old_capacity;
+        book.header.free_inner_node = old_capacity;
+        book.header.free_order_id_node = old_capacity;
+        Ok(book)
+    }
+
+    //empties every tree and chains every node into the free lists
+    fn init_nodes(&mut self) {
+        let capacity = self.header.capacity;
+        self.header.next_seq_num = 0;
+        self.header.fixed_root = NIL;
+        self.header.pegged_root = NIL;
+        self.header.order_id_root = NIL;
+        for i in 0..capacity {
+            let next = if i + 1 < capacity { i + 1 } else { NIL };
+            let free_node = InnerNode { prefix_len: 0, key: 0, children: [next, NIL], peg_reach: 0 };
+            self.slots[i as usize] = BookSlot {
+                leaf: LeafNode { key: next as u128, order: Order::zeroed() },
+                inner_node: free_node,
+                order_id_node: free_node
+            };
+        }
+        self.header.free_leaf = 0;
+        self.header.free_inner_node = 0;
+        self.header.free_order_id_node = 0;
+        self.header.slots_filled = 0;
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.header.slots_filled >= self.header.capacity
+    }
+
+    pub fn order(&self, index: usize) -> &Order {
+        &self.slots[index].leaf.order
+    }
+
+    pub fn order_mut(&mut self, index: usize) -> &mut Order {
+        &mut self.slots[index].leaf.order
+    }
+
+    //fixed orders are keyed by price and pegged orders by peg offset, ties go to the earlier sequence number,
+    //bids walk their keys from the highest down so their sequence number is inverted
+    fn priority_key(&self, order: &Order, seq_num: u64) -> u128 {
+        let level = if order.is_pegged() { (order.peg_offset as u64) ^ (1 << 63) } else { order.price };
+        let time = match self.header.side {
+            Side::Bid => !seq_num,
+            Side::Ask => seq_num
+        };
+        ((level as u128) << 64) | time as u128
+    }
+
+    fn priority_tree(order: &Order) -> BookTree {
+        if order.is_pegged() { BookTree::Pegged } else { BookTree::Fixed }
+    }
+
+    fn root(&self, tree: BookTree) -> u32 {
+        match tree {
+            BookTree::Fixed => self.header.fixed_root,
+            BookTree::Pegged => self.header.pegged_root,
+            BookTree::OrderIds => self.header.order_id_root
+        }
+    }
+
+    fn set_root(&mut self, tree: BookTree, node: u32) {
+        match tree {
+            BookTree::Fixed => self.header.fixed_root = node,
+            BookTree::Pegged => self.header.pegged_root = node,
+            BookTree::OrderIds => self.header.order_id_root = node
+        }
+    }
+
+    fn inner_node(&self, tree: BookTree, node: u32) -> &InnerNode {
+        match tree {
+            BookTree::OrderIds => &self.slots[node as usize].order_id_node,
+            _ => &self.slots[node as usize].inner_node
+        }
+    }
+
+    fn inner_node_mut(&mut self, tree: BookTree, node: u32) -> &mut InnerNode {
+        match tree {
+            BookTree::OrderIds => &mut self.slots[node as usize].order_id_node,
+            _ => &mut self.slots[node as usize].inner_node
+        }
+    }
+
+    //head of the tree's free list of inner nodes, the fixed and pegged trees share theirs
+    fn free_list(&self, tree: BookTree) -> u32 {
+        match tree {
+            BookTree::OrderIds => self.header.free_order_id_node,
+            _ => self.header.free_inner_node
+        }
+    }
+
+    fn set_free_list(&mut self, tree: BookTree, node: u32) {
+        match tree {
+            BookTree::OrderIds => self.header.free_order_id_node = node,
+            _ => self.header.free_inner_node = node
+        }
+    }
+
+    fn alloc_inner_node(&mut self, tree: BookTree) -> u32 {
+        let node = self.free_list(tree);
+        self.set_free_list(tree, self.inner_node(tree, node).children[0]);
+        node
+    }
+
+    fn free_inner_node(&mut self, tree: BookTree, node: u32) {
+        self.inner_node_mut(tree, node).children[0] = self.free_list(tree);
+        self.set_free_list(tree, node);
+    }
+
+    fn leaf_key(&self, tree: BookTree, leaf_index: u32) -> u128 {
+        let leaf = &self.slots[leaf_index as usize].leaf;
+        match tree {
+            BookTree::OrderIds => leaf.order.order_id as u128,
+            _ => leaf.key
+        }
+    }
+
+    //pegged tree only: best peg reach of the orders below a node
+    fn node_peg_reach(&self, node: u32) -> i128 {
+        if node & LEAF_TAG != 0 {
+            peg_reach(self.header.side, self.order((node & !LEAF_TAG) as usize))
+        } else {
+            self.slots[node as usize].inner_node.peg_reach
+        }
+    }
+
+    //bit of `key` right after the first `prefix_len` bits
+    fn crit_bit(key: u128, prefix_len: u32) -> usize {
+        ((key >> (127 - prefix_len)) & 1) as usize
+    }
+
+    //links a used leaf into a tree, keyed by its key in that tree
+    fn insert_leaf(&mut self, tree: BookTree, leaf_index: u32) -> ProgramResult {
+        let key = self.leaf_key(tree, leaf_index);
+        let leaf = leaf_index | LEAF_TAG;
+        let leaf_peg_reach = if tree == BookTree::Pegged { self.node_peg_reach(leaf) } else { 0 };
+        let mut node = self.root(tree);
+        if node == NIL {
+            self.set_root(tree, leaf);
+            return Ok(());
+        }
+
+        //walk down while the key matches the node's prefix, then split off above the first node it differs from
+        let mut parent: Option<(u32, usize)> = None;
+        loop {
+            let (node_key, node_prefix_len) = if node & LEAF_TAG != 0 {
+                (self.leaf_key(tree, node & !LEAF_TAG), 128)
+            } else {
+                let inner_node = self.inner_node(tree, node);
+                (inner_node.key, inner_node.prefix_len)
+            };
+
+            let shared_len = (node_key ^ key).leading_zeros();
+            if shared_len == 128 {
+                msg!("Order book already holds an order with key {}", key);
+                return Err(ProgramError::InvalidAccountData);
+            }
+            if shared_len < node_prefix_len {
+                let mut children = [node, node];
+                children[Self::crit_bit(key, shared_len)] = leaf;
+                let peg_reach = if tree == BookTree::Pegged {
+                    best_peg_reach(self.header.side, self.node_peg_reach(node), leaf_peg_reach)
+                } else {
+                    0
+                };
+
+                let inner_index = self.alloc_inner_node(tree);
+                *self.inner_node_mut(tree, inner_index) = InnerNode { prefix_len: shared_len, key, children, peg_reach };
+
+                match parent {
+                    Some((parent_index, dir)) => self.inner_node_mut(tree, parent_index).children[dir] = inner_index,
+                    None => self.set_root(tree, inner_index)
+                }
+                return Ok(());
+            }
+
+            //the leaf ends up below this node
+            if tree == BookTree::Pegged {
+                let side = self.header.side;
+                let inner_node = self.inner_node_mut(tree, node);
+                inner_node.peg_reach = best_peg_reach(side, inner_node.peg_reach, leaf_peg_reach);
+            }
+            let dir = Self::crit_bit(key, node_prefix_len);
+            parent = Some((node, dir));
+            node = self.inner_node(tree, node).children[dir];
+        }
+    }
+
+    //unlinks a leaf from a tree, its sibling takes its parent's place
+    fn detach_leaf(&mut self, tree: BookTree, leaf_index: u32) -> ProgramResult {
+        let key = self.leaf_key(tree, leaf_index);
+        let leaf = leaf_index | LEAF_TAG;
+        let mut node = self.root(tree);
+        if node == leaf {
+            self.set_root(tree, NIL);
+            return Ok(());
+        }
+
+        let mut ancestors = [NIL; WALK_STACK_LEN];
+        let mut ancestors_len = 0;
+        loop {
+            if node == NIL || node & LEAF_TAG != 0 {
+                msg!("Order is not in the book");
+                return Err(OrderBookError::OrderNotFound.into());
+            }
+
+            let inner_node = *self.inner_node(tree, node);
+            let dir = Self::crit_bit(key, inner_node.prefix_len);
+            if inner_node.children[dir] != leaf {
+                ancestors[ancestors_len] = node;
+                ancestors_len += 1;
+                node = inner_node.children[dir];
+                continue;
+            }
+
+            let sibling = inner_node.children[1 - dir];
+            match ancestors_len.checked_sub(1).map(|i| ancestors[i]) {
+                Some(grandparent) => {
+                    let grandparent_dir = Self::crit_bit(key, self.inner_node(tree, grandparent).prefix_len);
+                    self.inner_node_mut(tree, grandparent).children[grandparent_dir] = sibling;
+                }
+                None => self.set_root(tree, sibling)
+            }
+            self.free_inner_node(tree, node);
 
-    pub fn add_order(&mut self, order: Order) -> ProgramResult {
-        if self.slots_filled >= 1024 {
-             if self.side == Side::Bid {
+            //the subtrees the leaf left take their peg reach from what remains, from the bottom up
+            if tree == BookTree::Pegged {
+                for &ancestor in ancestors[..ancestors_len].iter().rev() {
+                    let children = self.slots[ancestor as usize].inner_node.children;
+                    let peg_reach = best_peg_reach(self.header.side, self.node_peg_reach(children[0]), self.node_peg_reach(children[1]));
+                    self.slots[ancestor as usize].inner_node.peg_reach = peg_reach;
+                }
+            }
+            return Ok(());
+        }
+    }
+
+    //leaf in a tree with exactly this key
+    fn find_leaf(&self, tree: BookTree, key: u128) -> Option<usize> {
+        let mut node = self.root(tree);
+        if node == NIL {
+            return None;
+        }
+        while node & LEAF_TAG == 0 {
+            let inner_node = self.inner_node(tree, node);
+            node = inner_node.children[Self::crit_bit(key, inner_node.prefix_len)];
+        }
+        let leaf_index = node & !LEAF_TAG;
+        (self.leaf_key(tree, leaf_index) == key).then_some(leaf_index as usize)
+    }
+
+    //adds the order behind the others at its level, returns its leaf index
+    pub fn add_order(&mut self, order: Order) -> Result<usize, ProgramError> {
+        if self.is_full() {
+             if self.header.side == Side::Bid {
                 msg!("Bids is full right now");  
             } else {
                 msg!("Asks is full right now");
             }
-            return Err(ProgramError::Custom(1));
+            return Err(OrderBookError::BookFull.into());
         }
 
-        if self.slots_filled == 0 {
-            self.orders[0] = order;
-            self.slots_filled += 1;
-            return Ok(());
+        let key = self.priority_key(&order, self.header.next_seq_num);
+        self.header.next_seq_num += 1;
+
+        let leaf_index = self.header.free_leaf;
+        self.header.free_leaf = self.slots[leaf_index as usize].leaf.key as u32;
+        self.slots[leaf_index as usize].leaf = LeafNode { key, order };
+        self.header.slots_filled += 1;
+
+        self.insert_leaf(Self::priority_tree(&order), leaf_index)?;
+        self.insert_leaf(BookTree::OrderIds, leaf_index)?;
+        Ok(leaf_index as usize)
+    }
+
+    //moves the order at `index` behind the other orders at its level, it keeps its index
+    pub fn move_to_back(&mut self, index: usize) -> Result<usize, ProgramError> {
+        let order = *self.order(index);
+        let tree = Self::priority_tree(&order);
+        self.detach_leaf(tree, index as u32)?;
+        self.slots[index].leaf.key = self.priority_key(&order, self.header.next_seq_num);
+        self.header.next_seq_num += 1;
+        self.insert_leaf(tree, index as u32)?;
+        Ok(index)
+    }
+
+    //leaf indexes of one tree in the order a taker meets them
+    pub fn orders_by_priority(&self, pegged: bool) -> OrderBookIter<'_> {
+        let root = if pegged { self.header.pegged_root } else { self.header.fixed_root };
+        let mut stack = [NIL; WALK_STACK_LEN];
+        stack[0] = root;
+        OrderBookIter {
+            side: self.header.side,
+            slots: self.slots,
+            stack,
+            stack_len: (root != NIL) as usize,
+            oracle_price: None
+        }
+    }
+
+    //leaf indexes of the pegged orders that have a price at `oracle_price`, in the order a taker meets them,
+    //subtrees whose orders are all past their peg limit are not walked
+    pub fn pegged_orders_priced_at(&self, oracle_price: u64) -> OrderBookIter<'_> {
+        OrderBookIter {
+            oracle_price: Some(oracle_price),
+            ..self.orders_by_priority(true)
         }
+    }
 
-        let order_slice = &self.orders[0..(self.slots_filled as usize)];
+    //leaf indexes of every resting order, fixed orders first
+    pub fn order_indexes(&self) -> impl Iterator<Item = usize> + '_ {
+        self.orders_by_priority(false).chain(self.orders_by_priority(true))
+    }
 
-        let index = match self.side {
-            Side::Bid => {
-                order_slice.partition_point(|x| x.price >= order.price)
+    //best order a taker would match next and its effective price, merging fixed and pegged orders,
+    //fixed orders win ties; `skip` filters out orders that must not be considered
+    //each tree is walked from its best end, so this stops at the first order that is not skipped and has a price
+    pub fn best_order<F: Fn(&Order) -> bool>(&self, oracle_price: Option<u64>, skip: F) -> Option<(usize, u64)> {
+        let best = |orders: OrderBookIter| {
+            orders
+                .filter(|&i| !skip(self.order(i)))
+                .find_map(|i| self.order(i).effective_price(oracle_price).map(|price| (i, price)))
+        };
+
+        //pegged orders have no price without a usable oracle price, their tree is not walked at all
+        let best_pegged = oracle_price.and_then(|oracle_price| best(self.pegged_orders_priced_at(oracle_price)));
+        match (best(self.orders_by_priority(false)), best_pegged) {
+            (Some(fixed), Some(pegged)) => {
+                let pegged_is_better = match self.header.side {
+                    Side::Bid => pegged.1 > fixed.1,
+                    Side::Ask => pegged.1 < fixed.1
+                };
+                if pegged_is_better { Some(pegged) } else { Some(fixed) }
             },
-            Side::Ask => {
-                order_slice.partition_point(|x| x.price <= order.price)
-            }
+            (fixed, pegged) => fixed.or(pegged)
+        }
+    }
+
+    pub fn remove_order(&mut self, index: usize) -> ProgramResult {
+        let tree = Self::priority_tree(self.order(index));
+        self.detach_leaf(tree, index as u32)?;
+        self.detach_leaf(BookTree::OrderIds, index as u32)?;
+
+        self.slots[index].leaf = LeafNode { key: self.header.free_leaf as u128, order: Order::zeroed() };
+        self.header.free_leaf = index as u32;
+        self.header.slots_filled -= 1;
+        Ok(())
+    }
+
+    pub fn safely_remove_order_by_order_id(&mut self, order_id: u64, owner: Pubkey) -> Result<Order, ProgramError> {
+        let Some(index) = self.find_order_index(order_id) else {
+            msg!("Order Id is not present");
+            return Err(OrderBookError::OrderNotFound.into());
         };
 
-        //shift elements beyond index + 1 to right and set index to order
+        let order = *self.order(index);
+        if order.owner != owner {
+            msg!("Owner mismatch, you do not own the order");
+            return Err(ProgramError::IllegalOwner);
+        }
+        self.remove_order(index)?;
+        Ok(order)
+    }
+
+    //looked up in the order id tree, without walking the book
+    pub fn find_order_index(&self, order_id: u64) -> Option<usize> {
+        self.find_leaf(BookTree::OrderIds, order_id as u128)
+    }
+}
+
+//depth first walk of a tree taking the better child first, which visits leaves in priority order,
+//the first leaf is reached straight down the better children and the walk never allocates
+pub struct OrderBookIter<'a> {
+    side: Side,
+    slots: &'a [BookSlot],
+    stack: [u32; WALK_STACK_LEN],
+    stack_len: usize,
+    oracle_price: Option<u64>    //pegged walks only: nodes out of peg reach at this price are passed over
+}
+
+impl Iterator for OrderBookIter<'_> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        while self.stack_len > 0 {
+            self.stack_len -= 1;
+            let node = self.stack[self.stack_len];
+            if node & LEAF_TAG != 0 {
+                let index = (node & !LEAF_TAG) as usize;
+                let reach = peg_reach(self.side, &self.slots[index].leaf.order);
+                if self.oracle_price.is_some_and(|oracle_price| !is_within_peg_reach(self.side, reach, oracle_price)) {
+                    continue;
+                }
+                return Some(index);
+            }
+            let inner_node = self.slots[node as usize].inner_node;
+            if self.oracle_price.is_some_and(|oracle_price| !is_within_peg_reach(self.side, inner_node.peg_reach, oracle_price)) {
+                continue;
+            }
+            let (worse, better) = match self.side {
+                Side::Bid => (inner_node.children[0], inner_node.children[1]),
+                Side::Ask => (inner_node.children[1], inner_node.children[0])
+            };
+            self.stack[self.stack_len] = worse;
+            self.stack[self.stack_len + 1] = better;
+            self.stack_len += 2;
+        }
+        None
+    }
+}
+
+
+#[repr(C, packed)]
+#[derive(Copy, Clone, Zeroable, Pod)]
+pub struct TriggerOrder {
+    pub trigger_id: u64,
+    pub owner: Pubkey,
+    pub side: Side,
+    pub order_type: OrderType,    //order placed once triggered, Limit for stop limit or ImmediateOrCancel for stop
+    pub self_trade_behavior: SelfTradeBehavior,
+    pub trigger_price: u64,
+    pub limit_price: u64,
+    pub coin_qty: u64,
+    pub pc_qty: u64,        //native pc locked by a bid, derived from its limit price and quantity
+    pub client_order_id: u64
+}
+
+impl TriggerOrder {
+    //bids stop in once the market trades at or above the trigger price, asks at or below it
+    pub fn is_triggered(&self, last_traded_price: u64) -> bool {
+        if last_traded_price == 0 {
+            return false;
+        }
+        match self.side {
+            Side::Bid => last_traded_price >= self.trigger_price,
+            Side::Ask => last_traded_price <= self.trigger_price
+        }
+    }
+
+    pub fn to_create_order_args(&self) -> CreateOrderArgs {
+        CreateOrderArgs {
+            side: self.side,
+            limit_price: self.limit_price,
+            coin_qty: self.coin_qty,
+            order_type: self.order_type,
+            pc_qty_limit: 0,
+            self_trade_behavior: self.self_trade_behavior,
+            client_order_id: self.client_order_id,
+            expiry_timestamp: None,
+            display_quantity: 0,
+            peg_offset: None,
+            referrer: None,
+            match_limit: None,
+            match_limit_behavior: MatchLimitBehavior::DropRemainder    //a full event queue must not fail the crank
+        }
+    }
+}
+
+
+#[repr(C, packed)]
+#[derive(Copy, Clone, Zeroable, Pod)]
+pub struct TriggerOrderBook {
+    pub market: Pubkey,
+    pub next_trigger_id: u64,
+    pub orders: [TriggerOrder; MAX_TRIGGER_ORDERS as usize],    //sorted by trigger price, lowest first
+    pub slots_filled: u16
+}
+
+impl TriggerOrderBook {
+    pub const LEN: usize = 32 + 8 + (83 * MAX_TRIGGER_ORDERS as usize) + 2;  //21290 bytes
+
+    //views the data of a trigger orders account, which has to be exactly this size
+    pub fn load(data: &mut [u8]) -> Result<&mut Self, ProgramError> {
+        if data.len() != Self::LEN {
+            msg!("Trigger orders account has {} bytes, expected {}", data.len(), Self::LEN);
+            return Err(ProgramError::InvalidAccountData);
+        }
+        Ok(bytemuck::from_bytes_mut(data))
+    }
+
+    pub fn add_order(&mut self, order: TriggerOrder) -> ProgramResult {
+        if self.slots_filled >= MAX_TRIGGER_ORDERS {
+            msg!("Trigger orders are full right now");
+            return Err(OrderBookError::BookFull.into());
+        }
+
+        let order_slice = &self.orders[0..(self.slots_filled as usize)];
+        let index = order_slice.partition_point(|x| x.trigger_price <= order.trigger_price);
+
+        //shift elements beyond index to right and set index to order
         for i in (index..self.slots_filled as usize).rev() {
             self.orders[i + 1] = self.orders[i];
         }
@@ -94,35 +911,19 @@ impl OrderBook {
         self.slots_filled += 1;
 
         Ok(())
-        
     }
 
     pub fn remove_order(&mut self, index: usize) -> ProgramResult {
-        //shift elements left beyond index + 1 and set slots_filled_index to 0;
-        for i in index..self.slots_filled as usize {
-            self.orders[i] = self.orders[i+1];
+        //shift elements beyond index to left
+        for i in index..(self.slots_filled as usize - 1) {
+            self.orders[i] = self.orders[i + 1];
         }
         self.slots_filled -= 1;
         Ok(())
     }
 
-    pub fn safely_remove_order_by_order_id(&mut self, order_id: u64, owner: Pubkey) -> Result<Order, ProgramError> {
-        for i in 0..self.slots_filled {
-            if self.orders[i as usize].order_id == order_id {
-
-                if self.orders[i as usize].owner == owner {
-                    let order = self.orders[i as usize];
-                    self.remove_order(i as usize)?;
-                    return Ok(order);
-                } 
-
-                msg!("Owner mismatch, you do not own the order");
-                return Err(ProgramError::IllegalOwner);
-
-            }
-        }
-        msg!("Order Id is not present");
-        return Err(ProgramError::InvalidInstructionData);
+    pub fn find_order_index(&self, trigger_id: u64) -> Option<usize> {
+        (0..self.slots_filled as usize).find(|&i| self.orders[i].trigger_id == trigger_id)
     }
 }
 
@@ -132,24 +933,52 @@ impl OrderBook {
 pub struct OpenOrderAccount {
     pub owner: Pubkey,
     pub market: Pubkey,
-    pub order_ids: [u64; 64],
-    pub next_array_index: u8,
+    pub order_ids: [u64; MAX_OPEN_ORDERS],
+    pub client_order_ids: [u64; MAX_OPEN_ORDERS],
+    pub next_array_index: u8,   //open orders fill slots 0..next_array_index, a freed slot takes the last one
     pub bump: u8
 }
 
 impl OpenOrderAccount {
-    pub const LEN: usize = 32 + 32 + (8 * 64) + 1 + 1;  //578 bytes
+    pub const LEN: usize = 32 + 32 + (8 * 64) + (8 * 64) + 1 + 1;  //1090 bytes
+
+    //records a new resting order in the first free slot
+    pub fn add_order(&mut self, side_encoded_order_id: u64, client_order_id: u64) -> ProgramResult {
+        let index = self.next_array_index as usize;
+        if index >= MAX_OPEN_ORDERS {
+            msg!("Too many open orders, at most {} can rest at once", MAX_OPEN_ORDERS);
+            return Err(OrderBookError::TooManyOpenOrders.into());
+        }
+        self.order_ids[index] = side_encoded_order_id;
+        self.client_order_ids[index] = client_order_id;
+        self.next_array_index += 1;
+        Ok(())
+    }
+
+    //frees the slot of an order that left the book, unknown ids were already freed
+    pub fn remove_order(&mut self, side_encoded_order_id: u64) {
+        let count = self.next_array_index as usize;
+        let Some(index) = (0..count).find(|&i| self.order_ids[i] == side_encoded_order_id) else {
+            return;
+        };
+        let last = count - 1;
+        self.order_ids[index] = self.order_ids[last];
+        self.client_order_ids[index] = self.client_order_ids[last];
+        self.order_ids[last] = 0;
+        self.client_order_ids[last] = 0;
+        self.next_array_index -= 1;
+    }
 
     pub fn create_side_encoded_order_id(plain_order_id: u64, side: Side) -> u64 {
         let side_bytes = (side as u64) << 63;
-        return side_bytes | plain_order_id;
+        side_bytes | plain_order_id
     }
 
     pub fn decode_side_encoded_order_id(encoded_order_id: u64) -> Result<(u64, Side), ProgramError> {
         let side_bytes = (encoded_order_id >> 63) as u8;
         let side = Side::try_from_slice(&[side_bytes])?;
         let order_id = encoded_order_id & 0x7FFF_FFFF_FFFF_FFFF;
-        return Ok((order_id, side));
+        Ok((order_id, side))
     }
 }
 
@@ -178,36 +1007,61 @@ impl UserMarketAccount {
             free_pc: 0,
             locked_pc: 0,
             open_order: *open_order,
-            bump: bump
+            bump
         }
     }
 
-    pub fn unlock_coin(&mut self, amount: &u64) {
-        self.locked_coin -= amount;
-        self.free_coin += amount;
+    pub fn unlock_coin(&mut self, amount: &u64) -> ProgramResult {
+        self.locked_coin = self.locked_coin.checked_sub(*amount).ok_or(OrderBookError::InsufficientFunds)?;
+        self.free_coin = self.free_coin.checked_add(*amount).ok_or(OrderBookError::ArithmeticOverflow)?;
+        Ok(())
+    }
+
+    pub fn lock_free_coin(&mut self, amount: &u64) -> ProgramResult {
+        self.free_coin = self.free_coin.checked_sub(*amount).ok_or(OrderBookError::InsufficientFunds)?;
+        self.credit_locked_coin(amount)
+    }
+
+    pub fn credit_locked_coin(&mut self, amount: &u64) -> ProgramResult {
+        self.locked_coin = self.locked_coin.checked_add(*amount).ok_or(OrderBookError::ArithmeticOverflow)?;
+        Ok(())
+    }
+
+    pub fn unlock_pc(&mut self, amount: &u64) -> ProgramResult {
+        self.locked_pc = self.locked_pc.checked_sub(*amount).ok_or(OrderBookError::InsufficientFunds)?;
+        self.free_pc = self.free_pc.checked_add(*amount).ok_or(OrderBookError::ArithmeticOverflow)?;
+        Ok(())
+    }
+
+    pub fn lock_free_pc(&mut self, amount: &u64) -> ProgramResult {
+        self.free_pc = self.free_pc.checked_sub(*amount).ok_or(OrderBookError::InsufficientFunds)?;
+        self.credit_locked_pc(amount)
     }
 
-    pub fn lock_free_coin(&mut self, amount: &u64) {
-        self.free_coin -= amount;
-        self.credit_locked_coin(amount);
+    pub fn credit_locked_pc(&mut self, amount: &u64) -> ProgramResult {
+        self.locked_pc = self.locked_pc.checked_add(*amount).ok_or(OrderBookError::ArithmeticOverflow)?;
+        Ok(())
     }
 
-    pub fn credit_locked_coin(&mut self, amount: &u64) {
-        self.locked_coin += amount;
+    //fills move funds between users: what a side pays leaves its locked balance, what it receives lands in its free balance
+    pub fn debit_locked_coin(&mut self, amount: &u64) -> ProgramResult {
+        self.locked_coin = self.locked_coin.checked_sub(*amount).ok_or(OrderBookError::InsufficientFunds)?;
+        Ok(())
     }
 
-    pub fn unlock_pc(&mut self, amount: &u64) {
-        self.locked_pc -= amount;
-        self.free_pc += amount;
+    pub fn credit_free_coin(&mut self, amount: &u64) -> ProgramResult {
+        self.free_coin = self.free_coin.checked_add(*amount).ok_or(OrderBookError::ArithmeticOverflow)?;
+        Ok(())
     }
 
-    pub fn lock_free_pc(&mut self, amount: &u64) {
-        self.free_pc -= amount;
-        self.credit_locked_pc(amount);
+    pub fn debit_locked_pc(&mut self, amount: &u64) -> ProgramResult {
+        self.locked_pc = self.locked_pc.checked_sub(*amount).ok_or(OrderBookError::InsufficientFunds)?;
+        Ok(())
     }
 
-    pub fn credit_locked_pc(&mut self, amount: &u64) {
-        self.locked_pc += amount;
+    pub fn credit_free_pc(&mut self, amount: &u64) -> ProgramResult {
+        self.free_pc = self.free_pc.checked_add(*amount).ok_or(OrderBookError::ArithmeticOverflow)?;
+        Ok(())
     }
 }
 
@@ -232,66 +1086,203 @@ pub struct Event {
     pub coin_qty: u64,
     pub pc_qty: u64,
     pub maker_order_id: u64,
+    pub maker_client_order_id: u64,
+    pub referrer: Pubkey,   //fills only: taker's referrer, default pubkey if there is none
+    pub maker_pc_locked: u64,   //bid maker fills only: pc the maker had locked for the filled quantity
+    pub maker_out: u8,          //1 if the maker order left the book with this event, its open order slot is freed when consumed
+    pub taker_client_order_id: u64    //fills only: client order id the taker gave its order
 }
 
+impl Event {
+    pub const LEN: usize = 1 + 1 + 32 + 32 + 8 + 8 + 8 + 8 + 32 + 8 + 1 + 8;   //147 bytes
 
+    //out event releasing the last `qty` unfilled base lots of a resting order back to its owner, amounts are native
+    //the order is taken to leave the book, callers that keep it resting clear `maker_out`
+    pub fn out(order: &Order, qty: u64, market_data: &MarketState) -> Result<Self, ProgramError> {
+        let total_qty = order.total_quantity()?;
+        let released_from = total_qty.checked_sub(qty).ok_or(OrderBookError::ArithmeticOverflow)?;
+        Ok(Event {
+            event_type: EventType::Out,
+            side: order.side,
+            maker: order.owner,
+            taker: order.owner,
+            coin_qty: market_data.coin_native(qty)?,
+            pc_qty: match order.side {
+                Side::Bid => market_data.bid_lock_between(order.price, released_from, total_qty)?,
+                Side::Ask => market_data.pc_native(order.price, qty)?
+            },
+            maker_order_id: order.order_id,
+            maker_client_order_id: order.client_order_id,
+            referrer: Pubkey::default(),
+            maker_pc_locked: 0,
+            maker_out: 1,
+            taker_client_order_id: 0
+        })
+    }
+}
+
+
+//start of a market events account, followed by `capacity` events
 #[repr(C, packed)]
 #[derive(Copy, Clone, Zeroable, Pod)]
-
-pub struct MarketEventsAccount {
+pub struct MarketEventsHeader {
     pub market: Pubkey,
-    pub head: u16,
-    pub tail: u16,
-    pub events: [Event; MAX_EVENT as usize],
+    pub head: u32,
+    pub tail: u32,
+    pub capacity: u32     //slots in the ring, derived from the account size, one always stays empty
 }
 
-impl MarketEventsAccount {
-    pub const LEN: u16 = 32 + 2 + 2 + (90 * MAX_EVENT);   //46,116 bytes
+pub struct MarketEventsAccount<'a> {
+    pub header: &'a mut MarketEventsHeader,
+    pub events: &'a mut [Event]
+}
+
+impl<'a> MarketEventsAccount<'a> {
+    pub const HEADER_LEN: usize = 32 + 4 + 4 + 4;   //44 bytes
 
     pub const DRAIN_LIMIT: u16 = 5;
 
-    pub fn enqueue(&mut self, event: Event) -> Result<bool, ProgramError> {
+    //account size needed for a ring of `capacity` events
+    pub fn account_len(capacity: u32) -> usize {
+        Self::HEADER_LEN + Event::LEN * capacity as usize
+    }
+
+    //views the data of an initialized market events account
+    pub fn load(data: &'a mut [u8]) -> Result<Self, ProgramError> {
+        if data.len() < Self::HEADER_LEN {
+            msg!("Market events account is too small");
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let (header_data, events_data) = data.split_at_mut(Self::HEADER_LEN);
+        let header: &mut MarketEventsHeader = bytemuck::from_bytes_mut(header_data);
+
+        let events_len = Event::LEN * header.capacity as usize;
+        if events_data.len() < events_len {
+            msg!("Market events account is smaller than its capacity of {} events", { header.capacity });
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let events: &mut [Event] = bytemuck::cast_slice_mut(&mut events_data[..events_len]);
+        Ok(Self { header, events })
+    }
+
+    //sizes a new queue by its account data length and empties it
+    pub fn init(data: &'a mut [u8], market: Pubkey) -> Result<Self, ProgramError> {
+        let capacity = data.len().saturating_sub(Self::HEADER_LEN) / Event::LEN;
+        if capacity < 2 || capacity > u32::MAX as usize {
+            msg!("Market events account of {} bytes can not hold a valid number of events", data.len());
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let header: &mut MarketEventsHeader = bytemuck::from_bytes_mut(&mut data[..Self::HEADER_LEN]);
+        header.market = market;
+        header.head = 0;
+        header.tail = 0;
+        header.capacity = capacity as u32;
+
+        let queue = Self::load(data)?;
+        queue.events.fill(Event::zeroed());
+        Ok(queue)
+    }
+
+    //takes in the events added to a resized queue, unwrapping the ring first so pending events stay in order
+    pub fn grow(data: &'a mut [u8]) -> Result<Self, ProgramError> {
+        let capacity = data.len().saturating_sub(Self::HEADER_LEN) / Event::LEN;
+        let header: &MarketEventsHeader = bytemuck::from_bytes(&data[..Self::HEADER_LEN]);
+        let (head, tail, old_capacity) = (header.head, header.tail, header.capacity);
+        if capacity <= old_capacity as usize || capacity > u32::MAX as usize {
+            msg!("Market events account of {} bytes can not grow past its capacity of {} events", data.len(), old_capacity);
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let wrapped = head < tail;
+        if wrapped {
+            data[Self::HEADER_LEN..Self::account_len(old_capacity)].rotate_left(Event::LEN * tail as usize);
+        }
+        let header: &mut MarketEventsHeader = bytemuck::from_bytes_mut(&mut data[..Self::HEADER_LEN]);
+        if wrapped {
+            header.head = old_capacity - (tail - head);
+            header.tail = 0;
+        }
+        header.capacity = capacity as u32;
+        Self::load(data)
+    }
+
+    pub fn enqueue(&mut self, event: Event) -> ProgramResult {
         if self.is_full() {
-            return Ok(false)
+            msg!("Event Queue is Full");
+            return Err(OrderBookError::EventQueueFull.into());
         }
-        self.events[self.head as usize] = event;
-        self.head = (self.head + 1) % MAX_EVENT;
-        Ok(true)
+        self.events[self.header.head as usize] = event;
+        self.header.head = (self.header.head + 1) % self.header.capacity;
+        Ok(())
     }
 
     pub fn dequeue(&mut self) -> Result<Option<Event>, ProgramError> {
         if self.is_empty() {
             return Ok(None)
         }
-        let event = self.events[self.tail as usize];
-        self.tail = (self.tail + 1) % MAX_EVENT;
+        let event = self.events[self.header.tail as usize];
+        self.header.tail = (self.header.tail + 1) % self.header.capacity;
         Ok(Some(event))
     }
 
-    pub fn is_empty(&mut self) -> bool {
-        return self.head == self.tail;
+    pub fn is_empty(&self) -> bool {
+        self.header.head == self.header.tail
     }
 
-    pub fn is_full(&mut self) -> bool {
-        return (self.head + 1) % MAX_EVENT == self.tail
+    pub fn is_full(&self) -> bool {
+        (self.header.head + 1) % self.header.capacity == self.header.tail
     }
 
-    pub fn size(&mut self) -> u16 {
-        if self.head >= self.tail {
-            return self.head - self.tail;
+    //number of events that can still be enqueued, one slot always stays empty
+    pub fn free_space(&self) -> u32 {
+        self.header.capacity - self.size() - 1
+    }
+
+    //true while an event waiting to be consumed still settles a balance of `user`
+    pub fn has_pending_events_of(&self, user: &Pubkey) -> bool {
+        let (tail, capacity) = (self.header.tail as usize, self.header.capacity as usize);
+        (0..self.size() as usize)
+            .map(|i| &self.events[(tail + i) % capacity])
+            .any(|event| event.maker == *user || event.taker == *user || event.referrer == *user)
+    }
+
+    pub fn size(&self) -> u32 {
+        if self.header.head >= self.header.tail {
+            return self.header.head - self.header.tail;
         }
-        return MAX_EVENT - (self.tail - self.head);
+        self.header.capacity - (self.header.tail - self.header.head)
     }
 }
 
 
 
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct InitializeMarketArgs {
+    pub tick_size: u64,
+    pub base_lot_size: u64,
+    pub quote_lot_size: u64,
+    pub min_order_size: u64,
+    pub maker_fee_bps: i16,
+    pub taker_fee_bps: u16,
+    pub referrer_fee_share_bps: u16
+}
+
 #[derive(BorshSerialize, BorshDeserialize)]
 pub struct CreateOrderArgs {
     pub side: Side,
-    pub limit_price: u64,
-    pub coin_qty: u64,
-    pub pc_qty: u64,
+    pub limit_price: u64,       //quote lots per base lot
+    pub coin_qty: u64,          //base lots
+    pub order_type: OrderType,
+    pub pc_qty_limit: u64,      //market orders only: max pc spent by a bid, min pc received by an ask, after taker fees
+    pub self_trade_behavior: SelfTradeBehavior,
+    pub client_order_id: u64,
+    pub expiry_timestamp: Option<i64>,
+    pub display_quantity: u64,      //iceberg orders only: visible part of the resting order, 0 shows all of it
+    pub peg_offset: Option<i64>,    //pegged orders only: offset from the oracle price, `limit_price` becomes the peg limit
+    pub referrer: Option<Pubkey>,   //owner of the user market account credited with a share of the taker fee
+    pub match_limit: Option<u16>,   //max number of maker fills, none matches until the order is filled or stops crossing
+    pub match_limit_behavior: MatchLimitBehavior    //what happens to the remainder once match_limit or a full event queue stops matching
 }
 
 #[derive(BorshSerialize, BorshDeserialize)]
@@ -303,4 +1294,69 @@ pub struct ConsumeEventsArgs {
 pub struct CancelOrderArgs {
     pub order_id: u64,
     pub side: Side
-}
\ No newline at end of file
+}
+
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct CancelOrderByClientIdArgs {
+    pub client_order_id: u64
+}
+
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct PruneExpiredOrdersArgs {
+    pub side: Side,
+    pub prune_limit: u8
+}
+
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct AmendOrderArgs {
+    pub order_id: u64,
+    pub side: Side,
+    pub new_price: u64,
+    pub new_quantity: u64       //new unfilled quantity of the order
+}
+
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct CancelAllOrdersArgs {
+    pub side: Option<Side>,     //only cancel orders on this side, both sides if none
+    pub limit: u8               //max number of orders cancelled in one instruction
+}
+
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct CancelAndReplaceArgs {
+    pub cancel_orders: Vec<CancelOrderArgs>,
+    pub new_orders: Vec<CreateOrderArgs>
+}
+
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct CreateTriggerOrderArgs {
+    pub side: Side,
+    pub trigger_price: u64,
+    pub limit_price: u64,
+    pub coin_qty: u64,
+    pub order_type: OrderType,      //Limit or ImmediateOrCancel
+    pub self_trade_behavior: SelfTradeBehavior,
+    pub client_order_id: u64
+}
+
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct CancelTriggerOrderArgs {
+    pub trigger_id: u64
+}
+
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct ExecuteTriggerOrdersArgs {
+    pub limit: u8
+}
+
+#[derive(BorshSerialize, BorshDeserialize)]
+pub enum MarketAccountKind {
+    Bids,
+    Asks,
+    Events
+}
+
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct ReallocMarketAccountArgs {
+    pub account: MarketAccountKind,
+    pub new_capacity: u32       //orders or events the account will have room for
+}